@@ -0,0 +1,99 @@
+//! A line for status bars every time the countdown
+//! ticks, as JSON in waybar's custom module format or
+//! as plain text for i3blocks and polybar
+use std::time::Duration;
+
+use clap::ValueEnum;
+use color_eyre::eyre::{self, bail};
+use jiff::Timestamp;
+use lockinspiel_common::{
+    daemon::{DaemonClient, DaemonState},
+    timer::TimerState,
+};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Format {
+    Waybar,
+    Text,
+}
+
+/// https://github.com/Alexays/Waybar/wiki/Module:-Custom
+#[derive(Serialize)]
+struct Waybar {
+    text: String,
+    /// Picks `format-icons` by phase
+    alt: &'static str,
+    tooltip: String,
+    class: [&'static str; 2],
+    /// How far through the timer it is
+    percentage: u8,
+}
+
+pub async fn run(daemon: DaemonClient, format: Format) -> eyre::Result<()> {
+    let (mut state, mut subscription) = daemon.subscribe().await?;
+    loop {
+        let now = Timestamp::now();
+        println!("{}", line(&state, now, format)?);
+
+        // Next second, or sooner if the timer changes
+        let remaining = state.timer.remaining(now);
+        let wait = match remaining.subsec_millis() {
+            millis if millis > 0 => millis as u64,
+            _ => 1000,
+        };
+        tokio::select! {
+            event = subscription.next() => match event {
+                Some(event) => state = event?.state().clone(),
+                None => bail!("lockinspield went away"),
+            },
+            _ = tokio::time::sleep(Duration::from_millis(wait)) => {}
+        }
+    }
+}
+
+fn line(state: &DaemonState, now: Timestamp, format: Format) -> eyre::Result<String> {
+    let timer = &state.timer;
+    let current = timer.current();
+    let going = matches!(timer.state, TimerState::Going(_));
+    let secs = timer.remaining(now).as_secs().max(0);
+    let countdown = format!("{}:{:02}", secs / 60, secs % 60);
+
+    Ok(match format {
+        Format::Text => format!(
+            "{} {}{}",
+            current.name,
+            countdown,
+            if going { "" } else { " (paused)" }
+        ),
+        Format::Waybar => {
+            let total = current.len.as_secs_f64();
+            let percentage = if total > 0.0 {
+                ((1.0 - secs as f64 / total) * 100.0).clamp(0.0, 100.0) as u8
+            } else {
+                100
+            };
+            let mut tooltip = format!(
+                "{}: {}, {} of {}",
+                state.split,
+                current.name,
+                timer.timer_on + 1,
+                timer.timers.len()
+            );
+            if !state.tags.is_empty() {
+                tooltip.push('\n');
+                tooltip.push_str(&state.tags.join(", "));
+            }
+            serde_json::to_string(&Waybar {
+                text: countdown,
+                alt: if current.work { "work" } else { "break" },
+                tooltip,
+                class: [
+                    if current.work { "work" } else { "break" },
+                    if going { "going" } else { "paused" },
+                ],
+                percentage,
+            })?
+        }
+    })
+}
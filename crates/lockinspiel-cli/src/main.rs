@@ -0,0 +1,286 @@
+use clap::{Parser, Subcommand};
+use clap_verbosity_flag::WarnLevel;
+use color_eyre::eyre::{self, Context};
+use jiff::{SignedDuration, Timestamp, Zoned, civil::Date, tz::TimeZone};
+use lockinspiel_common::{
+    daemon::{DaemonClient, DaemonState},
+    history,
+    timer::TimerState,
+};
+use serde::Serialize;
+
+mod bar;
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+#[command(propagate_version = true)]
+struct Cli {
+    #[command(flatten)]
+    verbose: clap_verbosity_flag::Verbosity<WarnLevel>,
+    /// Print JSON instead of text, for scripts
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start the timer, switching to another split first if one is given
+    Start { split: Option<String> },
+    /// Pause the timer
+    Pause,
+    /// Skip to the next timer in the split
+    Skip,
+    /// Start the timer if it's paused, pause it if it's going
+    Toggle,
+    /// Show the current timer
+    Status,
+    /// List timesheet rows
+    Log {
+        /// A timestamp, a date, or how long ago, like 2d
+        #[arg(long, default_value = "1d", value_parser = parse_since)]
+        since: Timestamp,
+    },
+    /// Show work time per tag
+    Tags {
+        /// A timestamp, a date, or how long ago, like 2d
+        #[arg(long, value_parser = parse_since)]
+        since: Option<Timestamp>,
+    },
+    /// Write the timesheet out as CSV, or JSON with --json
+    Export {
+        /// A timestamp, a date, or how long ago, like 2d
+        #[arg(long, value_parser = parse_since)]
+        since: Option<Timestamp>,
+    },
+    /// Show totals of work time
+    Stats,
+    /// Keep printing the countdown for a status bar
+    Bar {
+        #[arg(long, value_enum, default_value_t = bar::Format::Waybar)]
+        format: bar::Format,
+    },
+}
+
+/// What `status` prints with --json
+#[derive(Serialize)]
+struct Status<'a> {
+    split: &'a str,
+    timer: &'a str,
+    work: bool,
+    going: bool,
+    remaining: SignedDuration,
+    /// When the timer runs out, if it's going
+    ends_at: Option<Timestamp>,
+    /// Which timer in the split this is, from 0
+    timer_on: usize,
+    timers: usize,
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    let cli = Cli::parse();
+
+    lockinspiel_common::install_init_boilerplate(Some(cli.verbose.tracing_level_filter()))?;
+
+    let mut daemon = DaemonClient::connect_or_spawn()
+        .await
+        .wrap_err("Failed to connect to lockinspield")?;
+    let now = Timestamp::now();
+    let tz = TimeZone::system();
+
+    match cli.command {
+        Command::Start { split } => {
+            let state = daemon.start(split).await?;
+            print_status(&state, now, cli.json)?;
+        }
+        Command::Pause => {
+            let state = daemon.pause().await?;
+            print_status(&state, now, cli.json)?;
+        }
+        Command::Skip => {
+            let state = daemon.skip().await?;
+            print_status(&state, now, cli.json)?;
+        }
+        Command::Toggle => {
+            let state = match daemon.status().await?.timer.state {
+                TimerState::Going(_) => daemon.pause().await?,
+                TimerState::Paused(_) => daemon.start(None).await?,
+            };
+            print_status(&state, now, cli.json)?;
+        }
+        Command::Status => {
+            let state = daemon.status().await?;
+            print_status(&state, now, cli.json)?;
+        }
+        Command::Log { since } => {
+            let entries = daemon.history(since).await?;
+            if cli.json {
+                print_json(&entries)?;
+            } else {
+                for entry in &entries {
+                    println!(
+                        "{} - {}  {:>10}  {}{}{}  {}  {}",
+                        entry
+                            .start_time
+                            .to_zoned(tz.clone())
+                            .strftime("%a %b %-d %H:%M"),
+                        entry.end_time.to_zoned(tz.clone()).strftime("%H:%M"),
+                        format!("{:#}", round_to_secs(entry.length(now))),
+                        if entry.work { "Work" } else { "Break" },
+                        if entry.completed || entry.end_time > now {
+                            ""
+                        } else {
+                            " (cut short)"
+                        },
+                        if entry.idle.is_zero() {
+                            String::new()
+                        } else {
+                            format!(" ({:#} idle)", round_to_secs(entry.idle))
+                        },
+                        entry.split.as_deref().unwrap_or("-"),
+                        entry.tags.join(", "),
+                    );
+                }
+            }
+        }
+        Command::Tags { since } => {
+            let entries = daemon
+                .history(since.unwrap_or(Timestamp::UNIX_EPOCH))
+                .await?;
+            let totals = history::tag_totals(daemon.tags().await?, &entries, now);
+            if cli.json {
+                print_json(&totals)?;
+            } else {
+                for total in &totals {
+                    println!(
+                        "{:<20} {:>12}  {} sessions",
+                        total.tag,
+                        format!("{:#}", round_to_secs(total.worked)),
+                        total.sessions
+                    );
+                }
+            }
+        }
+        Command::Export { since } => {
+            let entries = daemon
+                .history(since.unwrap_or(Timestamp::UNIX_EPOCH))
+                .await?;
+            if cli.json {
+                print_json(&entries)?;
+            } else {
+                println!("start_time,end_time,work,completed,split,tags,idle_secs");
+                for entry in &entries {
+                    println!(
+                        "{},{},{},{},{},{},{}",
+                        entry.start_time,
+                        entry.end_time,
+                        entry.work,
+                        entry.completed,
+                        csv_field(entry.split.as_deref().unwrap_or("")),
+                        csv_field(&entry.tags.join(";")),
+                        entry.idle.as_secs(),
+                    );
+                }
+            }
+        }
+        Command::Stats => {
+            let entries = daemon.history(Timestamp::UNIX_EPOCH).await?;
+            let stats = history::stats(&entries, now, &tz)?;
+            if cli.json {
+                print_json(&stats)?;
+            } else {
+                println!("Today      {:#}", round_to_secs(stats.today));
+                println!("This week  {:#}", round_to_secs(stats.week));
+                println!("All time   {:#}", round_to_secs(stats.total));
+                println!("Streak     {} days", stats.streak);
+                println!(
+                    "Timers     {} finished, {} cut short",
+                    stats.completed, stats.cut_short
+                );
+            }
+        }
+        Command::Bar { format } => bar::run(daemon, format).await?,
+    }
+
+    Ok(())
+}
+
+fn print_status(state: &DaemonState, now: Timestamp, json: bool) -> eyre::Result<()> {
+    let timer = &state.timer;
+    let current = timer.current();
+    let remaining = timer.remaining(now);
+    let ends_at = match timer.state {
+        TimerState::Going(end_time) => Some(end_time),
+        TimerState::Paused(_) => None,
+    };
+
+    if json {
+        return print_json(&Status {
+            split: &state.split,
+            timer: &current.name,
+            work: current.work,
+            going: ends_at.is_some(),
+            remaining,
+            ends_at,
+            timer_on: timer.timer_on,
+            timers: timer.timers.len(),
+        });
+    }
+
+    let secs = remaining.as_secs();
+    println!(
+        "{} {}:{:02} {} ({}, {} of {})",
+        current.name,
+        secs / 60,
+        secs % 60,
+        if ends_at.is_some() { "left" } else { "paused" },
+        state.split,
+        timer.timer_on + 1,
+        timer.timers.len()
+    );
+    Ok(())
+}
+
+fn print_json<T: Serialize + ?Sized>(value: &T) -> eyre::Result<()> {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).wrap_err("Failed to write JSON")?
+    );
+    Ok(())
+}
+
+/// Timestamps, dates in the local time zone,
+/// or spans like 2d meaning that long ago
+fn parse_since(since: &str) -> Result<Timestamp, String> {
+    if let Ok(timestamp) = since.parse::<Timestamp>() {
+        return Ok(timestamp);
+    }
+    if let Ok(date) = since.parse::<Date>() {
+        return date
+            .to_zoned(TimeZone::system())
+            .map(|zoned| zoned.timestamp())
+            .map_err(|e| e.to_string());
+    }
+    let span: jiff::Span = since
+        .parse()
+        .map_err(|e| format!("Expected a timestamp, date or span like 2d: {}", e))?;
+    Zoned::now()
+        .checked_sub(span)
+        .map(|zoned| zoned.timestamp())
+        .map_err(|e| e.to_string())
+}
+
+/// Sub-second precision is just noise in a listing
+fn round_to_secs(duration: SignedDuration) -> SignedDuration {
+    SignedDuration::from_secs(duration.as_secs())
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
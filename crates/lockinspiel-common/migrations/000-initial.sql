@@ -11,7 +11,7 @@ CREATE TABLE time_split_timer(
     len INTERVAL NOT NULL,
     name VARCHAR NOT NULL,
     work BOOLEAN NOT NULL
-)
+);
 
 CREATE SEQUENCE timesheet_group_pk;
 CREATE TABLE timesheet_group(
@@ -0,0 +1,3 @@
+-- Rows that existed before this count as interrupted,
+-- since there's no telling whether they ran out
+ALTER TABLE timesheet ADD COLUMN completed BOOLEAN DEFAULT false;
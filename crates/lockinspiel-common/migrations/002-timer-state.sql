@@ -0,0 +1,9 @@
+-- The timer as it was last left, so a restart picks
+-- it back up exactly. There's only ever one row
+CREATE TABLE timer_state(
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    split VARCHAR NOT NULL,
+    -- JSON `Timer`
+    timer VARCHAR NOT NULL,
+    timesheet_group BIGINT
+);
@@ -0,0 +1,14 @@
+-- The row of the timer that's going, if one is. Rows
+-- used to be found by having the latest end, which
+-- picked the wrong one once clocks or imports put
+-- rows out of order. There's only ever one row
+CREATE TABLE active_session(
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    start_time TIMESTAMP_MS NOT NULL
+);
+-- Whatever was still going when this ran
+INSERT INTO active_session
+SELECT 0, start_time FROM timesheet
+WHERE end_time > make_timestamp(epoch_us(now()))
+ORDER BY start_time DESC
+LIMIT 1;
@@ -0,0 +1,7 @@
+-- Time nobody was at the computer while a session was
+-- going, because it slept or sat idle. Sessions it
+-- overlaps count it as idle instead of worked
+CREATE TABLE idle_gap(
+    start_time TIMESTAMP_MS PRIMARY KEY,
+    end_time TIMESTAMP_MS NOT NULL
+);
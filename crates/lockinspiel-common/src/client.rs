@@ -1,8 +1,20 @@
 use std::num::ParseIntError;
 
+use futures_util::StreamExt;
 use jiff::{SignedDuration, Timestamp};
 use supabase_auth::models::{AuthClient, Session};
 use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream,
+    tungstenite::{self, Message, client::IntoClientRequest, http::header::AUTHORIZATION},
+};
+
+use crate::{
+    room::{CreateRoom, RoomAction, RoomEvent, RoomState},
+    sync::SyncBatch,
+    timer::SplitTimer,
+};
 
 #[derive(Error, Debug)]
 pub enum ClientError {
@@ -18,13 +30,31 @@ pub enum ClientError {
     JiffError(#[from] jiff::Error),
     #[error("The offset was not present in the client for some reason")]
     NoOffsetCached,
+    #[error("WebSocket connection to server failed")]
+    WebSocketError(#[from] tokio_tungstenite::tungstenite::Error),
+    #[error("Failed to parse JSON sent by server")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Server is rate limiting us, try again in {0}")]
+    RateLimited(SignedDuration),
+    #[error("Supabase Auth request failed")]
+    AuthError(#[from] supabase_auth::error::Error),
 }
 
+/// Access tokens are refreshed when they expire
+/// within this long, so requests don't race expiry
+const TOKEN_REFRESH_MARGIN: SignedDuration = SignedDuration::from_secs(60);
+
+/// How long to back off when the server rate
+/// limits us without saying for how long
+const DEFAULT_RETRY_AFTER: SignedDuration = SignedDuration::from_secs(5);
+
 pub struct LockinspielClient {
     offset: Option<SignedDuration>,
     session: Option<Session>,
     auth_client: AuthClient,
     offline: bool,
+    clock_sync: bool,
+    retry_after: Option<Timestamp>,
     client: reqwest::Client,
 }
 
@@ -40,6 +70,8 @@ impl Default for LockinspielClient {
             session: None,
             auth_client: AuthClient::new(PROJECT_URL, API_KEY, JWT_SECRET),
             offline: false,
+            clock_sync: true,
+            retry_after: None,
             client: reqwest::Client::new(),
         }
     }
@@ -59,11 +91,25 @@ impl LockinspielClient {
         self.offline
     }
 
+    /// Whether `now()` corrects the local clock with
+    /// the server's. Turning this off stops `now()`
+    /// from contacting the server at all
+    #[inline]
+    pub fn set_clock_sync(&mut self, clock_sync: bool) {
+        self.clock_sync = clock_sync;
+    }
+
     /// Uses the clock offset from the server
     /// to offset the current time
     pub async fn now(&mut self) -> jiff::Timestamp {
-        let clock_offset = if self.offline {
+        let rate_limited = self
+            .retry_after
+            .is_some_and(|retry_after| retry_after > Timestamp::now());
+        let clock_offset = if self.offline || !self.clock_sync {
             jiff::SignedDuration::from_secs(0)
+        } else if rate_limited {
+            // Asking again before Retry-After would only be refused
+            self.offset.unwrap_or(SignedDuration::ZERO)
         } else {
             match self.clock_offset().await {
                 Ok(offset) => offset,
@@ -79,14 +125,39 @@ impl LockinspielClient {
 
     /// Refreshes the client with the
     /// most accurate clock offset from
-    /// the server.
+    /// the server. If the server rate limited
+    /// the last attempt, this fails without
+    /// contacting it until its `Retry-After`
+    /// has passed.
     pub async fn refresh_clock_offset(&mut self) -> Result<(), ClientError> {
+        if let Some(retry_after) = self.retry_after {
+            let wait = Timestamp::now().duration_until(retry_after);
+            if wait.is_positive() {
+                return Err(ClientError::RateLimited(wait));
+            }
+            self.retry_after = None;
+        }
+
         self.offline = true;
         let request = self.client.get(format!("{}/time_sync", BASE_URL));
         let time1 = Timestamp::now();
         let response = request.send().await?;
         let time4 = Timestamp::now();
-        let timestamps = response.text().await?;
+        if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let wait = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<i64>().ok())
+                .map(SignedDuration::from_secs)
+                .unwrap_or(DEFAULT_RETRY_AFTER);
+            self.retry_after = Some(time4 + wait);
+            // The server's there, just busy
+            self.offline = false;
+            tracing::warn!(%wait, "Rate limited by server");
+            return Err(ClientError::RateLimited(wait));
+        }
+        let timestamps = response.error_for_status()?.text().await?;
         self.offline = false;
         let (time2, time3) = timestamps
             .split_once('\n')
@@ -115,4 +186,183 @@ impl LockinspielClient {
     pub fn cached_clock_offset(&self) -> Option<SignedDuration> {
         self.offset
     }
+
+    /// Signs in with Supabase Auth. Requests to the
+    /// tinker terminal are made as this user afterwards
+    pub async fn sign_in(&mut self, email: &str, password: &str) -> Result<(), ClientError> {
+        let session = self.auth_client.login_with_email(email, password).await?;
+        self.session = Some(session);
+        tracing::info!("Signed in");
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn signed_in(&self) -> bool {
+        self.session.is_some()
+    }
+
+    pub fn sign_out(&mut self) {
+        self.session = None;
+    }
+
+    /// The access token of the current session,
+    /// refreshing the session first if it's about
+    /// to expire
+    async fn access_token(&mut self) -> Result<Option<String>, ClientError> {
+        let Some(session) = &self.session else {
+            return Ok(None);
+        };
+
+        let expires_at = Timestamp::from_second(session.expires_at as i64)?;
+        if Timestamp::now() + TOKEN_REFRESH_MARGIN >= expires_at {
+            let refresh_token = session.refresh_token.clone();
+            self.session = Some(self.auth_client.refresh_session(&refresh_token).await?);
+            tracing::info!("Refreshed session");
+        }
+
+        Ok(self
+            .session
+            .as_ref()
+            .map(|session| session.access_token.clone()))
+    }
+
+    /// Adds the session's access token to a request
+    /// for the tinker terminal
+    async fn authorized(
+        &mut self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::RequestBuilder, ClientError> {
+        Ok(match self.access_token().await? {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        })
+    }
+
+    /// Uploads timesheet rows and their tags to the
+    /// server. Sending rows the server already has
+    /// replaces them, so overlapping batches are fine
+    pub async fn sync(&mut self, batch: &SyncBatch) -> Result<(), ClientError> {
+        let request = self.client.post(format!("{}/sync", BASE_URL)).json(batch);
+        self.authorized(request)
+            .await?
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// Creates a room on the server running `timers`.
+    /// Other people can join it with the returned
+    /// `RoomState::code`
+    pub async fn create_room(
+        &mut self,
+        split: String,
+        timers: Vec<SplitTimer>,
+    ) -> Result<RoomState, ClientError> {
+        let request = self
+            .client
+            .post(format!("{}/rooms", BASE_URL))
+            .json(&CreateRoom { split, timers });
+        let room = self
+            .authorized(request)
+            .await?
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(room)
+    }
+
+    /// Gets the current state of a room
+    pub async fn get_room(&mut self, code: &str) -> Result<RoomState, ClientError> {
+        let request = self.client.get(format!("{}/rooms/{}", BASE_URL, code));
+        let room = self
+            .authorized(request)
+            .await?
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(room)
+    }
+
+    /// Starts, pauses or skips the timer of a room
+    /// for everybody in it
+    pub async fn room_action(
+        &mut self,
+        code: &str,
+        action: RoomAction,
+    ) -> Result<RoomState, ClientError> {
+        let action = match action {
+            RoomAction::Start => "start",
+            RoomAction::Pause => "pause",
+            RoomAction::Skip => "skip",
+        };
+        let request = self
+            .client
+            .post(format!("{}/rooms/{}/{}", BASE_URL, code, action));
+        let room = self
+            .authorized(request)
+            .await?
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(room)
+    }
+
+    /// Joins a room. The returned subscription
+    /// yields the state of the room first, then
+    /// an event every time the room changes.
+    /// Dropping it leaves the room
+    pub async fn subscribe_room(&mut self, code: &str) -> Result<RoomSubscription, ClientError> {
+        let url = format!(
+            "{}/rooms/{}/events",
+            BASE_URL.replacen("http", "ws", 1),
+            code
+        );
+        let mut request = url.into_client_request()?;
+        if let Some(token) = self.access_token().await? {
+            let value = format!("Bearer {}", token)
+                .parse()
+                .map_err(|e| tungstenite::Error::HttpFormat(tungstenite::http::Error::from(e)))?;
+            request.headers_mut().insert(AUTHORIZATION, value);
+        }
+        let (stream, _) = tokio_tungstenite::connect_async(request).await?;
+
+        Ok(RoomSubscription { stream })
+    }
+}
+
+pub struct RoomSubscription {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl RoomSubscription {
+    /// Waits for the next event in the room. Returns
+    /// `None` once the server closes the connection
+    pub async fn next(&mut self) -> Option<Result<RoomEvent, ClientError>> {
+        loop {
+            let message = match self.stream.next().await? {
+                Ok(message) => message,
+                Err(e) => return Some(Err(e.into())),
+            };
+
+            match message {
+                Message::Text(text) => {
+                    return Some(serde_json::from_str(text.as_str()).map_err(Into::into));
+                }
+                Message::Close(_) => return None,
+                _ => continue,
+            }
+        }
+    }
 }
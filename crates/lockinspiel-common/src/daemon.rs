@@ -0,0 +1,607 @@
+//! Talking to `lockinspield`, which owns the database and
+//! the timer so any number of frontends can share them.
+//!
+//! The daemon listens on a Unix socket and speaks JSON-RPC
+//! 2.0, one message per line. Calls get a response with
+//! the same id. After `subscribe` the connection only gets
+//! notifications, one per change to the timer.
+//!
+//! Without Unix sockets there's no daemon, and the client
+//! runs the timer in the app instead
+use std::io;
+#[cfg(unix)]
+use std::{
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use thiserror::Error;
+#[cfg(not(unix))]
+use tokio::sync::{OnceCell, broadcast};
+#[cfg(unix)]
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        UnixStream,
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+    },
+};
+
+#[cfg(not(unix))]
+use crate::{
+    db::Database,
+    local::{DEFAULT_SPLIT, LocalTimer},
+    shared::{self, Connection, SharedTimer},
+};
+use crate::{
+    db::DbError,
+    edit::NewSession,
+    history::Entry,
+    idle::{Away, IdlePolicy},
+    sync::{SyncBatch, SyncedSplit},
+    timer::Timer,
+    undo::Action,
+};
+
+/// Overrides where the socket is, mostly for running
+/// a second daemon on a scratch database
+pub const SOCKET_ENV: &str = "LOCKINSPIEL_SOCKET";
+
+/// How long to wait for a daemon that was just spawned
+/// to start listening
+#[cfg(unix)]
+const SPAWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum DaemonError {
+    #[error("Failed to talk to the daemon")]
+    Io(#[from] io::Error),
+    #[error("Failed to convert a message to or from JSON")]
+    Json(#[from] serde_json::Error),
+    #[error("{message}")]
+    Rpc { code: i64, message: String },
+    #[error("The daemon closed the connection")]
+    Closed,
+    #[error("Failed to get the socket directory")]
+    NoSocketDirectory,
+    #[error("Failed to start lockinspield")]
+    Spawn(#[source] io::Error),
+    #[error("Started lockinspield but it never started listening")]
+    SpawnTimedOut,
+    /// Only without a daemon, when the timer's in the app
+    #[error("Failed to open the timer")]
+    Db(#[from] DbError),
+}
+
+/// JSON-RPC error codes the daemon sends
+pub mod codes {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const INTERNAL_ERROR: i64 = -32603;
+    /// The call made sense but can't happen right
+    /// now, like switching splits while going
+    pub const REFUSED: i64 = 1;
+}
+
+/// Everything a frontend shows about the timer
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DaemonState {
+    pub split: String,
+    pub timer: Timer,
+    /// Tags on this run of the split
+    pub tags: Vec<String>,
+    pub auto_start: bool,
+    /// Whether a room is deciding what the timer does,
+    /// in which case the daemon doesn't expire it
+    pub following: bool,
+    /// What `undo` would take back
+    #[serde(default)]
+    pub undo: Option<Action>,
+    /// What `redo` would do again
+    #[serde(default)]
+    pub redo: Option<Action>,
+    /// What time away from the computer does
+    #[serde(default)]
+    pub idle: IdlePolicy,
+    /// Time away to ask about, when the policy is `Ask`
+    #[serde(default)]
+    pub away: Option<Away>,
+}
+
+/// A call to the daemon, as `method` and `params`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Method {
+    Status,
+    /// Switches to `split` first if it's given
+    Start {
+        split: Option<String>,
+    },
+    Pause,
+    Skip,
+    SelectSplit {
+        split: String,
+    },
+    ToggleTag {
+        tag: String,
+    },
+    SetAutoStart {
+        auto_start: bool,
+    },
+    SetIdlePolicy {
+        idle: IdlePolicy,
+    },
+    /// Answers for the time away in the state, keeping
+    /// it as worked or counting it as idle
+    ResolveAway {
+        keep: bool,
+    },
+    /// Takes the timer a room decided on. Timers aren't
+    /// expired by the daemon until `unfollow` or this
+    /// connection closing
+    Follow {
+        timer: Timer,
+    },
+    Unfollow,
+    /// Takes back the last start, pause, skip or split
+    /// switch, along with what it wrote to the timesheet
+    Undo,
+    Redo,
+    Splits,
+    Tags,
+    History {
+        since: Timestamp,
+    },
+    SyncBatch {
+        since: Timestamp,
+    },
+    /// Records a session that happened without the timer
+    AddSession(NewSession),
+    /// Moves the session that started at `start_time`
+    AdjustSession {
+        start_time: Timestamp,
+        new_start_time: Timestamp,
+        new_end_time: Timestamp,
+    },
+    /// Cuts the session that started at `start_time` in two
+    SplitSession {
+        start_time: Timestamp,
+        at: Timestamp,
+    },
+    /// Joins a session with `second`, the one right after it
+    MergeSessions {
+        first: Timestamp,
+        second: Timestamp,
+    },
+    DeleteSession {
+        start_time: Timestamp,
+    },
+    Subscribe,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    /// Left out for notifications, which get no response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub method: Method,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn ok<T: Serialize>(id: Option<u64>, result: &T) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            jsonrpc: "2.0".to_owned(),
+            id,
+            result: Some(serde_json::to_value(result)?),
+            error: None,
+        })
+    }
+
+    pub fn error(id: Option<u64>, code: i64, message: impl Into<String>) -> Self {
+        Self {
+            jsonrpc: "2.0".to_owned(),
+            id,
+            result: None,
+            error: Some(RpcError {
+                code,
+                message: message.into(),
+            }),
+        }
+    }
+}
+
+/// What subscribers are told, sent as notifications
+/// with these as the method
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Event {
+    /// Someone started, paused, skipped or switched splits
+    Changed(DaemonState),
+    /// A timer ran out. `ended` is the last one
+    /// that did, if the daemon was catching up
+    Expired { ended: String, state: DaemonState },
+}
+
+impl Event {
+    pub fn state(&self) -> &DaemonState {
+        match self {
+            Event::Changed(state) | Event::Expired { state, .. } => state,
+        }
+    }
+
+    /// The event as a line to send a subscriber
+    pub fn to_line(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&Notification {
+            jsonrpc: "2.0".to_owned(),
+            event: self.clone(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Notification {
+    jsonrpc: String,
+    #[serde(flatten)]
+    event: Event,
+}
+
+/// Where the daemon listens, in the runtime directory
+/// when there is one so it goes away on log out
+#[cfg(unix)]
+pub fn socket_path() -> Result<PathBuf, DaemonError> {
+    if let Some(path) = std::env::var_os(SOCKET_ENV) {
+        return Ok(path.into());
+    }
+
+    let project_dir = directories::ProjectDirs::from("live", "Lockinspiel", "Lockinspiel")
+        .ok_or(DaemonError::NoSocketDirectory)?;
+    let dir = project_dir
+        .runtime_dir()
+        .unwrap_or(project_dir.data_dir())
+        .to_owned();
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir.join("lockinspield.sock"))
+}
+
+#[cfg(unix)]
+pub struct DaemonClient {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    next_id: u64,
+}
+
+#[cfg(unix)]
+impl DaemonClient {
+    pub async fn connect() -> Result<Self, DaemonError> {
+        let stream = UnixStream::connect(socket_path()?).await?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer,
+            next_id: 0,
+        })
+    }
+
+    /// Connects to the daemon, starting it first if it
+    /// isn't running. `lockinspield` is looked for next
+    /// to this executable, then on `PATH`
+    pub async fn connect_or_spawn() -> Result<Self, DaemonError> {
+        match Self::connect().await {
+            Ok(client) => return Ok(client),
+            Err(DaemonError::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+                ) => {}
+            Err(e) => return Err(e),
+        }
+
+        let exe = std::env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.parent()?.join("lockinspield")))
+            .filter(|exe| exe.exists())
+            .unwrap_or_else(|| "lockinspield".into());
+        tracing::info!(?exe, "Starting daemon");
+        let mut command = Command::new(exe);
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        // Its own process group, so Ctrl+C in the terminal
+        // that started it doesn't take it down too
+        command.process_group(0);
+        command.spawn().map_err(DaemonError::Spawn)?;
+
+        let started = tokio::time::Instant::now();
+        loop {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            match Self::connect().await {
+                Ok(client) => return Ok(client),
+                Err(_) if started.elapsed() < SPAWN_TIMEOUT => continue,
+                Err(_) => return Err(DaemonError::SpawnTimedOut),
+            }
+        }
+    }
+
+    pub async fn call<T: DeserializeOwned>(&mut self, method: Method) -> Result<T, DaemonError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut line = serde_json::to_string(&Request {
+            jsonrpc: "2.0".to_owned(),
+            id: Some(id),
+            method,
+        })?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+
+        // Only subscribers get notifications, so the next
+        // line is always the response
+        let line = self.read_line().await?.ok_or(DaemonError::Closed)?;
+        let response: Response = serde_json::from_str(&line)?;
+        match (response.result, response.error) {
+            (_, Some(error)) => Err(DaemonError::Rpc {
+                code: error.code,
+                message: error.message,
+            }),
+            (result, None) => Ok(serde_json::from_value(
+                result.unwrap_or(serde_json::Value::Null),
+            )?),
+        }
+    }
+
+    /// Turns this connection into a stream of events,
+    /// returning the state as of subscribing
+    pub async fn subscribe(mut self) -> Result<(DaemonState, Subscription), DaemonError> {
+        let state = self.call(Method::Subscribe).await?;
+        Ok((state, Subscription { client: self }))
+    }
+
+    async fn read_line(&mut self) -> Result<Option<String>, DaemonError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line))
+    }
+}
+
+impl DaemonClient {
+    pub async fn status(&mut self) -> Result<DaemonState, DaemonError> {
+        self.call(Method::Status).await
+    }
+
+    pub async fn start(&mut self, split: Option<String>) -> Result<DaemonState, DaemonError> {
+        self.call(Method::Start { split }).await
+    }
+
+    pub async fn pause(&mut self) -> Result<DaemonState, DaemonError> {
+        self.call(Method::Pause).await
+    }
+
+    pub async fn skip(&mut self) -> Result<DaemonState, DaemonError> {
+        self.call(Method::Skip).await
+    }
+
+    pub async fn select_split(&mut self, split: String) -> Result<DaemonState, DaemonError> {
+        self.call(Method::SelectSplit { split }).await
+    }
+
+    pub async fn toggle_tag(&mut self, tag: String) -> Result<DaemonState, DaemonError> {
+        self.call(Method::ToggleTag { tag }).await
+    }
+
+    pub async fn set_auto_start(&mut self, auto_start: bool) -> Result<DaemonState, DaemonError> {
+        self.call(Method::SetAutoStart { auto_start }).await
+    }
+
+    pub async fn set_idle_policy(&mut self, idle: IdlePolicy) -> Result<DaemonState, DaemonError> {
+        self.call(Method::SetIdlePolicy { idle }).await
+    }
+
+    pub async fn resolve_away(&mut self, keep: bool) -> Result<DaemonState, DaemonError> {
+        self.call(Method::ResolveAway { keep }).await
+    }
+
+    pub async fn follow(&mut self, timer: Timer) -> Result<DaemonState, DaemonError> {
+        self.call(Method::Follow { timer }).await
+    }
+
+    pub async fn unfollow(&mut self) -> Result<DaemonState, DaemonError> {
+        self.call(Method::Unfollow).await
+    }
+
+    pub async fn undo(&mut self) -> Result<DaemonState, DaemonError> {
+        self.call(Method::Undo).await
+    }
+
+    pub async fn redo(&mut self) -> Result<DaemonState, DaemonError> {
+        self.call(Method::Redo).await
+    }
+
+    pub async fn splits(&mut self) -> Result<Vec<SyncedSplit>, DaemonError> {
+        self.call(Method::Splits).await
+    }
+
+    /// Every tag that isn't deleted
+    pub async fn tags(&mut self) -> Result<Vec<String>, DaemonError> {
+        self.call(Method::Tags).await
+    }
+
+    /// Timesheet rows that started at or after `since`, oldest first
+    pub async fn history(&mut self, since: Timestamp) -> Result<Vec<Entry>, DaemonError> {
+        self.call(Method::History { since }).await
+    }
+
+    pub async fn sync_batch(&mut self, since: Timestamp) -> Result<SyncBatch, DaemonError> {
+        self.call(Method::SyncBatch { since }).await
+    }
+
+    pub async fn add_session(&mut self, session: NewSession) -> Result<(), DaemonError> {
+        self.call(Method::AddSession(session)).await
+    }
+
+    pub async fn adjust_session(
+        &mut self,
+        start_time: Timestamp,
+        new_start_time: Timestamp,
+        new_end_time: Timestamp,
+    ) -> Result<(), DaemonError> {
+        self.call(Method::AdjustSession {
+            start_time,
+            new_start_time,
+            new_end_time,
+        })
+        .await
+    }
+
+    pub async fn split_session(
+        &mut self,
+        start_time: Timestamp,
+        at: Timestamp,
+    ) -> Result<(), DaemonError> {
+        self.call(Method::SplitSession { start_time, at }).await
+    }
+
+    pub async fn merge_sessions(
+        &mut self,
+        first: Timestamp,
+        second: Timestamp,
+    ) -> Result<(), DaemonError> {
+        self.call(Method::MergeSessions { first, second }).await
+    }
+
+    pub async fn delete_session(&mut self, start_time: Timestamp) -> Result<(), DaemonError> {
+        self.call(Method::DeleteSession { start_time }).await
+    }
+}
+
+#[cfg(unix)]
+pub struct Subscription {
+    client: DaemonClient,
+}
+
+#[cfg(unix)]
+impl Subscription {
+    /// Waits for the next event. Returns `None`
+    /// once the daemon closes the connection
+    pub async fn next(&mut self) -> Option<Result<Event, DaemonError>> {
+        let line = match self.client.read_line().await {
+            Ok(line) => line?,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(
+            serde_json::from_str::<Notification>(&line)
+                .map(|notification| notification.event)
+                .map_err(Into::into),
+        )
+    }
+}
+
+/// Every client in the app shares the one timer,
+/// started by whichever connects first
+#[cfg(not(unix))]
+static TIMER: OnceCell<SharedTimer> = OnceCell::const_new();
+
+#[cfg(not(unix))]
+pub struct DaemonClient {
+    connection: Connection,
+}
+
+#[cfg(not(unix))]
+impl DaemonClient {
+    pub async fn connect() -> Result<Self, DaemonError> {
+        let timer = TIMER
+            .get_or_try_init(|| async {
+                tracing::info!("No daemon here, running the timer in the app");
+                let mut local = LocalTimer::open(Database::default()?, DEFAULT_SPLIT)?;
+                // Same as the daemon starting up, see there
+                local.expire(Timestamp::now(), false)?;
+                let timer = SharedTimer::new(local, false, IdlePolicy::Ignore);
+                tokio::spawn(shared::tick(timer.clone()));
+                Ok::<_, DaemonError>(timer)
+            })
+            .await?;
+        Ok(Self {
+            connection: timer.connect(),
+        })
+    }
+
+    pub async fn connect_or_spawn() -> Result<Self, DaemonError> {
+        Self::connect().await
+    }
+
+    pub async fn call<T: DeserializeOwned>(&mut self, method: Method) -> Result<T, DaemonError> {
+        let result = self
+            .connection
+            .call(method, Timestamp::now())
+            .await
+            .map_err(|e| DaemonError::Rpc {
+                code: e.code(),
+                message: e.to_string(),
+            })?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Turns this client into a stream of events,
+    /// returning the state as of subscribing
+    pub async fn subscribe(self) -> Result<(DaemonState, Subscription), DaemonError> {
+        let events = self.connection.timer().subscribe();
+        let state = self.connection.timer().state().await?;
+        Ok((
+            state,
+            Subscription {
+                connection: self.connection,
+                events,
+            },
+        ))
+    }
+}
+
+#[cfg(not(unix))]
+pub struct Subscription {
+    connection: Connection,
+    events: broadcast::Receiver<Event>,
+}
+
+#[cfg(not(unix))]
+impl Subscription {
+    /// Waits for the next event. Returns `None`
+    /// once the timer's gone
+    pub async fn next(&mut self) -> Option<Result<Event, DaemonError>> {
+        match self.events.recv().await {
+            Ok(event) => Some(Ok(event)),
+            // Missed some, but the state's all a frontend needs
+            Err(broadcast::error::RecvError::Lagged(_)) => Some(
+                self.connection
+                    .timer()
+                    .state()
+                    .await
+                    .map(Event::Changed)
+                    .map_err(Into::into),
+            ),
+            Err(broadcast::error::RecvError::Closed) => None,
+        }
+    }
+}
@@ -4,8 +4,14 @@ use duckdb::{
     Appender, CachedStatement, DuckdbConnectionManager, Row, Rows, ToSql,
     types::{FromSql, FromSqlError, FromSqlResult, TimeUnit, ToSqlOutput, ValueRef},
 };
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::{
+    sync::{SyncBatch, SyncedGroup, SyncedIdleGap, SyncedSplit, SyncedTag},
+    timer::{SplitTimer, Timer},
+};
+
 macro_rules! try_result_option {
     ($e:expr) => {
         match $e {
@@ -34,9 +40,19 @@ pub enum DbError {
     MigrationDoesntExist(usize),
     #[error("Failed to get DB directory")]
     FailedToGetDBDirectory(#[from] std::io::Error),
+    #[error("Failed to convert saved timer to or from JSON")]
+    Json(#[from] serde_json::Error),
+    #[error("The timesheet already has a session from {0} to {1}, did the clock go back?")]
+    Overlaps(jiff::Timestamp, jiff::Timestamp),
 }
 
-const MIGRATIONS: [&str; 1] = [include_str!("../migrations/000-initial.sql")];
+const MIGRATIONS: [&str; 5] = [
+    include_str!("../migrations/000-initial.sql"),
+    include_str!("../migrations/001-completed.sql"),
+    include_str!("../migrations/002-timer-state.sql"),
+    include_str!("../migrations/003-active-session.sql"),
+    include_str!("../migrations/004-idle-gap.sql"),
+];
 
 impl Database {
     pub fn default() -> Result<Self, DbError> {
@@ -53,7 +69,26 @@ impl Database {
     }
 
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, DbError> {
-        let manager = DuckdbConnectionManager::file(path)?;
+        Self::with_migrations(path, &MIGRATIONS)
+    }
+
+    /// Opens a database with a schema of its own, like the
+    /// tinker terminal's. `migrations` run in order, each
+    /// one once
+    pub fn with_migrations<P: AsRef<Path>>(path: P, migrations: &[&str]) -> Result<Self, DbError> {
+        Self::with_manager(DuckdbConnectionManager::file(path)?, migrations)
+    }
+
+    /// A database that only lives as long as it's open,
+    /// for trying things out
+    pub fn memory() -> Result<Self, DbError> {
+        Self::with_manager(DuckdbConnectionManager::memory()?, &MIGRATIONS)
+    }
+
+    fn with_manager(
+        manager: DuckdbConnectionManager,
+        migrations: &[&str],
+    ) -> Result<Self, DbError> {
         let pool = r2d2::Pool::builder().build(manager)?;
 
         let conn = pool.get()?;
@@ -72,12 +107,14 @@ impl Database {
         let migration_version: i32 =
             conn.query_row("SELECT * FROM migrations", [], |row| row.get(0))?;
 
-        for migration in migration_version + 1..MIGRATIONS.len() as i32 {
+        for migration in migration_version + 1..migrations.len() as i32 {
             let migration_index = migration as usize;
-            let migration_str = MIGRATIONS
+            let migration_str = migrations
                 .get(migration_index)
                 .ok_or(DbError::MigrationDoesntExist(migration_index))?;
-            conn.execute(migration_str, [])?;
+            // Migrations are several statements, which
+            // `execute` can't prepare
+            conn.execute_batch(migration_str)?;
             conn.execute("UPDATE migrations SET version = ?", [migration])?;
             tracing::info!(migration, "Applying migration");
         }
@@ -92,12 +129,16 @@ impl Database {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TimesheetRow {
     pub group: i64,
     pub start_time: JiffTimestamp,
     pub end_time: JiffTimestamp,
-    pub activity: i32,
+    pub work: bool,
+    /// Whether the timer ran all the way out
+    /// instead of being paused or skipped
+    #[serde(default)]
+    pub completed: bool,
 }
 
 impl TryFrom<&Row<'_>> for TimesheetRow {
@@ -108,23 +149,26 @@ impl TryFrom<&Row<'_>> for TimesheetRow {
             group: row.get(0)?,
             start_time: row.get(1)?,
             end_time: row.get(2)?,
-            activity: row.get(3)?,
+            work: row.get(3)?,
+            completed: row.get(4)?,
         })
     }
 }
 
 impl TimesheetRow {
     #[inline]
-    fn as_params(&self) -> [&dyn ToSql; 4] {
+    fn as_params(&self) -> [&dyn ToSql; 5] {
         [
             &self.group,
             &self.start_time,
             &self.end_time,
-            &self.activity,
+            &self.work,
+            &self.completed,
         ]
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TimesheetTagRow {
     pub timesheet_group: i64,
     pub tag_id: i32,
@@ -137,6 +181,15 @@ impl TimesheetTagRow {
     }
 }
 
+/// The timer as a frontend last left it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedTimer {
+    pub split: String,
+    pub timer: Timer,
+    /// Timesheet group the split's rows go in
+    pub group: Option<i64>,
+}
+
 pub struct TimesheetAppender<'a> {
     appender: Appender<'a>,
 }
@@ -154,23 +207,200 @@ pub struct GetTimesheetStmt<'a> {
 }
 
 impl PooledDatabase {
+    /// The connection itself, for schemas
+    /// other than the app's
+    pub fn connection(&mut self) -> &mut duckdb::Connection {
+        &mut self.conn
+    }
+
     pub fn add_to_timesheet(&self, row: TimesheetRow) -> Result<(), DbError> {
-        self.conn
-            .execute("INSERT INTO timesheet VALUES (?, ?, ?, ?)", row.as_params())?;
+        self.conn.execute(
+            "INSERT INTO timesheet VALUES (?, ?, ?, ?, ?)",
+            row.as_params(),
+        )?;
 
         Ok(())
     }
 
+    /// Adds the row of a timer that just started. It's the
+    /// active session until it's stopped or completed.
+    /// After the clock goes back it can land on a session
+    /// that's already recorded, which is refused
+    pub fn start_timer(&self, row: TimesheetRow) -> Result<(), DbError> {
+        self.transaction(|db| {
+            let overlapping = db.overlapping_rows(row.start_time.0, row.end_time.0)?;
+            if let Some(overlapping) = overlapping.first() {
+                return Err(DbError::Overlaps(
+                    overlapping.start_time.0,
+                    overlapping.end_time.0,
+                ));
+            }
+            db.conn.execute(
+                "INSERT INTO timesheet VALUES (?, ?, ?, ?, ?)",
+                row.as_params(),
+            )?;
+            db.set_active_session(Some(row.start_time.0))
+        })
+    }
+
+    /// Ends the active session at `now`. If the clock says
+    /// that's before it started, there's no telling how long
+    /// it ran, so it's kept but ends right after it started
     pub fn stop_timer(&self, now: jiff::Timestamp) -> Result<(), DbError> {
-        self.conn.execute("UPDATE timesheet SET end_time = ? WHERE end_time = (SELECT max(end_time) FROM timesheet)", [JiffTimestamp(now)])?;
+        self.transaction(|db| {
+            let Some(active) = db.get_active_timer()? else {
+                return Ok(());
+            };
+            // A millisecond is as short as rows get, and keeps
+            // the end apart from the row before's
+            let shortest = active.start_time.0 + jiff::SignedDuration::from_millis(1);
+            if now < shortest {
+                tracing::warn!(
+                    %now,
+                    start_time = %active.start_time.0,
+                    "Clock went back, ending session where it started"
+                );
+            }
+            db.conn.execute(
+                "UPDATE timesheet SET end_time = ? WHERE start_time = ?",
+                [JiffTimestamp(now.max(shortest)), active.start_time],
+            )?;
+            db.set_active_session(None)
+        })
+    }
+
+    /// Ends the active session at `end_time` as having run
+    /// all the way out
+    pub fn complete_timer(&self, end_time: jiff::Timestamp) -> Result<(), DbError> {
+        self.transaction(|db| {
+            db.conn.execute(
+                "UPDATE timesheet SET end_time = ?, completed = true
+                WHERE start_time = (SELECT start_time FROM active_session)",
+                [JiffTimestamp(end_time)],
+            )?;
+            db.set_active_session(None)
+        })
+    }
+
+    /// The row that started at `start_time`, which
+    /// is what tells rows apart
+    pub fn timesheet_row(
+        &self,
+        start_time: jiff::Timestamp,
+    ) -> Result<Option<TimesheetRow>, DbError> {
+        let row = match self.conn.query_row(
+            "SELECT * FROM timesheet WHERE start_time = ?",
+            [JiffTimestamp(start_time)],
+            |row| TimesheetRow::try_from(row),
+        ) {
+            Ok(r) => Ok(Some(r)),
+            Err(duckdb::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }?;
+
+        Ok(row)
+    }
+
+    /// The first row to start after `start_time`
+    pub fn next_timesheet_row(
+        &self,
+        start_time: jiff::Timestamp,
+    ) -> Result<Option<TimesheetRow>, DbError> {
+        let row = match self.conn.query_row(
+            "SELECT * FROM timesheet WHERE start_time > ? ORDER BY start_time LIMIT 1",
+            [JiffTimestamp(start_time)],
+            |row| TimesheetRow::try_from(row),
+        ) {
+            Ok(r) => Ok(Some(r)),
+            Err(duckdb::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }?;
+
+        Ok(row)
+    }
+
+    /// Rows with any time between `start_time` and
+    /// `end_time`, oldest first. Rows that only touch
+    /// either end don't count
+    pub fn overlapping_rows(
+        &self,
+        start_time: jiff::Timestamp,
+        end_time: jiff::Timestamp,
+    ) -> Result<Vec<TimesheetRow>, DbError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM timesheet WHERE start_time < ? AND end_time > ? ORDER BY start_time",
+        )?;
+        let rows = stmt
+            .query_map(
+                [JiffTimestamp(end_time), JiffTimestamp(start_time)],
+                |row| TimesheetRow::try_from(row),
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Replaces the row that started at `start_time`
+    pub fn update_timesheet_row(
+        &self,
+        start_time: jiff::Timestamp,
+        row: TimesheetRow,
+    ) -> Result<(), DbError> {
+        self.conn.execute(
+            "UPDATE timesheet
+            SET timesheet_group = ?, start_time = ?, end_time = ?, work = ?, completed = ?
+            WHERE start_time = ?",
+            duckdb::params![
+                row.group,
+                row.start_time,
+                row.end_time,
+                row.work,
+                row.completed,
+                JiffTimestamp(start_time)
+            ],
+        )?;
 
         Ok(())
     }
 
-    pub fn get_active_timer(&self, now: jiff::Timestamp) -> Result<Option<TimesheetRow>, DbError> {
+    pub fn delete_timesheet_row(&self, start_time: jiff::Timestamp) -> Result<(), DbError> {
+        self.conn.execute(
+            "DELETE FROM timesheet WHERE start_time = ?",
+            [JiffTimestamp(start_time)],
+        )?;
+
+        Ok(())
+    }
+
+    /// Runs `f` in a transaction, rolling
+    /// everything back if it fails
+    pub fn transaction<T, E: From<DbError>>(
+        &self,
+        f: impl FnOnce(&Self) -> Result<T, E>,
+    ) -> Result<T, E> {
+        self.conn
+            .execute_batch("BEGIN TRANSACTION")
+            .map_err(DbError::from)?;
+        match f(self) {
+            Ok(result) => {
+                self.conn.execute_batch("COMMIT").map_err(DbError::from)?;
+                Ok(result)
+            }
+            Err(e) => {
+                if let Err(e) = self.conn.execute_batch("ROLLBACK") {
+                    tracing::error!(?e, "Failed to roll back transaction");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// The row of the timer that's going, whether or
+    /// not it's run past its end yet
+    pub fn get_active_timer(&self) -> Result<Option<TimesheetRow>, DbError> {
         let active_timer: Option<TimesheetRow> = match self.conn.query_row(
-            "SELECT * FROM timesheet WHERE end_time >= $1",
-            [JiffTimestamp(now)],
+            "SELECT timesheet.* FROM timesheet
+            JOIN active_session ON active_session.start_time = timesheet.start_time",
+            [],
             |row| TimesheetRow::try_from(row),
         ) {
             Ok(r) => Ok(Some(r)),
@@ -181,6 +411,89 @@ impl PooledDatabase {
         Ok(active_timer)
     }
 
+    /// Makes the row that started at `start_time` the
+    /// active session, or leaves none
+    pub fn set_active_session(&self, start_time: Option<jiff::Timestamp>) -> Result<(), DbError> {
+        match start_time {
+            Some(start_time) => self.conn.execute(
+                "INSERT OR REPLACE INTO active_session VALUES (0, ?)",
+                [JiffTimestamp(start_time)],
+            )?,
+            None => self.conn.execute("DELETE FROM active_session", [])?,
+        };
+
+        Ok(())
+    }
+
+    /// Notes nobody was around from `start_time` to `end_time`
+    pub fn add_idle_gap(
+        &self,
+        start_time: jiff::Timestamp,
+        end_time: jiff::Timestamp,
+    ) -> Result<(), DbError> {
+        self.conn.execute(
+            "INSERT INTO idle_gap VALUES (?, ?)
+            ON CONFLICT (start_time) DO UPDATE SET end_time = greatest(end_time, excluded.end_time)",
+            [JiffTimestamp(start_time), JiffTimestamp(end_time)],
+        )?;
+
+        Ok(())
+    }
+
+    /// Every idle gap that ended after `since`, oldest first
+    pub fn idle_gaps(
+        &self,
+        since: jiff::Timestamp,
+    ) -> Result<Vec<(jiff::Timestamp, jiff::Timestamp)>, DbError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT start_time, end_time FROM idle_gap WHERE end_time > ? ORDER BY start_time",
+        )?;
+        let gaps = stmt
+            .query_map([JiffTimestamp(since)], |row| {
+                Ok((
+                    row.get::<_, JiffTimestamp>(0)?.0,
+                    row.get::<_, JiffTimestamp>(1)?.0,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(gaps)
+    }
+
+    pub fn save_timer(&self, saved: &SavedTimer) -> Result<(), DbError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO timer_state VALUES (0, ?, ?, ?)",
+            duckdb::params![
+                saved.split,
+                serde_json::to_string(&saved.timer)?,
+                saved.group
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn load_timer(&self) -> Result<Option<SavedTimer>, DbError> {
+        let saved = match self.conn.query_row(
+            "SELECT split, timer, timesheet_group FROM timer_state",
+            [],
+            |row| Ok((row.get(0)?, row.get::<_, String>(1)?, row.get(2)?)),
+        ) {
+            Ok(r) => Ok(Some(r)),
+            Err(duckdb::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }?;
+
+        saved
+            .map(|(split, timer, group)| {
+                Ok(SavedTimer {
+                    split,
+                    timer: serde_json::from_str(&timer)?,
+                    group,
+                })
+            })
+            .transpose()
+    }
+
     pub fn add_tag(&self, tag: &str) -> Result<i32, DbError> {
         let tag_id = self.conn.query_row(
             "INSERT INTO tag(tag) VALUES (?) RETURNING id",
@@ -190,10 +503,72 @@ impl PooledDatabase {
         Ok(tag_id)
     }
 
-    pub fn next_timesheet_group(&self) -> Result<i64, DbError> {
+    /// Every tag that hasn't been deleted, by name
+    pub fn tags(&self) -> Result<Vec<String>, DbError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT tag FROM tag WHERE NOT deleted ORDER BY tag")?;
+        let tags = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(tags)
+    }
+
+    /// The ID of the tag named `tag`, adding it if
+    /// there isn't one or bringing it back if it
+    /// was deleted
+    pub fn tag_id(&self, tag: &str) -> Result<i32, DbError> {
+        let tag_id = match self.conn.query_row(
+            "UPDATE tag SET deleted = false WHERE tag = ? RETURNING id",
+            [tag],
+            |row| row.get(0),
+        ) {
+            Ok(tag_id) => tag_id,
+            Err(duckdb::Error::QueryReturnedNoRows) => self.add_tag(tag)?,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(tag_id)
+    }
+
+    /// Tags on a timesheet group, by name
+    pub fn group_tags(&self, timesheet_group: i64) -> Result<Vec<String>, DbError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT tag.tag FROM timesheet_tag
+            JOIN tag ON tag.id = timesheet_tag.tag_id
+            WHERE timesheet_tag.timesheet_group = ?
+            ORDER BY tag.tag",
+        )?;
+        let tags = stmt
+            .query_map([timesheet_group], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(tags)
+    }
+
+    pub fn tag_group(&self, row: TimesheetTagRow) -> Result<(), DbError> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO timesheet_tag VALUES (?, ?)",
+            row.as_params(),
+        )?;
+        Ok(())
+    }
+
+    pub fn untag_group(&self, row: TimesheetTagRow) -> Result<(), DbError> {
+        self.conn.execute(
+            "DELETE FROM timesheet_tag WHERE timesheet_group = ? AND tag_id = ?",
+            row.as_params(),
+        )?;
+        Ok(())
+    }
+
+    /// Starts a group for a run of the split named `split`
+    pub fn next_timesheet_group(&self, split: &str) -> Result<i64, DbError> {
         let next_timesheet = self.conn.query_row(
-            "INSERT INTO timesheet_group(timesheet_group) VALUES (DEFAULT) RETURNING timesheet_group",
-            [],
+            "INSERT INTO timesheet_group(time_split_id)
+            SELECT id FROM time_split WHERE name = ? AND id != 0
+            ORDER BY deleted, id
+            LIMIT 1
+            RETURNING timesheet_group",
+            [split],
             |row| row.get(0),
         )?;
         Ok(next_timesheet)
@@ -211,6 +586,102 @@ impl PooledDatabase {
         })
     }
 
+    /// Everything that started at or after `since`,
+    /// ready to be sent to the tinker terminal
+    pub fn sync_batch(&self, since: jiff::Timestamp) -> Result<SyncBatch, DbError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT * FROM timesheet WHERE start_time >= ? ORDER BY start_time")?;
+        let timesheet = stmt
+            .query_map([JiffTimestamp(since)], |row| TimesheetRow::try_from(row))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT timesheet_tag.timesheet_group, tag.tag FROM timesheet_tag
+            JOIN tag ON tag.id = timesheet_tag.tag_id
+            WHERE timesheet_tag.timesheet_group IN
+                (SELECT timesheet_group FROM timesheet WHERE start_time >= ?)",
+        )?;
+        let tags = stmt
+            .query_map([JiffTimestamp(since)], |row| {
+                Ok(SyncedTag {
+                    timesheet_group: row.get(0)?,
+                    tag: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT timesheet_group.timesheet_group, time_split.name FROM timesheet_group
+            JOIN time_split ON time_split.id = timesheet_group.time_split_id
+            WHERE timesheet_group.timesheet_group IN
+                (SELECT timesheet_group FROM timesheet WHERE start_time >= ?)",
+        )?;
+        let groups = stmt
+            .query_map([JiffTimestamp(since)], |row| {
+                Ok(SyncedGroup {
+                    timesheet_group: row.get(0)?,
+                    split: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(SyncBatch {
+            since: Some(since),
+            timesheet,
+            tags,
+            splits: self.splits()?,
+            groups,
+            idle_gaps: self
+                .idle_gaps(since)?
+                .into_iter()
+                .map(|(start_time, end_time)| SyncedIdleGap {
+                    start_time,
+                    end_time,
+                })
+                .collect(),
+        })
+    }
+
+    /// Every split that hasn't been deleted, with its
+    /// timers in the order they run
+    pub fn splits(&self) -> Result<Vec<SyncedSplit>, DbError> {
+        // `_paused_` (id 0) only exists to give paused
+        // time a group
+        let mut stmt = self.conn.prepare(
+            "SELECT time_split.id, time_split.name, time_split.description,
+                time_split_timer.name, CAST(epoch(time_split_timer.len) * 1000 AS BIGINT),
+                time_split_timer.work
+            FROM time_split
+            JOIN time_split_timer ON time_split_timer.time_split_id = time_split.id
+            WHERE time_split.id != 0 AND NOT time_split.deleted
+            ORDER BY time_split.id, time_split_timer.rowid",
+        )?;
+        let mut rows = stmt.query([])?;
+
+        let mut splits: Vec<SyncedSplit> = Vec::new();
+        let mut last_id = None;
+        while let Some(row) = rows.next()? {
+            let id: i32 = row.get(0)?;
+            let timer = SplitTimer {
+                name: row.get(3)?,
+                len: jiff::SignedDuration::from_millis(row.get(4)?),
+                work: row.get(5)?,
+            };
+            match splits.last_mut() {
+                Some(split) if last_id == Some(id) => split.timers.push(timer),
+                _ => splits.push(SyncedSplit {
+                    name: row.get(1)?,
+                    description: row.get(2)?,
+                    timers: vec![timer],
+                }),
+            }
+            last_id = Some(id);
+        }
+
+        Ok(splits)
+    }
+
     pub fn get_timesheet_stmt<'a>(&'a self) -> Result<GetTimesheetStmt<'a>, DbError> {
         Ok(GetTimesheetStmt {
             stmt: self
@@ -274,7 +745,8 @@ impl<'a> TimesheetTagAppender<'a> {
 }
 
 #[repr(transparent)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct JiffTimestamp(pub jiff::Timestamp);
 
 /// Taken from here
@@ -342,3 +814,112 @@ impl FromSql for JiffTimestamp {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use jiff::Timestamp;
+
+    use super::*;
+
+    fn at(secs: i64) -> Timestamp {
+        Timestamp::from_second(1_700_000_000 + secs).unwrap()
+    }
+
+    fn row(db: &PooledDatabase, start: i64, end: i64) -> TimesheetRow {
+        TimesheetRow {
+            group: db.next_timesheet_group("Pomodoro").unwrap(),
+            start_time: JiffTimestamp(at(start)),
+            end_time: JiffTimestamp(at(end)),
+            work: true,
+            completed: false,
+        }
+    }
+
+    fn active_start(db: &PooledDatabase) -> Option<Timestamp> {
+        db.get_active_timer()
+            .unwrap()
+            .map(|active| active.start_time.0)
+    }
+
+    #[test]
+    fn stop_after_clock_went_back_keeps_session() {
+        let db = Database::memory().unwrap().get().unwrap();
+        db.start_timer(row(&db, 0, 1500)).unwrap();
+
+        db.stop_timer(at(-60)).unwrap();
+
+        let kept = db.timesheet_row(at(0)).unwrap().unwrap();
+        assert!(kept.end_time.0 > kept.start_time.0);
+        assert!(kept.end_time.0 < at(1));
+        assert!(!kept.completed);
+        assert_eq!(active_start(&db), None);
+    }
+
+    #[test]
+    fn stop_after_clock_went_back_back_to_back() {
+        let db = Database::memory().unwrap().get().unwrap();
+        db.start_timer(row(&db, 0, 1500)).unwrap();
+        db.complete_timer(at(1500)).unwrap();
+        db.start_timer(row(&db, 1500, 1800)).unwrap();
+
+        // Ending where it started would clash with the
+        // end of the row before
+        db.stop_timer(at(1000)).unwrap();
+
+        assert!(db.timesheet_row(at(1500)).unwrap().is_some());
+        assert_eq!(
+            db.timesheet_row(at(0)).unwrap().unwrap().end_time.0,
+            at(1500)
+        );
+    }
+
+    #[test]
+    fn start_after_clock_went_back_into_recorded_session() {
+        let db = Database::memory().unwrap().get().unwrap();
+        db.start_timer(row(&db, 0, 1500)).unwrap();
+        db.stop_timer(at(600)).unwrap();
+
+        let error = db.start_timer(row(&db, 300, 1800)).unwrap_err();
+
+        assert!(matches!(
+            error,
+            DbError::Overlaps(start, end) if start == at(0) && end == at(600)
+        ));
+        assert!(db.timesheet_row(at(300)).unwrap().is_none());
+        assert_eq!(active_start(&db), None);
+    }
+
+    #[test]
+    fn start_after_clock_went_back_before_recorded_session() {
+        let db = Database::memory().unwrap().get().unwrap();
+        db.start_timer(row(&db, 0, 1500)).unwrap();
+        db.stop_timer(at(600)).unwrap();
+
+        // Running into the recorded session is refused,
+        // stopping short of it isn't
+        assert!(db.start_timer(row(&db, -1500, 300)).is_err());
+        db.start_timer(row(&db, -1500, 0)).unwrap();
+
+        assert_eq!(active_start(&db), Some(at(-1500)));
+    }
+
+    #[test]
+    fn active_timer_with_rows_in_the_future() {
+        let db = Database::memory().unwrap().get().unwrap();
+        // Left by a clock that was ahead
+        db.add_to_timesheet(row(&db, 3000, 4500)).unwrap();
+        db.add_to_timesheet(row(&db, 6000, 7500)).unwrap();
+        db.start_timer(row(&db, 0, 1500)).unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(active_start(&db), Some(at(0)));
+        }
+
+        db.stop_timer(at(100)).unwrap();
+        assert_eq!(active_start(&db), None);
+        assert_eq!(
+            db.timesheet_row(at(3000)).unwrap().unwrap().end_time.0,
+            at(4500)
+        );
+    }
+}
@@ -0,0 +1,373 @@
+//! Fixing the timesheet up by hand, for when a timer was
+//! left going or never started. Sessions are found by
+//! when they started, like the entries in `history`.
+//!
+//! The row of a timer that's still going belongs to the
+//! timer, so it can't be touched until it's paused, and
+//! nothing can be put in the future
+use jiff::{Timestamp, tz::TimeZone};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::db::{DbError, JiffTimestamp, PooledDatabase, TimesheetRow, TimesheetTagRow};
+
+#[derive(Error, Debug)]
+pub enum EditError {
+    #[error("Database error")]
+    Db(#[from] DbError),
+    #[error("There's no session starting at {}", local(.0))]
+    NoSuchSession(Timestamp),
+    #[error("No split named {0}")]
+    NoSuchSplit(String),
+    #[error("Sessions have to end after they start")]
+    Backwards,
+    #[error("Sessions can't end in the future")]
+    InFuture,
+    #[error("That session is still going, pause the timer first")]
+    StillGoing,
+    #[error("That overlaps the session from {} to {}", local(.0), local(.1))]
+    Overlaps(Timestamp, Timestamp),
+    #[error("Sessions can only be split somewhere in the middle")]
+    SplitOutside,
+    #[error("Only a session and the one right after it can be merged")]
+    NotBackToBack,
+    #[error("Work can't be merged with a break")]
+    MixedWork,
+}
+
+/// A session that happened without the timer
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NewSession {
+    pub start_time: Timestamp,
+    pub end_time: Timestamp,
+    pub work: bool,
+    /// Split to record it under
+    pub split: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Records a session after the fact, in a
+/// timesheet group of its own
+pub fn add(db: &PooledDatabase, session: &NewSession, now: Timestamp) -> Result<(), EditError> {
+    check_span(session.start_time, session.end_time, now)?;
+    if !db.splits()?.iter().any(|split| split.name == session.split) {
+        return Err(EditError::NoSuchSplit(session.split.clone()));
+    }
+
+    db.transaction(|db| {
+        check_free(db, session.start_time, session.end_time, &[])?;
+        let group = db.next_timesheet_group(&session.split)?;
+        db.add_to_timesheet(TimesheetRow {
+            group,
+            start_time: JiffTimestamp(session.start_time),
+            end_time: JiffTimestamp(session.end_time),
+            work: session.work,
+            completed: false,
+        })?;
+        for tag in &session.tags {
+            db.tag_group(TimesheetTagRow {
+                timesheet_group: group,
+                tag_id: db.tag_id(tag)?,
+            })?;
+        }
+        tracing::info!(%session.start_time, %session.end_time, "Added session");
+        Ok(())
+    })
+}
+
+/// Moves the start and end of the session that started
+/// at `start_time`. One that ends somewhere else didn't
+/// run all the way out anymore
+pub fn adjust(
+    db: &PooledDatabase,
+    start_time: Timestamp,
+    new_start_time: Timestamp,
+    new_end_time: Timestamp,
+    now: Timestamp,
+) -> Result<(), EditError> {
+    check_span(new_start_time, new_end_time, now)?;
+
+    db.transaction(|db| {
+        let row = finished_row(db, start_time, now)?;
+        check_free(db, new_start_time, new_end_time, &[start_time])?;
+        db.update_timesheet_row(
+            start_time,
+            TimesheetRow {
+                start_time: JiffTimestamp(new_start_time),
+                end_time: JiffTimestamp(new_end_time),
+                completed: row.completed && row.end_time.0 == new_end_time,
+                ..row
+            },
+        )?;
+        tracing::info!(%new_start_time, %new_end_time, "Adjusted session");
+        Ok(())
+    })
+}
+
+/// Cuts the session that started at `start_time` in two
+/// `at` some point in it. Both halves keep the split and
+/// tags, and only the second can have run all the way out
+pub fn split(
+    db: &PooledDatabase,
+    start_time: Timestamp,
+    at: Timestamp,
+    now: Timestamp,
+) -> Result<(), EditError> {
+    db.transaction(|db| {
+        let row = finished_row(db, start_time, now)?;
+        if at <= row.start_time.0 || at >= row.end_time.0 {
+            return Err(EditError::SplitOutside);
+        }
+
+        // Shortened first, so the two never share an end
+        db.update_timesheet_row(
+            start_time,
+            TimesheetRow {
+                end_time: JiffTimestamp(at),
+                completed: false,
+                ..row.clone()
+            },
+        )?;
+        db.add_to_timesheet(TimesheetRow {
+            start_time: JiffTimestamp(at),
+            ..row
+        })?;
+        tracing::info!(%start_time, %at, "Split session");
+        Ok(())
+    })
+}
+
+/// Joins the session that started at `first` with
+/// `second`, the one right after it, covering any gap
+/// between them. The first one's split and tags stay
+pub fn merge(
+    db: &PooledDatabase,
+    first: Timestamp,
+    second: Timestamp,
+    now: Timestamp,
+) -> Result<(), EditError> {
+    db.transaction(|db| {
+        let first_row = finished_row(db, first, now)?;
+        let second_row = finished_row(db, second, now)?;
+        let next = db.next_timesheet_row(first)?;
+        if next.is_none_or(|next| next.start_time != second_row.start_time) {
+            return Err(EditError::NotBackToBack);
+        }
+        if first_row.work != second_row.work {
+            return Err(EditError::MixedWork);
+        }
+
+        // Deleted first, so its end is free to take
+        db.delete_timesheet_row(second)?;
+        db.update_timesheet_row(
+            first,
+            TimesheetRow {
+                end_time: second_row.end_time,
+                completed: second_row.completed,
+                ..first_row
+            },
+        )?;
+        tracing::info!(%first, %second, "Merged sessions");
+        Ok(())
+    })
+}
+
+pub fn delete(db: &PooledDatabase, start_time: Timestamp, now: Timestamp) -> Result<(), EditError> {
+    db.transaction(|db| {
+        finished_row(db, start_time, now)?;
+        db.delete_timesheet_row(start_time)?;
+        tracing::info!(%start_time, "Deleted session");
+        Ok(())
+    })
+}
+
+fn check_span(start_time: Timestamp, end_time: Timestamp, now: Timestamp) -> Result<(), EditError> {
+    if end_time <= start_time {
+        return Err(EditError::Backwards);
+    }
+    if end_time > now {
+        return Err(EditError::InFuture);
+    }
+    Ok(())
+}
+
+/// Refuses a span that would overlap any row, other
+/// than the ones starting at `ignoring`
+fn check_free(
+    db: &PooledDatabase,
+    start_time: Timestamp,
+    end_time: Timestamp,
+    ignoring: &[Timestamp],
+) -> Result<(), EditError> {
+    let overlapping = db
+        .overlapping_rows(start_time, end_time)?
+        .into_iter()
+        .find(|row| !ignoring.contains(&row.start_time.0));
+    match overlapping {
+        Some(row) => Err(EditError::Overlaps(row.start_time.0, row.end_time.0)),
+        None => Ok(()),
+    }
+}
+
+/// The row that started at `start_time`,
+/// as long as it isn't still going
+fn finished_row(
+    db: &PooledDatabase,
+    start_time: Timestamp,
+    now: Timestamp,
+) -> Result<TimesheetRow, EditError> {
+    let row = db
+        .timesheet_row(start_time)?
+        .ok_or(EditError::NoSuchSession(start_time))?;
+    let active = db.get_active_timer()?;
+    if row.end_time.0 > now || active.is_some_and(|active| active.start_time == row.start_time) {
+        return Err(EditError::StillGoing);
+    }
+    Ok(row)
+}
+
+/// Times in errors are for people, so they're local
+fn local(timestamp: &Timestamp) -> String {
+    timestamp
+        .to_zoned(TimeZone::system())
+        .strftime("%a %b %-d %H:%M")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::Database;
+
+    use super::*;
+
+    fn at(secs: i64) -> Timestamp {
+        Timestamp::from_second(1_700_000_000 + secs).unwrap()
+    }
+
+    /// Well after every session in these tests
+    fn now() -> Timestamp {
+        at(100_000)
+    }
+
+    fn add_session(db: &PooledDatabase, start: i64, end: i64, work: bool) {
+        let session = NewSession {
+            start_time: at(start),
+            end_time: at(end),
+            work,
+            split: "Pomodoro".to_owned(),
+            tags: Vec::new(),
+        };
+        add(db, &session, now()).unwrap();
+    }
+
+    fn span(db: &PooledDatabase, start: i64) -> Option<(Timestamp, Timestamp)> {
+        db.timesheet_row(at(start))
+            .unwrap()
+            .map(|row| (row.start_time.0, row.end_time.0))
+    }
+
+    #[test]
+    fn refuses_overlap() {
+        let db = Database::memory().unwrap().get().unwrap();
+        add_session(&db, 0, 1500, true);
+
+        let session = NewSession {
+            start_time: at(1000),
+            end_time: at(2000),
+            work: true,
+            split: "Pomodoro".to_owned(),
+            tags: Vec::new(),
+        };
+        match add(&db, &session, now()) {
+            Err(EditError::Overlaps(start, end)) => assert_eq!((start, end), (at(0), at(1500))),
+            result => panic!("Expected an overlap, got {:?}", result),
+        }
+        assert_eq!(span(&db, 1000), None);
+
+        add_session(&db, 2000, 2300, false);
+        let result = adjust(&db, at(2000), at(1400), at(2300), now());
+        assert!(matches!(result, Err(EditError::Overlaps(..))));
+        assert_eq!(span(&db, 2000), Some((at(2000), at(2300))));
+    }
+
+    #[test]
+    fn allows_touching_edges() {
+        let db = Database::memory().unwrap().get().unwrap();
+        add_session(&db, 0, 1500, true);
+        add_session(&db, 1500, 1800, false);
+        add_session(&db, -300, 0, false);
+
+        assert_eq!(span(&db, -300), Some((at(-300), at(0))));
+        assert_eq!(span(&db, 0), Some((at(0), at(1500))));
+        assert_eq!(span(&db, 1500), Some((at(1500), at(1800))));
+
+        // Moving a session right up against another is fine too
+        add_session(&db, 2000, 2600, true);
+        adjust(&db, at(2000), at(1800), at(2600), now()).unwrap();
+        assert_eq!(span(&db, 1800), Some((at(1800), at(2600))));
+    }
+
+    #[test]
+    fn split_at_exact_boundaries() {
+        let db = Database::memory().unwrap().get().unwrap();
+        add_session(&db, 0, 1500, true);
+
+        assert!(matches!(
+            split(&db, at(0), at(0), now()),
+            Err(EditError::SplitOutside)
+        ));
+        assert!(matches!(
+            split(&db, at(0), at(1500), now()),
+            Err(EditError::SplitOutside)
+        ));
+        assert_eq!(span(&db, 0), Some((at(0), at(1500))));
+
+        split(&db, at(0), at(1), now()).unwrap();
+        assert_eq!(span(&db, 0), Some((at(0), at(1))));
+        assert_eq!(span(&db, 1), Some((at(1), at(1500))));
+        let first = db.timesheet_row(at(0)).unwrap().unwrap();
+        let second = db.timesheet_row(at(1)).unwrap().unwrap();
+        assert_eq!(first.group, second.group);
+        assert!(first.work && second.work);
+    }
+
+    #[test]
+    fn merge_refuses_sessions_that_arent_next_to_each_other() {
+        let db = Database::memory().unwrap().get().unwrap();
+        add_session(&db, 0, 600, true);
+        add_session(&db, 900, 1200, true);
+        add_session(&db, 1500, 1800, true);
+
+        assert!(matches!(
+            merge(&db, at(0), at(1500), now()),
+            Err(EditError::NotBackToBack)
+        ));
+        // Backwards counts as not next to each other
+        assert!(matches!(
+            merge(&db, at(900), at(0), now()),
+            Err(EditError::NotBackToBack)
+        ));
+        assert_eq!(span(&db, 0), Some((at(0), at(600))));
+        assert_eq!(span(&db, 1500), Some((at(1500), at(1800))));
+
+        // Any gap up to the next one is covered
+        merge(&db, at(0), at(900), now()).unwrap();
+        assert_eq!(span(&db, 0), Some((at(0), at(1200))));
+        assert_eq!(span(&db, 900), None);
+    }
+
+    #[test]
+    fn merge_refuses_work_with_a_break() {
+        let db = Database::memory().unwrap().get().unwrap();
+        add_session(&db, 0, 1500, true);
+        add_session(&db, 1500, 1800, false);
+
+        assert!(matches!(
+            merge(&db, at(0), at(1500), now()),
+            Err(EditError::MixedWork)
+        ));
+        assert_eq!(span(&db, 0), Some((at(0), at(1500))));
+        assert_eq!(span(&db, 1500), Some((at(1500), at(1800))));
+    }
+}
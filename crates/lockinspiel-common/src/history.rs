@@ -0,0 +1,188 @@
+//! The local timesheet, joined up with the split and
+//! tags each row was recorded with
+use std::collections::{BTreeMap, HashMap};
+
+use jiff::{SignedDuration, Timestamp, tz::TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{DbError, PooledDatabase},
+    idle,
+};
+
+/// One row of the timesheet
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub start_time: Timestamp,
+    pub end_time: Timestamp,
+    pub work: bool,
+    pub completed: bool,
+    pub split: Option<String>,
+    pub tags: Vec<String>,
+    /// Time away from the computer while it was going
+    #[serde(default)]
+    pub idle: SignedDuration,
+}
+
+impl Entry {
+    /// How long the row has run as of `now`. Rows
+    /// that are still going end in the future
+    pub fn length(&self, now: Timestamp) -> SignedDuration {
+        self.start_time
+            .duration_until(self.end_time.min(now))
+            .max(SignedDuration::ZERO)
+    }
+
+    /// The length, less the time nobody was around
+    pub fn worked(&self, now: Timestamp) -> SignedDuration {
+        (self.length(now) - self.idle).max(SignedDuration::ZERO)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TagTotal {
+    pub tag: String,
+    pub worked: SignedDuration,
+    /// Timesheet groups with the tag
+    pub sessions: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Stats {
+    pub today: SignedDuration,
+    /// The last seven days, today included
+    pub week: SignedDuration,
+    pub total: SignedDuration,
+    /// Days in a row with some work, up to today
+    /// or yesterday
+    pub streak: usize,
+    /// Work timers that ran all the way out
+    pub completed: usize,
+    /// Work timers that were paused or skipped
+    pub cut_short: usize,
+}
+
+/// Every row that started at or after `since`, oldest first
+pub fn entries(db: &PooledDatabase, since: Timestamp) -> Result<Vec<Entry>, DbError> {
+    let batch = db.sync_batch(since)?;
+    let gaps: Vec<_> = batch
+        .idle_gaps
+        .iter()
+        .map(|gap| (gap.start_time, gap.end_time))
+        .collect();
+
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for tag in batch.tags {
+        tags.entry(tag.timesheet_group).or_default().push(tag.tag);
+    }
+    let splits: HashMap<i64, String> = batch
+        .groups
+        .into_iter()
+        .map(|group| (group.timesheet_group, group.split))
+        .collect();
+
+    Ok(batch
+        .timesheet
+        .into_iter()
+        .map(|row| Entry {
+            start_time: row.start_time.0,
+            end_time: row.end_time.0,
+            work: row.work,
+            completed: row.completed,
+            split: splits.get(&row.group).cloned(),
+            tags: tags.get(&row.group).cloned().unwrap_or_default(),
+            idle: idle::overlap(&gaps, row.start_time.0, row.end_time.0),
+        })
+        .collect())
+}
+
+/// Work time per tag, most worked first. Tags
+/// nothing was recorded with are included
+pub fn tag_totals(all_tags: Vec<String>, entries: &[Entry], now: Timestamp) -> Vec<TagTotal> {
+    let mut totals: BTreeMap<String, (SignedDuration, usize)> = all_tags
+        .into_iter()
+        .map(|tag| (tag, (SignedDuration::ZERO, 0)))
+        .collect();
+
+    // Consecutive rows share a group, so a new session
+    // starts wherever the tags change
+    let mut last_tags: Option<&[String]> = None;
+    for entry in entries {
+        let new_session = last_tags != Some(entry.tags.as_slice());
+        last_tags = Some(entry.tags.as_slice());
+        for tag in &entry.tags {
+            let (worked, sessions) = totals.entry(tag.clone()).or_default();
+            if entry.work {
+                *worked += entry.worked(now);
+            }
+            if new_session {
+                *sessions += 1;
+            }
+        }
+    }
+
+    let mut totals: Vec<TagTotal> = totals
+        .into_iter()
+        .map(|(tag, (worked, sessions))| TagTotal {
+            tag,
+            worked,
+            sessions,
+        })
+        .collect();
+    totals.sort_by(|a, b| b.worked.cmp(&a.worked));
+    totals
+}
+
+/// Totals over `entries`, with days counted in `tz`
+pub fn stats(entries: &[Entry], now: Timestamp, tz: &TimeZone) -> Result<Stats, jiff::Error> {
+    let today = now.to_zoned(tz.clone()).date();
+    let start_of_today = today.to_zoned(tz.clone())?.timestamp();
+    let start_of_week = today
+        .checked_sub(jiff::Span::new().days(6))?
+        .to_zoned(tz.clone())?
+        .timestamp();
+
+    let mut stats = Stats {
+        today: SignedDuration::ZERO,
+        week: SignedDuration::ZERO,
+        total: SignedDuration::ZERO,
+        streak: 0,
+        completed: 0,
+        cut_short: 0,
+    };
+    let mut days = Vec::new();
+    for entry in entries.iter().filter(|entry| entry.work) {
+        let length = entry.worked(now);
+        stats.total += length;
+        if entry.start_time >= start_of_week {
+            stats.week += length;
+        }
+        if entry.start_time >= start_of_today {
+            stats.today += length;
+        }
+        if entry.completed {
+            stats.completed += 1;
+        } else if entry.end_time <= now {
+            stats.cut_short += 1;
+        }
+        days.push(entry.start_time.to_zoned(tz.clone()).date());
+    }
+
+    // Walk back from the newest work until a day is missing
+    days.sort_unstable();
+    days.dedup();
+    let mut expected = today;
+    for day in days.into_iter().rev() {
+        if day > expected {
+            continue;
+        }
+        if day == expected || (stats.streak == 0 && day == today.yesterday()?) {
+            stats.streak += 1;
+            expected = day.yesterday()?;
+            continue;
+        }
+        break;
+    }
+
+    Ok(stats)
+}
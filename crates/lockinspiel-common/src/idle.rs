@@ -0,0 +1,94 @@
+//! Time away from the computer while a session was going,
+//! because it slept or nobody touched it. The daemon
+//! notices, and the policy decides what that does to the
+//! timer and the timesheet
+use std::{fmt, str::FromStr};
+
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdlePolicy {
+    /// Leave the timer going, like nothing happened
+    #[default]
+    Ignore,
+    /// Pause the timer as of when the computer was left
+    Pause,
+    /// Leave the timer going, and ask when back whether
+    /// the time away was work
+    Ask,
+    /// Leave the timer going, counting the time away
+    /// as idle in the sessions it overlaps
+    Record,
+}
+
+impl IdlePolicy {
+    pub const ALL: [IdlePolicy; 4] = [
+        IdlePolicy::Ignore,
+        IdlePolicy::Pause,
+        IdlePolicy::Ask,
+        IdlePolicy::Record,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            IdlePolicy::Ignore => "ignore",
+            IdlePolicy::Pause => "pause",
+            IdlePolicy::Ask => "ask",
+            IdlePolicy::Record => "record",
+        }
+    }
+}
+
+impl fmt::Display for IdlePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for IdlePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|policy| policy.name() == s)
+            .ok_or_else(|| format!("{} isn't one of ignore, pause, ask or record", s))
+    }
+}
+
+/// Time away waiting on an answer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Away {
+    pub since: Timestamp,
+    pub until: Timestamp,
+}
+
+impl Away {
+    pub fn length(&self) -> SignedDuration {
+        self.since.duration_until(self.until)
+    }
+}
+
+/// How much of `start_time` to `end_time` falls in `gaps`,
+/// which are sorted by when they started
+pub fn overlap(
+    gaps: &[(Timestamp, Timestamp)],
+    start_time: Timestamp,
+    end_time: Timestamp,
+) -> SignedDuration {
+    // Gaps from sleeping while idle can overlap, so
+    // each one only counts past the last one's end
+    let mut counted = start_time;
+    let mut idle = SignedDuration::ZERO;
+    for (gap_start, gap_end) in gaps {
+        let from = (*gap_start).max(counted);
+        let to = (*gap_end).min(end_time);
+        if from < to {
+            idle += from.duration_until(to);
+            counted = to;
+        }
+    }
+    idle
+}
@@ -4,8 +4,17 @@ use tracing_error::ErrorLayer;
 use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
 
 pub mod client;
+pub mod daemon;
 pub mod db;
+pub mod edit;
+pub mod history;
+pub mod idle;
+pub mod local;
+pub mod room;
+pub mod shared;
+pub mod sync;
 pub mod timer;
+pub mod undo;
 
 pub fn install_init_boilerplate(level_filter: Option<LevelFilter>) -> eyre::Result<()> {
     color_eyre::install()?;
@@ -0,0 +1,298 @@
+//! The timer kept in the local database. Every change
+//! is written to the timesheet and saved as it happens,
+//! so any frontend can pick up where another left off
+use jiff::Timestamp;
+
+use crate::{
+    db::{Database, DbError, JiffTimestamp, SavedTimer, TimesheetRow, TimesheetTagRow},
+    room::RoomAction,
+    sync::SyncedSplit,
+    timer::{Timer, TimerState},
+    undo::{Action, ActionLog, Snapshot},
+};
+
+/// The split run when nothing's been picked
+pub const DEFAULT_SPLIT: &str = "Tyson Split";
+
+pub struct LocalTimer {
+    db: Database,
+    saved: SavedTimer,
+    /// Starts, pauses, skips and split switches since
+    /// the timer last changed on its own
+    log: ActionLog,
+}
+
+impl LocalTimer {
+    /// Picks up the saved timer, or starts `default_split`
+    /// (the first split if there's no such split) when
+    /// nothing's been saved yet
+    pub fn open(db: Database, default_split: &str) -> Result<Self, DbError> {
+        let conn = db.get()?;
+        let saved = conn
+            .load_timer()?
+            .filter(|saved| saved.timer.timer_on < saved.timer.timers.len());
+        let saved = match saved {
+            Some(saved) => {
+                tracing::info!(split = saved.split, "Resuming saved timer");
+                saved
+            }
+            // Nothing saved yet, so start the default split,
+            // picking up the session that was going
+            None => {
+                let splits = conn.splits()?;
+                let split = splits
+                    .iter()
+                    .find(|split| split.name == default_split)
+                    .or(splits.first())
+                    .expect("Database has no splits")
+                    .clone();
+                let mut timer = Timer::new(split.timers);
+                if let Some(span) = conn.get_active_timer()? {
+                    timer.state = TimerState::Going(span.end_time.0);
+                }
+                SavedTimer {
+                    split: split.name,
+                    timer,
+                    group: None,
+                }
+            }
+        };
+        drop(conn);
+
+        Ok(Self {
+            db,
+            saved,
+            log: ActionLog::default(),
+        })
+    }
+
+    #[inline]
+    pub fn timer(&self) -> &Timer {
+        &self.saved.timer
+    }
+
+    /// Name of the split the timer is running
+    #[inline]
+    pub fn split(&self) -> &str {
+        &self.saved.split
+    }
+
+    #[inline]
+    pub fn db(&self) -> &Database {
+        &self.db
+    }
+
+    /// Tags on this run of the split
+    pub fn tags(&self) -> Result<Vec<String>, DbError> {
+        match self.saved.group {
+            Some(group) => self.db.get()?.group_tags(group),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Adds `tag` to this run of the split, or takes it
+    /// off if it's already there. Returns whether it's on
+    pub fn toggle_tag(&mut self, tag: &str) -> Result<bool, DbError> {
+        let tagged = self.tags()?.iter().any(|t| t == tag);
+        let group = self.group_or_insert()?;
+        let db = self.db.get()?;
+        let row = TimesheetTagRow {
+            timesheet_group: group,
+            tag_id: db.tag_id(tag)?,
+        };
+        if tagged {
+            db.untag_group(row)?;
+        } else {
+            db.tag_group(row)?;
+        }
+        Ok(!tagged)
+    }
+
+    /// Starts the split from the top. Does nothing while
+    /// the timer is going, returning whether it switched
+    pub fn select_split(&mut self, split: SyncedSplit) -> Result<bool, DbError> {
+        if matches!(self.saved.timer.state, TimerState::Going(_)) || split.timers.is_empty() {
+            return Ok(false);
+        }
+
+        tracing::info!(split = split.name, "Selected split");
+        self.logged(Action::SelectSplit, |this| {
+            this.saved = SavedTimer {
+                split: split.name,
+                timer: Timer::new(split.timers),
+                group: None,
+            };
+            this.save()
+        })?;
+        Ok(true)
+    }
+
+    /// Starts, pauses or skips the timer
+    pub fn act(&mut self, action: RoomAction, now: Timestamp) -> Result<(), DbError> {
+        let mut timer = self.saved.timer.clone();
+        let logged = match action {
+            RoomAction::Start => {
+                timer.start(now);
+                Action::Start
+            }
+            RoomAction::Pause => {
+                timer.pause(now);
+                Action::Pause
+            }
+            RoomAction::Skip => {
+                timer.skip();
+                Action::Skip
+            }
+        };
+        self.logged(logged, |this| this.apply(timer, now))
+    }
+
+    /// Replaces the timer, like with the one a room decided
+    /// on. There's no undoing from before it
+    pub fn set_timer(&mut self, timer: Timer, now: Timestamp) -> Result<(), DbError> {
+        self.log.clear();
+        self.apply(timer, now)
+    }
+
+    /// The action undo would take back
+    #[inline]
+    pub fn next_undo(&self) -> Option<Action> {
+        self.log.next_undo()
+    }
+
+    /// The action redo would do again
+    #[inline]
+    pub fn next_redo(&self) -> Option<Action> {
+        self.log.next_redo()
+    }
+
+    /// Takes back the last start, pause, skip or split
+    /// switch, rows in the timesheet included. Returns
+    /// what was undone, if there was anything
+    pub fn undo(&mut self) -> Result<Option<Action>, DbError> {
+        let undone = self.log.undo(&self.db.get()?)?;
+        Ok(undone.map(|(action, saved)| {
+            self.saved = saved;
+            action
+        }))
+    }
+
+    /// Does the last undone action again
+    pub fn redo(&mut self) -> Result<Option<Action>, DbError> {
+        let redone = self.log.redo(&self.db.get()?)?;
+        Ok(redone.map(|(action, saved)| {
+            self.saved = saved;
+            action
+        }))
+    }
+
+    /// Forgets what could be undone, for when the timesheet
+    /// was edited and undoing could put back rows the edit
+    /// changed. Returns whether there was anything
+    pub fn forget_actions(&mut self) -> bool {
+        self.log.clear()
+    }
+
+    /// Runs `f`, logging what it did to the timer
+    /// and timesheet so it can be undone
+    fn logged(
+        &mut self,
+        action: Action,
+        f: impl FnOnce(&mut Self) -> Result<(), DbError>,
+    ) -> Result<(), DbError> {
+        let db = self.db.get()?;
+        let active = db.get_active_timer()?.map(|row| row.start_time.0);
+        let mut before = Snapshot::take(&db, &self.saved, active.as_slice())?;
+        drop(db);
+
+        f(self)?;
+
+        // Starting adds a row that wasn't there before
+        let db = self.db.get()?;
+        let mut rows: Vec<_> = active.into_iter().collect();
+        rows.extend(db.get_active_timer()?.map(|row| row.start_time.0));
+        rows.dedup();
+        before.add_missing(&rows);
+        let after = Snapshot::take(&db, &self.saved, &rows)?;
+        self.log.push(action, before, after);
+        Ok(())
+    }
+
+    /// Replaces the timer, recording whatever
+    /// started or stopped in the timesheet
+    fn apply(&mut self, timer: Timer, now: Timestamp) -> Result<(), DbError> {
+        match (self.saved.timer.state, timer.state) {
+            (TimerState::Going(_), TimerState::Paused(_)) => self.db.get()?.stop_timer(now)?,
+            (TimerState::Paused(_), TimerState::Going(end_time)) => {
+                self.record(now, end_time, timer.current().work)?
+            }
+            (TimerState::Going(old_end), TimerState::Going(end_time)) if old_end != end_time => {
+                self.db.get()?.stop_timer(now)?;
+                self.record(now, end_time, timer.current().work)?;
+            }
+            _ => {}
+        }
+        self.saved.timer = timer;
+        self.save()
+    }
+
+    /// Moves past the timer if it ran out by `now`, marking
+    /// its row completed. Only that one is caught up on, so
+    /// the next is left paused if it would have run out too.
+    /// Returns the name of the timer that ran out, if it did
+    pub fn expire(&mut self, now: Timestamp, auto_start: bool) -> Result<Option<String>, DbError> {
+        let name = self.saved.timer.current().name.clone();
+        let Some(ended_at) = self.saved.timer.expire(now, auto_start) else {
+            return Ok(None);
+        };
+        tracing::info!(%ended_at, timer = name, "Timer ran out");
+        self.db.get()?.complete_timer(ended_at)?;
+        // Auto-started timers begin where the last one ended
+        if let TimerState::Going(end_time) = self.saved.timer.state {
+            let work = self.saved.timer.current().work;
+            self.record(ended_at, end_time, work)?;
+        }
+
+        // Undoing past this would bring back a
+        // timer that's already run out
+        self.log.clear();
+        self.save()?;
+        Ok(Some(name))
+    }
+
+    fn record(
+        &mut self,
+        start_time: Timestamp,
+        end_time: Timestamp,
+        work: bool,
+    ) -> Result<(), DbError> {
+        let group = self.group_or_insert()?;
+        self.db.get()?.start_timer(TimesheetRow {
+            group,
+            start_time: JiffTimestamp(start_time),
+            end_time: JiffTimestamp(end_time),
+            work,
+            completed: false,
+        })
+    }
+
+    /// The group this run of the split records into,
+    /// started when the first row or tag needs it
+    fn group_or_insert(&mut self) -> Result<i64, DbError> {
+        match self.saved.group {
+            Some(group) => Ok(group),
+            None => {
+                let group = self.db.get()?.next_timesheet_group(&self.saved.split)?;
+                self.saved.group = Some(group);
+                self.save()?;
+                Ok(group)
+            }
+        }
+    }
+
+    /// Saves where the timer is, so a restart
+    /// picks up right here
+    fn save(&self) -> Result<(), DbError> {
+        self.db.get()?.save_timer(&self.saved)
+    }
+}
@@ -0,0 +1,54 @@
+//! Types shared between the tinker terminal and its
+//! clients for running a split together in a room.
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+use crate::timer::{SplitTimer, Timer};
+
+/// Body of `POST /rooms`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CreateRoom {
+    pub split: String,
+    pub timers: Vec<SplitTimer>,
+}
+
+/// Everything a member needs to show the room's timer.
+/// Timestamps in `timer` come from the server's clock,
+/// so compare them against `LockinspielClient::now()`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomState {
+    pub code: String,
+    pub split: String,
+    pub timer: Timer,
+    pub members: usize,
+    pub updated_at: Timestamp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomAction {
+    Start,
+    Pause,
+    Skip,
+}
+
+impl RoomAction {
+    /// The name used in URLs and JSON
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RoomAction::Start => "start",
+            RoomAction::Pause => "pause",
+            RoomAction::Skip => "skip",
+        }
+    }
+}
+
+/// Sent to every member of a room over
+/// `GET /rooms/{code}/events`. `action` is `None`
+/// when only the membership of the room changed,
+/// or for the snapshot sent right after joining
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RoomEvent {
+    pub action: Option<RoomAction>,
+    pub room: RoomState,
+}
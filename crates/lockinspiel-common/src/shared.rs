@@ -0,0 +1,465 @@
+//! The timer every frontend shares, with what's done to
+//! it sent out to subscribers. `lockinspield` serves it,
+//! and where there's no daemon it runs in the app itself
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use jiff::{SignedDuration, Timestamp};
+use thiserror::Error;
+use tokio::sync::broadcast;
+
+use crate::{
+    daemon::{DaemonState, Event, Method, codes},
+    db::DbError,
+    edit::{self, EditError},
+    history,
+    idle::{Away, IdlePolicy},
+    local::LocalTimer,
+    room::RoomAction,
+    timer::TimerState,
+};
+
+/// Shorter time away isn't worth asking about or recording
+const MIN_AWAY: SignedDuration = SignedDuration::from_mins(1);
+
+#[derive(Error, Debug)]
+pub enum CallError {
+    /// Sent back as is, these are for people
+    #[error("{0}")]
+    Refused(String),
+    /// Clashes with the timesheet are for people,
+    /// the rest are only worth logging
+    #[error("{0}")]
+    Db(#[from] DbError),
+    /// Mostly edits that don't fit the timesheet,
+    /// which are for people too
+    #[error("{0}")]
+    Edit(#[from] EditError),
+    #[error("Failed to convert the result to JSON")]
+    Json(#[from] serde_json::Error),
+    #[error("subscribe has to be handled by the connection")]
+    Subscribe,
+}
+
+impl CallError {
+    pub fn code(&self) -> i64 {
+        match self {
+            CallError::Refused(_) | CallError::Db(DbError::Overlaps(..)) => codes::REFUSED,
+            CallError::Edit(EditError::Db(_)) => codes::INTERNAL_ERROR,
+            CallError::Edit(_) => codes::REFUSED,
+            CallError::Subscribe => codes::INVALID_REQUEST,
+            CallError::Db(_) | CallError::Json(_) => codes::INTERNAL_ERROR,
+        }
+    }
+}
+
+struct Inner {
+    local: LocalTimer,
+    auto_start: bool,
+    /// The connection whose room decides what the timer
+    /// does, until it unfollows or goes away
+    following: Option<u64>,
+    idle: IdlePolicy,
+    /// When the computer was left while the timer was going
+    left: Option<Timestamp>,
+    /// Time away waiting on an answer
+    away: Option<Away>,
+}
+
+/// The one timer every frontend shares
+#[derive(Clone)]
+pub struct SharedTimer {
+    inner: Arc<Mutex<Inner>>,
+    events: broadcast::Sender<Event>,
+    next_connection: Arc<AtomicU64>,
+}
+
+/// One frontend's hold on the timer. Whatever room it
+/// followed is let go of once it's dropped
+pub struct Connection {
+    timer: SharedTimer,
+    id: u64,
+}
+
+impl Connection {
+    pub fn timer(&self) -> &SharedTimer {
+        &self.timer
+    }
+
+    pub async fn call(
+        &self,
+        method: Method,
+        now: Timestamp,
+    ) -> Result<serde_json::Value, CallError> {
+        let id = self.id;
+        self.timer
+            .blocking(move |timer| timer.call_from(Some(id), method, now))
+            .await
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let (timer, id) = (self.timer.clone(), self.id);
+        let disconnected = move || {
+            if let Err(e) = timer.disconnected(id) {
+                tracing::error!(?e, "Failed to stop following for a closed connection");
+            }
+        };
+        // Outside the runtime there's nothing to hold up
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(disconnected)),
+            Err(_) => disconnected(),
+        }
+    }
+}
+
+impl SharedTimer {
+    pub fn new(local: LocalTimer, auto_start: bool, idle: IdlePolicy) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                local,
+                auto_start,
+                following: None,
+                idle,
+                left: None,
+                away: None,
+            })),
+            events: broadcast::channel(16).0,
+            next_connection: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// A new frontend, which is the only kind that can
+    /// follow a room
+    pub fn connect(&self) -> Connection {
+        Connection {
+            timer: self.clone(),
+            id: self.next_connection.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Runs `f` off the async threads, since the timer
+    /// holds its lock through DuckDB calls
+    async fn blocking<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&SharedTimer) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let timer = self.clone();
+        match tokio::task::spawn_blocking(move || f(&timer)).await {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+
+    /// Stops following if it was `connection` doing it, so a
+    /// frontend that crashed doesn't leave the timer frozen
+    fn disconnected(&self, connection: u64) -> Result<(), DbError> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.following != Some(connection) {
+            return Ok(());
+        }
+
+        tracing::info!(connection, "Follower went away, back to the local timer");
+        inner.following = None;
+        let _ = self.events.send(Event::Changed(inner.state()?));
+        Ok(())
+    }
+
+    /// Events from now on. Subscribe before reading the
+    /// state so nothing in between is missed
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    pub async fn state(&self) -> Result<DaemonState, DbError> {
+        self.blocking(|timer| timer.inner.lock().unwrap().state())
+            .await
+    }
+
+    /// Moves past timers that ran out, telling subscribers
+    /// about the last one. Rooms are left alone, the
+    /// server owns their timer
+    pub async fn expire(&self, now: Timestamp) -> Result<(), DbError> {
+        self.blocking(move |timer| {
+            let mut inner = timer.inner.lock().unwrap();
+            if inner.following.is_some() {
+                return Ok(());
+            }
+
+            let auto_start = inner.auto_start;
+            if let Some(ended) = inner.local.expire(now, auto_start)? {
+                let state = inner.state()?;
+                // Nobody listening is fine
+                let _ = timer.events.send(Event::Expired { ended, state });
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Nobody's been at the computer since `since`, because
+    /// it's going to sleep or sat idle. Only matters while
+    /// the timer's going
+    pub async fn left(&self, since: Timestamp) -> Result<(), DbError> {
+        self.blocking(move |timer| {
+            let mut inner = timer.inner.lock().unwrap();
+            let going = matches!(inner.local.timer().state, TimerState::Going(_));
+            if inner.following.is_some() || !going || inner.idle == IdlePolicy::Ignore {
+                return Ok(());
+            }
+
+            tracing::info!(%since, "Left the computer");
+            inner.left = Some(since);
+            if inner.idle == IdlePolicy::Pause {
+                // Idle is only noticed a while after it started, and
+                // the timer could have moved on to another session
+                let active = inner.local.db().get()?.get_active_timer()?;
+                let at = active.map_or(since, |row| since.max(row.start_time.0));
+                inner.local.act(RoomAction::Pause, at)?;
+                let _ = timer.events.send(Event::Changed(inner.state()?));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Somebody's back at the computer
+    pub async fn back(&self, now: Timestamp) -> Result<(), DbError> {
+        self.blocking(move |timer| {
+            let mut inner = timer.inner.lock().unwrap();
+            let Some(since) = inner.left.take() else {
+                return Ok(());
+            };
+            let away = Away { since, until: now };
+            tracing::info!(length = %away.length(), "Back at the computer");
+            if away.length() < MIN_AWAY {
+                return Ok(());
+            }
+
+            match inner.idle {
+                IdlePolicy::Ignore | IdlePolicy::Pause => {}
+                IdlePolicy::Ask => {
+                    if let Some(unanswered) = inner.away.replace(away) {
+                        tracing::info!(?unanswered, "Kept time away nobody answered for");
+                    }
+                    let _ = timer.events.send(Event::Changed(inner.state()?));
+                }
+                IdlePolicy::Record => inner.local.db().get()?.add_idle_gap(since, now)?,
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// A call from something that isn't a connection,
+    /// like D-Bus, so it can't follow rooms
+    pub async fn call(
+        &self,
+        method: Method,
+        now: Timestamp,
+    ) -> Result<serde_json::Value, CallError> {
+        self.blocking(move |timer| timer.call_from(None, method, now))
+            .await
+    }
+
+    fn call_from(
+        &self,
+        connection: Option<u64>,
+        method: Method,
+        now: Timestamp,
+    ) -> Result<serde_json::Value, CallError> {
+        let mut inner = self.inner.lock().unwrap();
+        let room_decides = matches!(
+            method,
+            Method::Start { .. }
+                | Method::Pause
+                | Method::Skip
+                | Method::SelectSplit { .. }
+                | Method::Undo
+                | Method::Redo
+        );
+        if room_decides && inner.following.is_some() {
+            return Err(CallError::Refused(
+                "The room decides what the timer does".to_owned(),
+            ));
+        }
+
+        let going = matches!(inner.local.timer().state, TimerState::Going(_));
+        match method {
+            Method::Status => return Ok(serde_json::to_value(inner.state()?)?),
+            Method::Start { split } => {
+                if let Some(split) = split.filter(|split| split != inner.local.split()) {
+                    inner.select_split(&split)?;
+                }
+                if !matches!(inner.local.timer().state, TimerState::Going(_)) {
+                    inner.local.act(RoomAction::Start, now)?;
+                }
+            }
+            Method::Pause => {
+                if going {
+                    inner.local.act(RoomAction::Pause, now)?;
+                }
+            }
+            Method::Skip => {
+                if going {
+                    return Err(CallError::Refused(
+                        "Pause the timer before skipping".to_owned(),
+                    ));
+                }
+                inner.local.act(RoomAction::Skip, now)?;
+            }
+            Method::SelectSplit { split } => inner.select_split(&split)?,
+            Method::ToggleTag { tag } => {
+                inner.local.toggle_tag(&tag)?;
+            }
+            Method::SetAutoStart { auto_start } => inner.auto_start = auto_start,
+            Method::SetIdlePolicy { idle } => {
+                inner.idle = idle;
+                if idle != IdlePolicy::Ask {
+                    inner.away = None;
+                }
+            }
+            Method::ResolveAway { keep } => {
+                let Some(away) = inner.away.take() else {
+                    return Err(CallError::Refused(
+                        "There's no time away to answer for".to_owned(),
+                    ));
+                };
+                if !keep {
+                    let db = inner.local.db().get()?;
+                    db.add_idle_gap(away.since, away.until)?;
+                }
+            }
+            Method::Follow { timer } => {
+                let Some(connection) = connection else {
+                    return Err(CallError::Refused(
+                        "Only a connection can follow a room".to_owned(),
+                    ));
+                };
+                inner.following = Some(connection);
+                inner.local.set_timer(timer, now)?;
+            }
+            Method::Unfollow => {
+                // Someone else's room isn't this one's to leave
+                if inner.following == connection {
+                    inner.following = None;
+                }
+            }
+            Method::Undo => {
+                if inner.local.undo()?.is_none() {
+                    return Err(CallError::Refused("Nothing to undo".to_owned()));
+                }
+            }
+            Method::Redo => {
+                if inner.local.redo()?.is_none() {
+                    return Err(CallError::Refused("Nothing to redo".to_owned()));
+                }
+            }
+            Method::Splits => return Ok(serde_json::to_value(inner.local.db().get()?.splits()?)?),
+            Method::Tags => return Ok(serde_json::to_value(inner.local.db().get()?.tags()?)?),
+            Method::History { since } => {
+                let entries = history::entries(&inner.local.db().get()?, since)?;
+                return Ok(serde_json::to_value(entries)?);
+            }
+            Method::SyncBatch { since } => {
+                let batch = inner.local.db().get()?.sync_batch(since)?;
+                return Ok(serde_json::to_value(batch)?);
+            }
+            Method::AddSession(session) => {
+                edit::add(&inner.local.db().get()?, &session, now)?;
+                return self.edited(&mut inner);
+            }
+            Method::AdjustSession {
+                start_time,
+                new_start_time,
+                new_end_time,
+            } => {
+                let db = inner.local.db().get()?;
+                edit::adjust(&db, start_time, new_start_time, new_end_time, now)?;
+                return self.edited(&mut inner);
+            }
+            Method::SplitSession { start_time, at } => {
+                edit::split(&inner.local.db().get()?, start_time, at, now)?;
+                return self.edited(&mut inner);
+            }
+            Method::MergeSessions { first, second } => {
+                edit::merge(&inner.local.db().get()?, first, second, now)?;
+                return self.edited(&mut inner);
+            }
+            Method::DeleteSession { start_time } => {
+                edit::delete(&inner.local.db().get()?, start_time, now)?;
+                return self.edited(&mut inner);
+            }
+            Method::Subscribe => return Err(CallError::Subscribe),
+        }
+
+        // Everything left changed the timer
+        let state = inner.state()?;
+        let _ = self.events.send(Event::Changed(state.clone()));
+        Ok(serde_json::to_value(state)?)
+    }
+
+    /// Edits leave the timer alone, but undoing after one
+    /// could put back rows it changed, so what could be
+    /// undone is forgotten
+    fn edited(&self, inner: &mut Inner) -> Result<serde_json::Value, CallError> {
+        if inner.local.forget_actions() {
+            let _ = self.events.send(Event::Changed(inner.state()?));
+        }
+        Ok(serde_json::Value::Null)
+    }
+}
+
+impl Inner {
+    fn state(&self) -> Result<DaemonState, DbError> {
+        Ok(DaemonState {
+            split: self.local.split().to_owned(),
+            timer: self.local.timer().clone(),
+            tags: self.local.tags()?,
+            auto_start: self.auto_start,
+            following: self.following.is_some(),
+            undo: self.local.next_undo(),
+            redo: self.local.next_redo(),
+            idle: self.idle,
+            away: self.away,
+        })
+    }
+
+    fn select_split(&mut self, name: &str) -> Result<(), CallError> {
+        let splits = self.local.db().get()?.splits()?;
+        let Some(split) = splits.iter().find(|split| split.name == name) else {
+            let names: Vec<_> = splits.iter().map(|split| split.name.as_str()).collect();
+            return Err(CallError::Refused(format!(
+                "No split named {}, try one of {}",
+                name,
+                names.join(", ")
+            )));
+        };
+        if !self.local.select_split(split.clone())? {
+            return Err(CallError::Refused(
+                "Pause the timer before switching splits".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Expires timers as they run out, whether
+/// or not any frontend is open
+pub async fn tick(timer: SharedTimer) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if let Err(e) = timer.expire(Timestamp::now()).await {
+            tracing::error!(?e, "Failed to expire timer");
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! What clients upload to the tinker terminal so
+//! their history can be looked at from anywhere
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+use crate::{db::TimesheetRow, timer::SplitTimer};
+
+/// Body of `POST /sync`. Rows are identified by
+/// their `start_time`, so sending one again
+/// replaces it instead of duplicating it
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SyncBatch {
+    /// Everything synced before that started from here on
+    /// is replaced by the batch, so rows gone from the
+    /// timesheet go from the server too. Left out, it's
+    /// the first row in the batch
+    #[serde(default)]
+    pub since: Option<Timestamp>,
+    pub timesheet: Vec<TimesheetRow>,
+    pub tags: Vec<SyncedTag>,
+    #[serde(default)]
+    pub splits: Vec<SyncedSplit>,
+    #[serde(default)]
+    pub groups: Vec<SyncedGroup>,
+    /// Time away from the computer, which the rows it
+    /// overlaps don't count as worked
+    #[serde(default)]
+    pub idle_gaps: Vec<SyncedIdleGap>,
+}
+
+/// A tag on a timesheet group, by name since
+/// tag IDs are local to each client. Groups are
+/// too, so they only tie rows in one batch together
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncedTag {
+    pub timesheet_group: i64,
+    pub tag: String,
+}
+
+/// A split the client has, by name like tags
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncedSplit {
+    pub name: String,
+    pub description: Option<String>,
+    pub timers: Vec<SplitTimer>,
+}
+
+/// Time nobody was at the computer, that ended
+/// after the batch's `since`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncedIdleGap {
+    pub start_time: Timestamp,
+    pub end_time: Timestamp,
+}
+
+/// The split a timesheet group was run with
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncedGroup {
+    pub timesheet_group: i64,
+    pub split: String,
+}
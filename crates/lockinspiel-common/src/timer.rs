@@ -1,6 +1,115 @@
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TimerState {
-    Paused(jiff::SignedDuration),
-    Going(jiff::Timestamp),
+    Paused(SignedDuration),
+    Going(Timestamp),
+}
+
+/// One step of a split, like the "Work" or "Break"
+/// rows in `time_split_timer`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitTimer {
+    pub name: String,
+    pub len: SignedDuration,
+    pub work: bool,
 }
 
-pub struct Timer {}
+/// A split being run. `timer_on` indexes into `timers`
+/// and `state` is the countdown of that timer.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timer {
+    pub state: TimerState,
+    pub timers: Vec<SplitTimer>,
+    pub timer_on: usize,
+}
+
+impl Timer {
+    /// Creates a paused timer at the start of the split.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `timers` is empty
+    pub fn new(timers: Vec<SplitTimer>) -> Self {
+        Self {
+            state: TimerState::Paused(timers[0].len),
+            timers,
+            timer_on: 0,
+        }
+    }
+
+    #[inline]
+    pub fn current(&self) -> &SplitTimer {
+        &self.timers[self.timer_on]
+    }
+
+    /// Time left on the current timer as of `now`.
+    /// Never negative, even once the timer has run out
+    pub fn remaining(&self, now: Timestamp) -> SignedDuration {
+        match self.state {
+            TimerState::Going(end_time) => now.duration_until(end_time).max(SignedDuration::ZERO),
+            TimerState::Paused(remaining) => remaining,
+        }
+    }
+
+    /// Starts the countdown, returning when it will end.
+    /// Returns `None` if the timer is already going.
+    pub fn start(&mut self, now: Timestamp) -> Option<Timestamp> {
+        match self.state {
+            TimerState::Going(_) => None,
+            TimerState::Paused(remaining) => {
+                let end_time = now + remaining;
+                self.state = TimerState::Going(end_time);
+                Some(end_time)
+            }
+        }
+    }
+
+    /// Pauses the countdown, returning the time that was
+    /// left. Returns `None` if the timer is already paused.
+    pub fn pause(&mut self, now: Timestamp) -> Option<SignedDuration> {
+        match self.state {
+            TimerState::Paused(_) => None,
+            TimerState::Going(end_time) => {
+                let remaining = now.duration_until(end_time).max(SignedDuration::ZERO);
+                self.state = TimerState::Paused(remaining);
+                Some(remaining)
+            }
+        }
+    }
+
+    /// Moves on to the next timer in the split, wrapping
+    /// around at the end. The next timer starts paused.
+    pub fn skip(&mut self) {
+        self.timer_on += 1;
+        if self.timer_on >= self.timers.len() {
+            self.timer_on = 0;
+        }
+        self.state = TimerState::Paused(self.current().len);
+    }
+
+    /// Moves on to the next timer if the current one ran out
+    /// by `now`, returning when it ended. With `auto_start`
+    /// the next timer starts right when the last one ended,
+    /// unless it would have run out by `now` too. Nobody was
+    /// looking then, so it's left paused instead of making
+    /// up timers that ran while nobody was there.
+    pub fn expire(&mut self, now: Timestamp, auto_start: bool) -> Option<Timestamp> {
+        let TimerState::Going(end_time) = self.state else {
+            return None;
+        };
+        if end_time > now {
+            return None;
+        }
+
+        self.skip();
+        // An empty timer would end the moment it
+        // started, over and over
+        let len = self.current().len;
+        if auto_start && len > SignedDuration::ZERO && end_time + len > now {
+            self.start(end_time);
+        }
+        Some(end_time)
+    }
+}
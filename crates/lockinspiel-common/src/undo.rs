@@ -0,0 +1,295 @@
+//! Taking back timer actions hit by accident. Each one
+//! is logged with what the timer and the timesheet rows
+//! it touched looked like before and after, so undoing
+//! and redoing put them back exactly
+use std::collections::VecDeque;
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{DbError, PooledDatabase, SavedTimer, TimesheetRow};
+
+/// How many actions can be undone in a row
+pub const UNDO_LIMIT: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Start,
+    Pause,
+    Skip,
+    SelectSplit,
+}
+
+/// The timer and part of the timesheet at one point
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub saved: SavedTimer,
+    /// When the active session started
+    active: Option<Timestamp>,
+    /// Rows by when they started, `None` where there was
+    /// no row yet or it got dropped
+    rows: Vec<(Timestamp, Option<TimesheetRow>)>,
+}
+
+impl Snapshot {
+    /// The timer as `saved`, along with the active
+    /// session and the rows that started at `rows`
+    pub fn take(
+        db: &PooledDatabase,
+        saved: &SavedTimer,
+        rows: &[Timestamp],
+    ) -> Result<Self, DbError> {
+        Ok(Self {
+            saved: saved.clone(),
+            active: db.get_active_timer()?.map(|row| row.start_time.0),
+            rows: rows
+                .iter()
+                .map(|start_time| Ok((*start_time, db.timesheet_row(*start_time)?)))
+                .collect::<Result<_, DbError>>()?,
+        })
+    }
+
+    /// Notes that there were no rows at `rows` yet, for
+    /// rows an action added after this was taken
+    pub fn add_missing(&mut self, rows: &[Timestamp]) {
+        for start_time in rows {
+            if !self.rows.iter().any(|(row, _)| row == start_time) {
+                self.rows.push((*start_time, None));
+            }
+        }
+    }
+
+    /// Puts the timesheet and saved timer back how they were
+    fn restore(&self, db: &PooledDatabase) -> Result<(), DbError> {
+        db.transaction(|db| {
+            for (start_time, row) in &self.rows {
+                match (db.timesheet_row(*start_time)?, row) {
+                    (Some(_), Some(row)) => db.update_timesheet_row(*start_time, row.clone())?,
+                    (Some(_), None) => db.delete_timesheet_row(*start_time)?,
+                    (None, Some(row)) => db.add_to_timesheet(row.clone())?,
+                    (None, None) => {}
+                }
+            }
+            db.set_active_session(self.active)?;
+            db.save_timer(&self.saved)
+        })
+    }
+}
+
+struct Logged {
+    action: Action,
+    before: Snapshot,
+    after: Snapshot,
+}
+
+/// The last `UNDO_LIMIT` actions, and the ones undone
+/// since the last new action
+#[derive(Default)]
+pub struct ActionLog {
+    undo: VecDeque<Logged>,
+    redo: Vec<Logged>,
+}
+
+impl ActionLog {
+    /// Logs `action`, leaving nothing to redo. Actions
+    /// that changed nothing aren't worth undoing
+    pub fn push(&mut self, action: Action, before: Snapshot, after: Snapshot) {
+        if before == after {
+            return;
+        }
+        self.redo.clear();
+        if self.undo.len() == UNDO_LIMIT {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(Logged {
+            action,
+            before,
+            after,
+        });
+    }
+
+    /// Forgets everything, for when the timer or timesheet
+    /// changed some other way and the snapshots would put
+    /// back something stale. Returns whether there was
+    /// anything to forget
+    pub fn clear(&mut self) -> bool {
+        let had_any = !self.undo.is_empty() || !self.redo.is_empty();
+        self.undo.clear();
+        self.redo.clear();
+        had_any
+    }
+
+    /// What undo would take back
+    pub fn next_undo(&self) -> Option<Action> {
+        self.undo.back().map(|logged| logged.action)
+    }
+
+    /// What redo would do again
+    pub fn next_redo(&self) -> Option<Action> {
+        self.redo.last().map(|logged| logged.action)
+    }
+
+    /// Takes back the last action, returning the timer
+    /// as it was before it
+    pub fn undo(&mut self, db: &PooledDatabase) -> Result<Option<(Action, SavedTimer)>, DbError> {
+        let Some(logged) = self.undo.pop_back() else {
+            return Ok(None);
+        };
+        if let Err(e) = logged.before.restore(db) {
+            self.undo.push_back(logged);
+            return Err(e);
+        }
+        tracing::info!(action = ?logged.action, "Undid action");
+        let undone = (logged.action, logged.before.saved.clone());
+        self.redo.push(logged);
+        Ok(Some(undone))
+    }
+
+    /// Does the last undone action again, returning
+    /// the timer as it was after it
+    pub fn redo(&mut self, db: &PooledDatabase) -> Result<Option<(Action, SavedTimer)>, DbError> {
+        let Some(logged) = self.redo.pop() else {
+            return Ok(None);
+        };
+        if let Err(e) = logged.after.restore(db) {
+            self.redo.push(logged);
+            return Err(e);
+        }
+        tracing::info!(action = ?logged.action, "Redid action");
+        let redone = (logged.action, logged.after.saved.clone());
+        self.undo.push_back(logged);
+        Ok(Some(redone))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db::Database,
+        local::{DEFAULT_SPLIT, LocalTimer},
+        room::RoomAction,
+        timer::Timer,
+    };
+
+    use super::*;
+
+    fn at(secs: i64) -> Timestamp {
+        Timestamp::from_second(1_700_000_000 + secs).unwrap()
+    }
+
+    /// Everything undoing has to put back: the timesheet, the
+    /// active session and the timer, saved and in memory
+    #[derive(Debug, PartialEq)]
+    struct State {
+        rows: Vec<TimesheetRow>,
+        active: Option<TimesheetRow>,
+        saved: Option<SavedTimer>,
+        timer: Timer,
+        split: String,
+    }
+
+    fn state(local: &LocalTimer) -> State {
+        let db = local.db().get().unwrap();
+        let mut stmt = db.get_timesheet_stmt().unwrap();
+        let rows = stmt
+            .get_timesheet(at(-100_000), at(100_000))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        State {
+            rows,
+            active: db.get_active_timer().unwrap(),
+            saved: db.load_timer().unwrap(),
+            timer: local.timer().clone(),
+            split: local.split().to_owned(),
+        }
+    }
+
+    fn open() -> LocalTimer {
+        LocalTimer::open(Database::memory().unwrap(), DEFAULT_SPLIT).unwrap()
+    }
+
+    #[test]
+    fn undo_and_redo_every_action() {
+        let mut local = open();
+        let pomodoro = local
+            .db()
+            .get()
+            .unwrap()
+            .splits()
+            .unwrap()
+            .into_iter()
+            .find(|split| split.name == "Pomodoro")
+            .unwrap();
+
+        let mut states = vec![state(&local)];
+        local.act(RoomAction::Start, at(0)).unwrap();
+        states.push(state(&local));
+        local.act(RoomAction::Pause, at(600)).unwrap();
+        states.push(state(&local));
+        local.act(RoomAction::Skip, at(700)).unwrap();
+        states.push(state(&local));
+        assert!(local.select_split(pomodoro).unwrap());
+        states.push(state(&local));
+        assert_eq!(states[1].rows.len(), 1);
+        assert!(states[1].active.is_some());
+        assert!(states[2].active.is_none());
+
+        let actions = [
+            Action::Start,
+            Action::Pause,
+            Action::Skip,
+            Action::SelectSplit,
+        ];
+        for (i, action) in actions.iter().enumerate().rev() {
+            assert_eq!(local.undo().unwrap(), Some(*action));
+            assert_eq!(state(&local), states[i], "undoing {:?}", action);
+        }
+        assert_eq!(local.undo().unwrap(), None);
+
+        for (i, action) in actions.iter().enumerate() {
+            assert_eq!(local.redo().unwrap(), Some(*action));
+            assert_eq!(state(&local), states[i + 1], "redoing {:?}", action);
+        }
+        assert_eq!(local.redo().unwrap(), None);
+    }
+
+    #[test]
+    fn forgets_past_the_limit() {
+        let mut local = open();
+        let mut states = vec![state(&local)];
+        for i in 0..=UNDO_LIMIT as i64 {
+            let action = if i % 2 == 0 {
+                RoomAction::Start
+            } else {
+                RoomAction::Pause
+            };
+            local.act(action, at(i * 10)).unwrap();
+            states.push(state(&local));
+        }
+
+        for _ in 0..UNDO_LIMIT {
+            assert!(local.undo().unwrap().is_some());
+        }
+        assert_eq!(local.undo().unwrap(), None);
+        // The first action fell off the end
+        assert_eq!(state(&local), states[1]);
+    }
+
+    #[test]
+    fn new_action_clears_redo() {
+        let mut local = open();
+        local.act(RoomAction::Start, at(0)).unwrap();
+        local.act(RoomAction::Pause, at(600)).unwrap();
+        assert_eq!(local.undo().unwrap(), Some(Action::Pause));
+        assert_eq!(local.next_redo(), Some(Action::Pause));
+
+        local.act(RoomAction::Pause, at(900)).unwrap();
+        let paused = state(&local);
+        assert_eq!(local.next_redo(), None);
+        assert_eq!(local.redo().unwrap(), None);
+        assert_eq!(state(&local), paused);
+        assert_eq!(paused.rows[0].end_time.0, at(900));
+    }
+}
@@ -0,0 +1,374 @@
+//! The timer on the session bus, for desktop shells and
+//! scripts that would rather not speak JSON over a socket.
+//!
+//! Properties come from the last state the daemon sent
+//! out, so reading them never waits on the database.
+//! `Remaining` is the one that changes on its own, so it
+//! doesn't signal changes, watch `EndsAt` instead
+use jiff::Timestamp;
+use lockinspiel_common::{
+    daemon::{DaemonState, Event, Method},
+    timer::TimerState,
+};
+use tokio::sync::broadcast::error::RecvError;
+use zbus::{fdo, interface, object_server::SignalEmitter};
+
+use lockinspiel_common::shared::SharedTimer;
+
+pub const NAME: &str = "live.Lockinspiel.Timer";
+pub const PATH: &str = "/live/Lockinspiel/Timer";
+
+struct TimerInterface {
+    timer: SharedTimer,
+    state: DaemonState,
+}
+
+impl TimerInterface {
+    async fn call(&self, method: Method) -> fdo::Result<()> {
+        self.timer
+            .call(method, Timestamp::now())
+            .await
+            .map(|_| ())
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+}
+
+#[interface(name = "live.Lockinspiel.Timer1")]
+impl TimerInterface {
+    /// Starts the timer, or does nothing if it's going
+    async fn start(&self) -> fdo::Result<()> {
+        self.call(Method::Start { split: None }).await
+    }
+
+    async fn pause(&self) -> fdo::Result<()> {
+        self.call(Method::Pause).await
+    }
+
+    /// Fails while the timer is going
+    async fn skip(&self) -> fdo::Result<()> {
+        self.call(Method::Skip).await
+    }
+
+    /// Starts the split from the top. Fails while
+    /// the timer is going
+    async fn select_split(&self, split: String) -> fdo::Result<()> {
+        self.call(Method::SelectSplit { split }).await
+    }
+
+    #[zbus(property)]
+    fn running(&self) -> bool {
+        matches!(self.state.timer.state, TimerState::Going(_))
+    }
+
+    /// Name of the timer in the split, like Work or Break
+    #[zbus(property)]
+    fn phase(&self) -> String {
+        self.state.timer.current().name.clone()
+    }
+
+    #[zbus(property)]
+    fn work(&self) -> bool {
+        self.state.timer.current().work
+    }
+
+    #[zbus(property)]
+    fn split(&self) -> String {
+        self.state.split.clone()
+    }
+
+    #[zbus(property)]
+    fn tags(&self) -> Vec<String> {
+        self.state.tags.clone()
+    }
+
+    /// Whole seconds left
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn remaining(&self) -> i64 {
+        self.state.timer.remaining(Timestamp::now()).as_secs()
+    }
+
+    /// When the timer runs out in microseconds since the
+    /// Unix epoch, or 0 while paused
+    #[zbus(property)]
+    fn ends_at(&self) -> i64 {
+        match self.state.timer.state {
+            TimerState::Going(end_time) => end_time.as_microsecond(),
+            TimerState::Paused(_) => 0,
+        }
+    }
+
+    /// The timer was started, paused, skipped or switched
+    #[zbus(signal)]
+    async fn changed(emitter: &SignalEmitter<'_>, running: bool, phase: &str) -> zbus::Result<()>;
+
+    /// `ended` ran out and `next` is up, already
+    /// going if `started`
+    #[zbus(signal)]
+    async fn expired(
+        emitter: &SignalEmitter<'_>,
+        ended: &str,
+        next: &str,
+        started: bool,
+    ) -> zbus::Result<()>;
+}
+
+/// Serves the timer on the session bus, or on `address`
+/// when given, like a private bus for trying it out.
+/// Runs until the daemon's events stop
+pub async fn serve(timer: SharedTimer, address: Option<&str>) -> zbus::Result<()> {
+    let mut events = timer.subscribe();
+    let state = timer
+        .state()
+        .await
+        .map_err(|e| zbus::Error::Failure(e.to_string()))?;
+
+    let builder = match address {
+        Some(address) => zbus::connection::Builder::address(address)?,
+        None => zbus::connection::Builder::session()?,
+    };
+    let connection = builder
+        .name(NAME)?
+        .serve_at(
+            PATH,
+            TimerInterface {
+                timer: timer.clone(),
+                state,
+            },
+        )?
+        .build()
+        .await?;
+    tracing::info!(name = NAME, "Serving on D-Bus");
+
+    let iface = connection
+        .object_server()
+        .interface::<_, TimerInterface>(PATH)
+        .await?;
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            // Missed some, so catch up on how things are now
+            // rather than leave the properties behind
+            Err(RecvError::Lagged(missed)) => match timer.state().await {
+                Ok(state) => {
+                    tracing::warn!(missed, "Fell behind on D-Bus, catching up");
+                    Event::Changed(state)
+                }
+                Err(e) => {
+                    tracing::error!(?e, "Failed to catch up on D-Bus");
+                    continue;
+                }
+            },
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        let state = event.state().clone();
+        let emitter = iface.signal_emitter();
+        let old = std::mem::replace(&mut iface.get_mut().await.state, state);
+
+        let current = iface.get().await;
+        if current.running() != matches!(old.timer.state, TimerState::Going(_)) {
+            current.running_changed(emitter).await?;
+        }
+        if current.state.timer.current() != old.timer.current() {
+            current.phase_changed(emitter).await?;
+            current.work_changed(emitter).await?;
+        }
+        if current.state.split != old.split {
+            current.split_changed(emitter).await?;
+        }
+        if current.state.tags != old.tags {
+            current.tags_changed(emitter).await?;
+        }
+        if current.state.timer.state != old.timer.state {
+            current.ends_at_changed(emitter).await?;
+        }
+
+        match &event {
+            Event::Changed(_) => {
+                TimerInterface::changed(emitter, current.running(), &current.phase()).await?
+            }
+            Event::Expired { ended, .. } => {
+                TimerInterface::expired(emitter, ended, &current.phase(), current.running()).await?
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        time::Duration,
+    };
+
+    use futures_util::StreamExt;
+    use jiff::SignedDuration;
+    use lockinspiel_common::{
+        db::Database,
+        idle::IdlePolicy,
+        local::{DEFAULT_SPLIT, LocalTimer},
+    };
+    use zbus::{fdo::DBusProxy, proxy::CacheProperties};
+
+    use super::*;
+
+    #[zbus::proxy(
+        interface = "live.Lockinspiel.Timer1",
+        default_service = "live.Lockinspiel.Timer",
+        default_path = "/live/Lockinspiel/Timer"
+    )]
+    trait Timer {
+        fn start(&self) -> zbus::Result<()>;
+        fn pause(&self) -> zbus::Result<()>;
+        fn skip(&self) -> zbus::Result<()>;
+        fn select_split(&self, split: &str) -> zbus::Result<()>;
+
+        #[zbus(property)]
+        fn running(&self) -> zbus::Result<bool>;
+        #[zbus(property)]
+        fn phase(&self) -> zbus::Result<String>;
+        #[zbus(property)]
+        fn work(&self) -> zbus::Result<bool>;
+        #[zbus(property)]
+        fn split(&self) -> zbus::Result<String>;
+        #[zbus(property)]
+        fn tags(&self) -> zbus::Result<Vec<String>>;
+        #[zbus(property)]
+        fn remaining(&self) -> zbus::Result<i64>;
+        #[zbus(property)]
+        fn ends_at(&self) -> zbus::Result<i64>;
+
+        #[zbus(signal)]
+        fn changed(&self, running: bool, phase: &str) -> zbus::Result<()>;
+        #[zbus(signal)]
+        fn expired(&self, ended: &str, next: &str, started: bool) -> zbus::Result<()>;
+    }
+
+    /// A bus of its own, so tests don't clash with
+    /// a daemon that's really running
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl PrivateBus {
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("dbus-daemon should be installed");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Self {
+                daemon,
+                address: address.trim().to_owned(),
+            }
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    /// Serves a fresh timer on `bus`, returning it once
+    /// it's taken its name
+    async fn serve_on(bus: &PrivateBus) -> (SharedTimer, zbus::Connection) {
+        let local = LocalTimer::open(Database::memory().unwrap(), DEFAULT_SPLIT).unwrap();
+        let timer = SharedTimer::new(local, false, IdlePolicy::Ignore);
+        let address = bus.address.clone();
+        tokio::spawn({
+            let timer = timer.clone();
+            async move { serve(timer, Some(&address)).await.unwrap() }
+        });
+
+        let connection = zbus::connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let dbus = DBusProxy::new(&connection).await.unwrap();
+        for _ in 0..100 {
+            if dbus.name_has_owner(NAME.try_into().unwrap()).await.unwrap() {
+                return (timer, connection);
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("The timer never showed up on the bus");
+    }
+
+    /// Reads properties fresh every time, so
+    /// they're checked against what's served
+    async fn proxy(connection: &zbus::Connection) -> TimerProxy<'_> {
+        TimerProxy::builder(connection)
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn properties() {
+        let bus = PrivateBus::start();
+        let (_timer, connection) = serve_on(&bus).await;
+        let proxy = proxy(&connection).await;
+
+        assert_eq!(proxy.split().await.unwrap(), DEFAULT_SPLIT);
+        assert_eq!(proxy.phase().await.unwrap(), "Work");
+        assert!(proxy.work().await.unwrap());
+        assert!(!proxy.running().await.unwrap());
+        assert_eq!(proxy.tags().await.unwrap(), Vec::<String>::new());
+        assert_eq!(proxy.remaining().await.unwrap(), 90 * 60);
+        assert_eq!(proxy.ends_at().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn methods_and_signals() {
+        let bus = PrivateBus::start();
+        let (timer, connection) = serve_on(&bus).await;
+        let proxy = proxy(&connection).await;
+        let mut changed = proxy.receive_changed().await.unwrap();
+        let mut expired = proxy.receive_expired().await.unwrap();
+
+        proxy.start().await.unwrap();
+        let signal = changed.next().await.unwrap();
+        let args = signal.args().unwrap();
+        assert!(args.running);
+        assert_eq!(args.phase, "Work");
+        assert!(proxy.running().await.unwrap());
+        assert!(proxy.ends_at().await.unwrap() > Timestamp::now().as_microsecond());
+
+        assert!(proxy.skip().await.is_err(), "can't skip while going");
+
+        timer
+            .expire(Timestamp::now() + SignedDuration::from_hours(2))
+            .await
+            .unwrap();
+        let signal = expired.next().await.unwrap();
+        let args = signal.args().unwrap();
+        assert_eq!(args.ended, "Work");
+        assert_eq!(args.next, "Break");
+        assert!(!args.started);
+        assert_eq!(proxy.phase().await.unwrap(), "Break");
+        assert!(!proxy.work().await.unwrap());
+        assert!(!proxy.running().await.unwrap());
+
+        proxy.select_split("Pomodoro").await.unwrap();
+        let signal = changed.next().await.unwrap();
+        assert!(!signal.args().unwrap().running);
+        assert_eq!(proxy.split().await.unwrap(), "Pomodoro");
+        assert_eq!(proxy.remaining().await.unwrap(), 25 * 60);
+
+        proxy.start().await.unwrap();
+        changed.next().await.unwrap();
+        proxy.pause().await.unwrap();
+        let signal = changed.next().await.unwrap();
+        assert!(!signal.args().unwrap().running);
+        assert_eq!(proxy.ends_at().await.unwrap(), 0);
+    }
+}
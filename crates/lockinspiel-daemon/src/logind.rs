@@ -0,0 +1,137 @@
+//! Noticing the computer going to sleep or sitting idle,
+//! from logind on the system bus. Sleep is held off with
+//! a delay lock until the timer's been told, so a pause
+//! lands before the clock stops
+use futures_util::StreamExt;
+use jiff::Timestamp;
+use zbus::{
+    proxy,
+    zvariant::{OwnedFd, OwnedObjectPath},
+};
+
+use lockinspiel_common::shared::SharedTimer;
+
+#[proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait Manager {
+    fn get_session(&self, session_id: &str) -> zbus::Result<OwnedObjectPath>;
+
+    fn inhibit(&self, what: &str, who: &str, why: &str, mode: &str) -> zbus::Result<OwnedFd>;
+
+    /// Sent with `start` before sleeping and without after
+    #[zbus(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1"
+)]
+trait Session {
+    /// Set by the desktop once nobody's touched anything
+    /// for however long it's set up to wait
+    #[zbus(property)]
+    fn idle_hint(&self) -> zbus::Result<bool>;
+
+    /// When the session went idle, in microseconds
+    /// since the Unix epoch
+    #[zbus(property)]
+    fn idle_since_hint(&self) -> zbus::Result<u64>;
+}
+
+/// Tells `timer` whenever the computer's left and come
+/// back to. Runs until logind goes away
+pub async fn watch(timer: SharedTimer) -> zbus::Result<()> {
+    let connection = zbus::Connection::system().await?;
+    let manager = ManagerProxy::new(&connection).await?;
+    // The session the daemon was started in, or the user's
+    // graphical one when it was started outside any
+    let path = manager.get_session("auto").await?;
+    let session = SessionProxy::builder(&connection)
+        .path(path)?
+        .build()
+        .await?;
+
+    let mut sleeps = manager.receive_prepare_for_sleep().await?;
+    let mut idles = session.receive_idle_hint_changed().await;
+    let mut lock = inhibit(&manager).await;
+    let mut sleeping = false;
+    let mut idle = session.idle_hint().await?;
+    tracing::info!("Watching for sleep and idle");
+
+    loop {
+        let was_away = sleeping || idle;
+        // One bad message shouldn't stop the watching
+        tokio::select! {
+            Some(signal) = sleeps.next() => match signal.args() {
+                Ok(args) => sleeping = args.start,
+                Err(e) => {
+                    tracing::warn!(?e, "Failed to read sleep signal");
+                    continue;
+                }
+            },
+            Some(change) = idles.next() => match change.get().await {
+                Ok(hint) => idle = hint,
+                Err(e) => {
+                    tracing::warn!(?e, "Failed to read idle hint");
+                    continue;
+                }
+            },
+            else => return Ok(()),
+        }
+
+        let now = Timestamp::now();
+        let result = match (was_away, sleeping || idle) {
+            (false, true) if sleeping => timer.left(now).await,
+            (false, true) => {
+                let since = match session.idle_since_hint().await {
+                    Ok(since) => since,
+                    Err(e) => {
+                        tracing::warn!(?e, "Failed to read when the session went idle");
+                        0
+                    }
+                };
+                // 0 means it isn't set, not 1970
+                let since = i64::try_from(since)
+                    .ok()
+                    .filter(|since| *since > 0)
+                    .and_then(|since| Timestamp::from_microsecond(since).ok())
+                    .unwrap_or(now);
+                timer.left(since).await
+            }
+            (true, false) => timer.back(now).await,
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            tracing::error!(?e, "Failed to tell the timer about time away");
+        }
+
+        // Sleep goes ahead once the lock's let go,
+        // and the next one needs a new lock
+        if sleeping {
+            drop(lock.take());
+        } else if lock.is_none() {
+            lock = inhibit(&manager).await;
+        }
+    }
+}
+
+/// Holds off sleep until it's dropped
+async fn inhibit(manager: &ManagerProxy<'_>) -> Option<OwnedFd> {
+    match manager
+        .inhibit("sleep", "Lockinspiel", "Pausing the timer", "delay")
+        .await
+    {
+        Ok(lock) => Some(lock),
+        Err(e) => {
+            tracing::warn!(
+                ?e,
+                "Failed to hold off sleep, so the timer may not pause in time"
+            );
+            None
+        }
+    }
+}
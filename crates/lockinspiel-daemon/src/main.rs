@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use clap_verbosity_flag::InfoLevel;
+use color_eyre::eyre;
+use lockinspiel_common::idle::IdlePolicy;
+
+#[cfg(target_os = "linux")]
+mod dbus;
+#[cfg(target_os = "linux")]
+mod logind;
+#[cfg(unix)]
+mod server;
+#[cfg(not(unix))]
+mod server {
+    use color_eyre::eyre;
+
+    // The app runs the timer itself there instead
+    pub async fn run(_cli: crate::Cli) -> eyre::Result<()> {
+        eyre::bail!("lockinspield needs Unix sockets, which this platform doesn't have")
+    }
+}
+
+#[derive(Parser)]
+// Only the socket server reads the options
+#[cfg_attr(not(unix), allow(dead_code))]
+#[command(version, about, long_about = None)]
+#[command(propagate_version = true)]
+struct Cli {
+    #[command(flatten)]
+    verbose: clap_verbosity_flag::Verbosity<InfoLevel>,
+    /// Start the next timer as soon as one runs out.
+    /// Frontends can change this while running
+    #[arg(long, env = "LOCKINSPIEL_AUTO_START")]
+    auto_start: bool,
+    /// What to do while the computer sleeps or sits idle
+    /// with the timer going: ignore, pause, ask or record.
+    /// Frontends can change this while running
+    #[arg(long, env = "LOCKINSPIEL_ON_IDLE", default_value_t)]
+    on_idle: IdlePolicy,
+    /// DuckDB file to keep the timesheet in, instead of
+    /// the one in the data directory
+    #[arg(long, env = "LOCKINSPIEL_DATABASE")]
+    database: Option<PathBuf>,
+    /// Don't put the timer on the session bus
+    #[arg(long)]
+    no_dbus: bool,
+    /// Bus to use instead of the session bus, like
+    /// one started with `dbus-daemon --session --print-address`
+    #[arg(long, env = "LOCKINSPIEL_DBUS_ADDRESS")]
+    dbus_address: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    let cli = Cli::parse();
+
+    lockinspiel_common::install_init_boilerplate(Some(cli.verbose.tracing_level_filter()))?;
+
+    server::run(cli).await
+}
@@ -0,0 +1,256 @@
+use std::{
+    os::unix::fs::{DirBuilderExt, PermissionsExt},
+    path::Path,
+};
+
+use color_eyre::eyre::{self, Context, bail};
+use jiff::Timestamp;
+use lockinspiel_common::{
+    daemon::{self, Event, Method, Request, Response, codes},
+    db::Database,
+    local::{DEFAULT_SPLIT, LocalTimer},
+    shared::{self, SharedTimer},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream, unix::OwnedWriteHalf},
+    signal,
+    sync::broadcast::error::RecvError,
+};
+use tracing::instrument;
+
+use crate::Cli;
+#[cfg(target_os = "linux")]
+use crate::{dbus, logind};
+
+pub async fn run(cli: Cli) -> eyre::Result<()> {
+    let path = daemon::socket_path()?;
+    if UnixStream::connect(&path).await.is_ok() {
+        bail!("lockinspield is already running on {}", path.display());
+    }
+    // Left behind by a daemon that didn't get to clean up
+    if path.exists() {
+        std::fs::remove_file(&path).wrap_err("Failed to remove stale socket")?;
+    }
+
+    let db = match &cli.database {
+        Some(path) => Database::new(path),
+        None => Database::default(),
+    }
+    .wrap_err("Failed to open the database")?;
+    let mut local = LocalTimer::open(db, DEFAULT_SPLIT)?;
+    // A timer that ran out while the daemon was down is
+    // completed, but nobody was there to start the next
+    local.expire(Timestamp::now(), false)?;
+    let timer = SharedTimer::new(local, cli.auto_start, cli.on_idle);
+
+    let listener = listen(&path)?;
+    tracing::info!(path = %path.display(), "Listening");
+
+    tokio::spawn(shared::tick(timer.clone()));
+    #[cfg(target_os = "linux")]
+    if !cli.no_dbus {
+        let timer = timer.clone();
+        tokio::spawn(async move {
+            // The socket still works without it
+            if let Err(e) = dbus::serve(timer, cli.dbus_address.as_deref()).await {
+                tracing::error!(?e, "Failed to serve on D-Bus");
+            }
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let timer = timer.clone();
+        tokio::spawn(async move {
+            // Without logind time away just isn't noticed
+            if let Err(e) = logind::watch(timer).await {
+                tracing::warn!(?e, "Failed to watch for sleep and idle");
+            }
+        });
+    }
+
+    let accept = async {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve(timer.clone(), stream));
+                }
+                Err(e) => tracing::error!(?e, "Failed to accept connection"),
+            }
+        }
+    };
+    tokio::select! {
+        _ = accept => {},
+        _ = shutdown_signal() => {},
+    }
+
+    remove_socket(&path);
+    Ok(())
+}
+
+/// Binds the socket in a directory only this user can get
+/// into and then moves it into place, so it's never
+/// reachable before it's been locked down
+fn listen(path: &Path) -> eyre::Result<UnixListener> {
+    let dir = path.with_file_name(format!(".lockinspield-{}", std::process::id()));
+    // Left behind by an earlier daemon with the same pid
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+    let listener = bind_private(&dir.join("socket"), path);
+    if let Err(e) = std::fs::remove_dir_all(&dir) {
+        tracing::warn!(?e, "Failed to remove socket directory");
+    }
+    listener.wrap_err_with(|| format!("Failed to listen on {}", path.display()))
+}
+
+fn bind_private(private: &Path, path: &Path) -> std::io::Result<UnixListener> {
+    let listener = UnixListener::bind(private)?;
+    // Only this user gets to run the timer
+    std::fs::set_permissions(private, std::fs::Permissions::from_mode(0o600))?;
+    std::fs::rename(private, path)?;
+    Ok(listener)
+}
+
+#[instrument(skip_all)]
+async fn serve(timer: SharedTimer, stream: UnixStream) {
+    // Dropped whichever way this returns, which lets go
+    // of any room this connection followed
+    let connection = timer.connect();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => return,
+            Err(e) => {
+                tracing::debug!(?e, "Failed to read from connection");
+                return;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request = match serde_json::from_str::<serde_json::Value>(&line) {
+            Ok(value) => serde_json::from_value::<Request>(value).map_err(|e| {
+                Response::error(
+                    None,
+                    codes::INVALID_REQUEST,
+                    format!("Invalid request: {}", e),
+                )
+            }),
+            Err(e) => Err(Response::error(
+                None,
+                codes::PARSE_ERROR,
+                format!("Parse error: {}", e),
+            )),
+        };
+        let request = match request {
+            Ok(request) => request,
+            Err(response) => {
+                if send(&mut writer, &response).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+
+        if let Method::Subscribe = request.method {
+            subscribe(connection.timer(), request.id, writer).await;
+            return;
+        }
+
+        tracing::debug!(method = ?request.method, "Call");
+        let response = match connection.call(request.method, Timestamp::now()).await {
+            Ok(result) => Response::ok(request.id, &result).unwrap(),
+            Err(e) => {
+                if e.code() == codes::INTERNAL_ERROR {
+                    tracing::error!(?e, "Call failed");
+                }
+                Response::error(request.id, e.code(), e.to_string())
+            }
+        };
+        // Requests without an id are notifications
+        if request.id.is_some() && send(&mut writer, &response).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Sends the state, then every event until the
+/// subscriber goes away
+async fn subscribe(timer: &SharedTimer, id: Option<u64>, mut writer: OwnedWriteHalf) {
+    let mut events = timer.subscribe();
+    let response = match timer.state().await {
+        Ok(state) => Response::ok(id, &state).unwrap(),
+        Err(e) => {
+            tracing::error!(?e, "Failed to read state for subscriber");
+            Response::error(id, codes::INTERNAL_ERROR, e.to_string())
+        }
+    };
+    if send(&mut writer, &response).await.is_err() || response.error.is_some() {
+        return;
+    }
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            // Too slow to keep up, so skip to the latest
+            Err(RecvError::Lagged(_)) => match timer.state().await {
+                Ok(state) => Event::Changed(state),
+                Err(e) => {
+                    tracing::error!(?e, "Failed to read state for subscriber");
+                    return;
+                }
+            },
+            Err(RecvError::Closed) => return,
+        };
+        let Ok(line) = event.to_line() else {
+            continue;
+        };
+        if write_line(&mut writer, line).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn send(writer: &mut OwnedWriteHalf, response: &Response) -> std::io::Result<()> {
+    write_line(writer, serde_json::to_string(response)?).await
+}
+
+async fn write_line(writer: &mut OwnedWriteHalf, mut line: String) -> std::io::Result<()> {
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
+
+fn remove_socket(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        tracing::warn!(?e, "Failed to remove socket");
+    }
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("Shutting down");
+}
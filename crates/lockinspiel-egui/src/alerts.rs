@@ -0,0 +1,336 @@
+//! Tells the user when a timer runs out, even
+//! when the window isn't visible
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// What happens when a timer in a split runs out
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SplitAlerts {
+    pub notify: bool,
+    pub sound: bool,
+}
+
+impl Default for SplitAlerts {
+    fn default() -> Self {
+        Self {
+            notify: true,
+            sound: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertSettings {
+    /// Silences everything, whatever the split says
+    pub do_not_disturb: bool,
+    /// Played instead of the built-in chime. Empty for the chime
+    pub sound_file: String,
+    /// Keyed by split name. Splits that aren't
+    /// in here get `SplitAlerts::default()`
+    pub splits: HashMap<String, SplitAlerts>,
+}
+
+/// A timer that ran out, and the one that came after it
+pub struct PhaseChange<'a> {
+    pub split: &'a str,
+    pub ended: &'a str,
+    pub next: &'a str,
+    /// Whether `next` was started automatically
+    pub next_started: bool,
+}
+
+impl AlertSettings {
+    pub fn split(&self, split: &str) -> SplitAlerts {
+        self.splits.get(split).copied().unwrap_or_default()
+    }
+
+    pub fn split_mut(&mut self, split: &str) -> &mut SplitAlerts {
+        self.splits.entry(split.to_owned()).or_default()
+    }
+}
+
+#[derive(Default)]
+pub struct Alerts {
+    /// Opened the first time a sound plays. Sounds stop
+    /// when it's dropped, so it's kept around
+    #[cfg(not(target_arch = "wasm32"))]
+    audio: Option<(rodio::OutputStream, rodio::OutputStreamHandle)>,
+    #[cfg(not(target_arch = "wasm32"))]
+    notifier: Notifier,
+}
+
+#[cfg(target_os = "linux")]
+#[zbus::proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, zbus::zvariant::Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+/// Shows desktop notifications, on the session bus
+/// unless it's given another
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Debug, Default)]
+pub struct Notifier {
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    address: Option<String>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Notifier {
+    /// Sends notifications to the bus at `address`
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn at(address: impl Into<String>) -> Self {
+        Self {
+            address: Some(address.into()),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn show(&self, summary: &str, body: &str) -> color_eyre::eyre::Result<()> {
+        let connection = match &self.address {
+            Some(address) => {
+                zbus::connection::Builder::address(address.as_str())?
+                    .build()
+                    .await?
+            }
+            None => zbus::Connection::session().await?,
+        };
+        NotificationsProxy::new(&connection)
+            .await?
+            .notify("Lockinspiel", 0, "", summary, body, &[], HashMap::new(), -1)
+            .await?;
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub async fn show(&self, summary: &str, body: &str) -> color_eyre::eyre::Result<()> {
+        let (summary, body) = (summary.to_owned(), body.to_owned());
+        // Waits on the notification server, so off the runtime
+        tokio::task::spawn_blocking(move || {
+            notify_rust::Notification::new()
+                .appname("Lockinspiel")
+                .summary(&summary)
+                .body(&body)
+                .show()
+                .map(drop)
+        })
+        .await??;
+        Ok(())
+    }
+}
+
+impl Alerts {
+    /// Notifies and plays a sound, as far as the
+    /// split's settings and do not disturb allow
+    pub fn phase_changed(
+        &mut self,
+        settings: &AlertSettings,
+        change: &PhaseChange<'_>,
+        runtime: &tokio::runtime::Runtime,
+    ) {
+        if settings.do_not_disturb {
+            tracing::debug!("Not alerting, do not disturb is on");
+            return;
+        }
+
+        let alerts = settings.split(change.split);
+        if alerts.notify {
+            self.notify(change, runtime);
+        }
+        if alerts.sound {
+            self.play_sound(&settings.sound_file);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn notify(&self, change: &PhaseChange<'_>, runtime: &tokio::runtime::Runtime) {
+        let summary = format!("{} is over", change.ended);
+        let body = if change.next_started {
+            format!("{} has started", change.next)
+        } else {
+            format!("Start {} when you're ready", change.next)
+        };
+
+        // Showing a notification waits on the notification
+        // server, which shouldn't hold up a frame
+        let notifier = self.notifier.clone();
+        runtime.spawn(async move {
+            if let Err(e) = notifier.show(&summary, &body).await {
+                tracing::error!(?e, "Failed to show notification");
+            }
+        });
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn notify(&self, _change: &PhaseChange<'_>, _runtime: &tokio::runtime::Runtime) {}
+
+    /// Plays `sound_file`, or the chime if it's empty
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn play_sound(&mut self, sound_file: &str) {
+        if let Err(e) = self.try_play_sound(sound_file) {
+            tracing::error!(?e, "Failed to play sound");
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn try_play_sound(&mut self, sound_file: &str) -> color_eyre::eyre::Result<()> {
+        use std::{fs::File, io::BufReader, time::Duration};
+
+        use color_eyre::eyre::Context;
+        use rodio::Source;
+
+        let (_, handle) = match &mut self.audio {
+            Some(audio) => audio,
+            audio => audio.insert(
+                rodio::OutputStream::try_default().wrap_err("Failed to open audio output")?,
+            ),
+        };
+
+        if sound_file.is_empty() {
+            let chime = rodio::source::SineWave::new(880.0)
+                .take_duration(Duration::from_millis(300))
+                .amplify(0.25);
+            handle.play_raw(chime).wrap_err("Failed to play chime")?;
+        } else {
+            let file = File::open(sound_file)
+                .wrap_err_with(|| format!("Failed to open {}", sound_file))?;
+            handle
+                .play_once(BufReader::new(file))
+                .wrap_err("Failed to play sound file")?
+                .detach();
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn play_sound(&mut self, _sound_file: &str) {}
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        sync::{Arc, Mutex},
+    };
+
+    use zbus::zvariant::OwnedValue;
+
+    use super::*;
+
+    /// A bus of its own, so tests don't pop up
+    /// notifications on the desktop
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl PrivateBus {
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("dbus-daemon should be installed");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Self {
+                daemon,
+                address: address.trim().to_owned(),
+            }
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    /// Stands in for the desktop's notification server
+    #[derive(Clone, Default)]
+    struct Server {
+        shown: Arc<Mutex<Vec<(String, String, String)>>>,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.Notifications")]
+    impl Server {
+        #[allow(clippy::too_many_arguments)]
+        fn notify(
+            &self,
+            app_name: String,
+            _replaces_id: u32,
+            _app_icon: String,
+            summary: String,
+            body: String,
+            _actions: Vec<String>,
+            _hints: HashMap<String, OwnedValue>,
+            _expire_timeout: i32,
+        ) -> u32 {
+            let mut shown = self.shown.lock().unwrap();
+            shown.push((app_name, summary, body));
+            shown.len() as u32
+        }
+    }
+
+    #[tokio::test]
+    async fn notifies_on_the_bus() {
+        let bus = PrivateBus::start();
+        let server = Server::default();
+        let _connection = zbus::connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .name("org.freedesktop.Notifications")
+            .unwrap()
+            .serve_at("/org/freedesktop/Notifications", server.clone())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        Notifier::at(&bus.address)
+            .show("Work is over", "Break has started")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *server.shown.lock().unwrap(),
+            [(
+                "Lockinspiel".to_owned(),
+                "Work is over".to_owned(),
+                "Break has started".to_owned()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_without_a_server() {
+        let bus = PrivateBus::start();
+        assert!(
+            Notifier::at(&bus.address)
+                .show("Work is over", "Break has started")
+                .await
+                .is_err()
+        );
+    }
+}
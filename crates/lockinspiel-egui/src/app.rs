@@ -1,4 +1,7 @@
-use std::time::Duration;
+use std::{
+    sync::mpsc,
+    time::{Duration, Instant},
+};
 
 use egui::{Align2, FontId, RichText, Vec2};
 use egui_taffy::{
@@ -8,53 +11,731 @@ use egui_taffy::{
 };
 use lockinspiel_common::{
     client::LockinspielClient,
-    db::{Database, JiffTimestamp},
+    daemon::{DaemonClient, DaemonError, DaemonState, Event, Method},
+    idle::IdlePolicy,
+    room::{RoomAction, RoomEvent},
+    sync::SyncedSplit,
+    timer::TimerState,
+};
+
+use crate::{
+    alerts::{Alerts, PhaseChange},
+    mini::{self, MiniAction},
+    settings::Settings,
+    shortcuts::{self, GlobalShortcuts, Shortcut},
+    timesheet::{self, Timesheet, TimesheetAction},
+    tray::{TrayAction, TrayIcon, TrayStatus},
 };
 
-enum TimerState {
-    Paused(jiff::SignedDuration),
-    Going(jiff::Timestamp),
+/// How long the undo toast stays up
+const UNDO_TOAST: Duration = Duration::from_secs(5);
+
+/// Offered for a moment after the timer was started,
+/// paused or skipped, in case it was by accident
+struct UndoToast {
+    text: &'static str,
+    until: Instant,
+}
+
+/// A room on the tinker terminal this app is following
+struct JoinedRoom {
+    code: String,
+    members: usize,
+    events: mpsc::Receiver<RoomEvent>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct SignInDialog {
+    open: bool,
+    email: String,
+    password: String,
+    error: Option<String>,
+}
+
+#[derive(Default)]
+struct RoomDialog {
+    open: bool,
+    code: String,
+    error: Option<String>,
 }
 
 pub struct LockinspielApp {
-    timer_state: TimerState,
+    daemon: DaemonClient,
+    /// The timer as of the daemon's last word on it
+    state: DaemonState,
+    /// Changes made by the daemon or other frontends
+    daemon_events: mpsc::Receiver<Event>,
+    /// Every split in the database
+    splits: Vec<SyncedSplit>,
     client: LockinspielClient,
     runtime: tokio::runtime::Runtime,
-    timers: Vec<jiff::SignedDuration>,
-    timer_on: usize,
-    group: Option<i64>,
-    db: Database,
+    room: Option<JoinedRoom>,
+    room_dialog: RoomDialog,
+    sign_in_dialog: SignInDialog,
+    alerts: Alerts,
+    settings: Settings,
+    settings_open: bool,
+    timesheet: Timesheet,
+    undo_toast: Option<UndoToast>,
+    global_shortcuts: Option<GlobalShortcuts>,
+    tray: Option<TrayIcon>,
+    /// Set when quitting from the tray, so closing
+    /// the window isn't turned into minimizing it
+    quitting: bool,
 }
 
 impl LockinspielApp {
-    fn default() -> Self {
-        let db = Database::default().unwrap();
+    fn load(ctx: &egui::Context, settings: Settings) -> Self {
         let mut client = LockinspielClient::default();
+        client.set_clock_sync(settings.clock_sync);
         let runtime = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
             .unwrap();
-        let now = runtime.block_on(client.now());
-        let timers = vec![
-            jiff::SignedDuration::from_mins(90),
-            jiff::SignedDuration::from_mins(10),
-        ];
+        let mut daemon = runtime.block_on(DaemonClient::connect_or_spawn()).unwrap();
+        let splits = runtime.block_on(daemon.splits()).unwrap();
+        runtime
+            .block_on(daemon.set_auto_start(settings.auto_start))
+            .unwrap();
+        runtime
+            .block_on(daemon.set_idle_policy(settings.idle))
+            .unwrap();
+        let (state, daemon_events) = subscribe(ctx, &runtime).unwrap();
         Self {
-            timer_state: db
-                .get()
-                .unwrap()
-                .get_active_timer(now)
-                .unwrap()
-                .map(|span| TimerState::Going(span.end_time.0))
-                .unwrap_or_else(|| TimerState::Paused(timers[0])),
+            daemon,
+            state,
+            daemon_events,
+            splits,
             client,
             runtime,
-            timers,
-            timer_on: 0,
-            group: None,
-            db,
+            room: None,
+            room_dialog: RoomDialog::default(),
+            sign_in_dialog: SignInDialog::default(),
+            alerts: Alerts::default(),
+            settings,
+            settings_open: false,
+            timesheet: Timesheet::default(),
+            undo_toast: None,
+            global_shortcuts: None,
+            tray: None,
+            quitting: false,
+        }
+    }
+
+    /// Switches to another split, starting from its
+    /// first timer. Only possible while paused
+    fn select_split(&mut self, split: SyncedSplit) {
+        let result = self.runtime.block_on(self.daemon.select_split(split.name));
+        self.update_state(result);
+    }
+
+    /// Takes the state the daemon answered with
+    fn update_state(&mut self, result: Result<DaemonState, DaemonError>) {
+        match result {
+            Ok(state) => self.state = state,
+            Err(e) => tracing::error!(?e, "Call to daemon failed"),
+        }
+    }
+
+    fn shortcut(&mut self, shortcut: Shortcut) {
+        tracing::debug!(?shortcut, "Shortcut pressed");
+        match (shortcut, self.state.timer.state) {
+            (Shortcut::StartPause, TimerState::Going(_)) => self.act(RoomAction::Pause),
+            (Shortcut::StartPause, TimerState::Paused(_)) => self.act(RoomAction::Start),
+            // Same as the button, skipping only works while paused
+            (Shortcut::Skip, TimerState::Paused(_)) => self.act(RoomAction::Skip),
+            (Shortcut::Skip, TimerState::Going(_)) => {}
+            (Shortcut::Undo, _) => self.undo(),
+            (Shortcut::Redo, _) => self.redo(),
+            (Shortcut::Split(index), _) => {
+                if self.room.is_some() {
+                    return;
+                }
+                if let Some(split) = self.splits.get(index).cloned() {
+                    self.select_split(split);
+                }
+            }
+        }
+    }
+
+    fn set_global_shortcuts(&mut self, ctx: &egui::Context, enabled: bool) {
+        self.global_shortcuts = enabled.then(|| GlobalShortcuts::bind(ctx, &self.runtime));
+    }
+
+    fn set_mini(&mut self, ctx: &egui::Context, mini: bool) {
+        tracing::debug!(mini, "Switching mini mode");
+        self.settings.mini = mini;
+        mini::apply(ctx, mini);
+    }
+
+    fn set_tray(&mut self, ctx: &egui::Context, enabled: bool) {
+        self.tray = None;
+        if enabled {
+            let now = self.runtime.block_on(self.client.now());
+            self.tray = TrayIcon::spawn(ctx, &self.runtime, self.tray_status(now));
+        }
+    }
+
+    fn tray_status(&self, now: jiff::Timestamp) -> TrayStatus {
+        TrayStatus {
+            phase: self.state.timer.current().name.clone(),
+            remaining: countdown(self.state.timer.remaining(now)),
+            going: matches!(self.state.timer.state, TimerState::Going(_)),
+        }
+    }
+
+    fn tray_action(&mut self, ctx: &egui::Context, action: TrayAction) {
+        match action {
+            TrayAction::StartPause => self.shortcut(Shortcut::StartPause),
+            TrayAction::Skip => self.shortcut(Shortcut::Skip),
+            TrayAction::Show => {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+                ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+            }
+            TrayAction::Quit => {
+                self.quitting = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+        }
+    }
+
+    /// Starts, pauses or skips the timer. When in a room
+    /// the action goes to the server, which decides what
+    /// the timer looks like for everybody.
+    fn act(&mut self, action: RoomAction) {
+        let timer = match &mut self.room {
+            Some(room) => match self
+                .runtime
+                .block_on(self.client.room_action(&room.code, action))
+            {
+                Ok(state) => {
+                    room.members = state.members;
+                    state.timer
+                }
+                Err(e) => {
+                    tracing::error!(?e, ?action, "Failed to send action to room");
+                    return;
+                }
+            },
+            None => {
+                let result = match action {
+                    RoomAction::Start => self.runtime.block_on(self.daemon.start(None)),
+                    RoomAction::Pause => self.runtime.block_on(self.daemon.pause()),
+                    RoomAction::Skip => self.runtime.block_on(self.daemon.skip()),
+                };
+                self.update_state(result);
+                if self.state.undo.is_some() {
+                    self.undo_toast = Some(UndoToast {
+                        text: match action {
+                            RoomAction::Start => "Started",
+                            RoomAction::Pause => "Paused",
+                            RoomAction::Skip => "Skipped",
+                        },
+                        until: Instant::now() + UNDO_TOAST,
+                    });
+                }
+                return;
+            }
+        };
+
+        let result = self.runtime.block_on(self.daemon.follow(timer));
+        self.update_state(result);
+    }
+
+    /// Takes back the last thing done to the timer. Rooms
+    /// can't be undone, everybody else saw it happen
+    fn undo(&mut self) {
+        self.undo_toast = None;
+        if self.room.is_some() || self.state.undo.is_none() {
+            return;
+        }
+        let result = self.runtime.block_on(self.daemon.undo());
+        self.update_state(result);
+    }
+
+    fn redo(&mut self) {
+        if self.room.is_some() || self.state.redo.is_none() {
+            return;
+        }
+        let result = self.runtime.block_on(self.daemon.redo());
+        self.update_state(result);
+    }
+
+    /// Draws the undo toast until it runs out, or
+    /// there's nothing left to undo
+    fn undo_toast(&mut self, ctx: &egui::Context) {
+        let Some(toast) = &self.undo_toast else {
+            return;
+        };
+        let left = toast.until.saturating_duration_since(Instant::now());
+        if left.is_zero() || self.state.undo.is_none() {
+            self.undo_toast = None;
+            return;
+        }
+
+        let mut undo = false;
+        egui::Area::new(egui::Id::new("undo_toast"))
+            .anchor(Align2::CENTER_BOTTOM, Vec2::new(0.0, -48.0))
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(toast.text);
+                        undo = ui.button("Undo").clicked();
+                    });
+                });
+            });
+        if undo {
+            self.undo();
+        } else {
+            ctx.request_repaint_after(left);
+        }
+    }
+
+    /// Applies what the daemon sent since the last frame,
+    /// alerting when a timer ran out. The daemon leaves
+    /// rooms alone, the server owns their timer
+    fn poll_daemon(&mut self, ctx: &egui::Context) {
+        loop {
+            match self.daemon_events.try_recv() {
+                Ok(Event::Changed(state)) => self.state = state,
+                Ok(Event::Expired { ended, state }) => {
+                    self.state = state;
+                    let timer = &self.state.timer;
+                    self.alerts.phase_changed(
+                        &self.settings.alerts,
+                        &PhaseChange {
+                            split: &self.state.split,
+                            ended: &ended,
+                            next: &timer.current().name,
+                            next_started: matches!(timer.state, TimerState::Going(_)),
+                        },
+                        &self.runtime,
+                    );
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                // The daemon went away, so pick it back up if
+                // it's been restarted. Calls fail until it is
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    let reconnected = self.runtime.block_on(DaemonClient::connect());
+                    match reconnected.and_then(|daemon| {
+                        subscribe(ctx, &self.runtime).map(|subscribed| (daemon, subscribed))
+                    }) {
+                        Ok((daemon, (state, events))) => {
+                            tracing::info!("Reconnected to daemon");
+                            self.daemon = daemon;
+                            self.state = state;
+                            self.daemon_events = events;
+                            let result = self
+                                .runtime
+                                .block_on(self.daemon.set_auto_start(self.settings.auto_start));
+                            self.update_state(result);
+                            let result = self
+                                .runtime
+                                .block_on(self.daemon.set_idle_policy(self.settings.idle));
+                            self.update_state(result);
+                        }
+                        Err(e) => tracing::debug!(?e, "Daemon is still gone"),
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Uploads the whole timesheet to the tinker terminal
+    fn sync(&mut self) {
+        let batch = match self
+            .runtime
+            .block_on(self.daemon.sync_batch(jiff::Timestamp::UNIX_EPOCH))
+        {
+            Ok(batch) => batch,
+            Err(e) => {
+                tracing::error!(?e, "Failed to read timesheet for syncing");
+                return;
+            }
+        };
+        match self.runtime.block_on(self.client.sync(&batch)) {
+            Ok(()) => tracing::info!(rows = batch.timesheet.len(), "Synced timesheet"),
+            Err(e) => tracing::error!(?e, "Failed to sync timesheet"),
+        }
+    }
+
+    /// Creates a room running this app's split, or joins
+    /// an existing one when `code` is given
+    fn join_room(&mut self, ctx: &egui::Context, code: Option<&str>) {
+        self.leave_room();
+
+        let client = &mut self.client;
+        let timers = self.state.timer.timers.clone();
+        let split = self.state.split.clone();
+        let joined = self.runtime.block_on(async move {
+            let code = match code {
+                Some(code) => code.trim().to_uppercase(),
+                None => client.create_room(split, timers).await?.code,
+            };
+            let subscription = client.subscribe_room(&code).await?;
+            Ok::<_, lockinspiel_common::client::ClientError>((code, subscription))
+        });
+        let (code, mut subscription) = match joined {
+            Ok(joined) => joined,
+            Err(e) => {
+                tracing::error!(?e, "Failed to join room");
+                self.room_dialog.error = Some(e.to_string());
+                return;
+            }
+        };
+
+        let (tx, events) = mpsc::channel();
+        let ctx = ctx.clone();
+        let task = self.runtime.spawn(async move {
+            while let Some(event) = subscription.next().await {
+                match event {
+                    Ok(event) => {
+                        if tx.send(event).is_err() {
+                            break;
+                        }
+                        ctx.request_repaint();
+                    }
+                    Err(e) => {
+                        tracing::error!(?e, "Lost connection to room");
+                        break;
+                    }
+                }
+            }
+            ctx.request_repaint();
+        });
+
+        tracing::info!(code, "Joined room");
+        self.room_dialog.error = None;
+        self.room = Some(JoinedRoom {
+            code,
+            members: 0,
+            events,
+            task,
+        });
+    }
+
+    /// Leaves the room, handing the timer back to the daemon
+    fn leave_room(&mut self) {
+        if let Some(room) = self.room.take() {
+            room.task.abort();
+            tracing::info!(room.code, "Left room");
+            let result = self.runtime.block_on(self.daemon.unfollow());
+            self.update_state(result);
         }
     }
+
+    /// Applies events the room sent since the last frame
+    fn poll_room(&mut self) {
+        let Some(room) = &mut self.room else {
+            return;
+        };
+
+        let mut latest = None;
+        let mut disconnected = false;
+        loop {
+            match room.events.try_recv() {
+                Ok(event) => latest = Some(event),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.room_dialog.error = Some(format!("Disconnected from room {}", room.code));
+                    disconnected = true;
+                    break;
+                }
+            }
+        }
+
+        if let Some(event) = latest {
+            room.members = event.room.members;
+            let result = self.runtime.block_on(self.daemon.follow(event.room.timer));
+            self.update_state(result);
+        }
+        if disconnected {
+            self.leave_room();
+        }
+    }
+
+    fn sign_in_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.sign_in_dialog.open;
+        egui::Window::new("Sign In")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("sign_in_grid").show(ui, |ui| {
+                    ui.label("Email");
+                    ui.text_edit_singleline(&mut self.sign_in_dialog.email);
+                    ui.end_row();
+                    ui.label("Password");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.sign_in_dialog.password)
+                            .password(true),
+                    );
+                    ui.end_row();
+                });
+                if ui.button("Sign In").clicked() {
+                    let dialog = &mut self.sign_in_dialog;
+                    match self
+                        .runtime
+                        .block_on(self.client.sign_in(&dialog.email, &dialog.password))
+                    {
+                        Ok(()) => {
+                            dialog.password.clear();
+                            dialog.error = None;
+                            dialog.open = false;
+                        }
+                        Err(e) => {
+                            tracing::error!(?e, "Failed to sign in");
+                            dialog.error = Some(e.to_string());
+                        }
+                    }
+                }
+                if let Some(error) = &self.sign_in_dialog.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+            });
+        self.sign_in_dialog.open &= open;
+    }
+
+    fn settings_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.settings_open;
+        egui::Window::new("Settings")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let paused = matches!(self.state.timer.state, TimerState::Paused(_));
+                let mut selected = None;
+                ui.add_enabled_ui(paused && self.room.is_none(), |ui| {
+                    egui::ComboBox::from_label("Split")
+                        .selected_text(&self.state.split)
+                        .show_ui(ui, |ui| {
+                            for split in &self.splits {
+                                if ui
+                                    .selectable_label(split.name == self.state.split, &split.name)
+                                    .clicked()
+                                {
+                                    selected = Some(split.clone());
+                                }
+                            }
+                        });
+                });
+                if let Some(split) = selected {
+                    self.select_split(split);
+                }
+                if ui
+                    .checkbox(
+                        &mut self.settings.auto_start,
+                        "Start the next timer when one runs out",
+                    )
+                    .changed()
+                {
+                    let result = self
+                        .runtime
+                        .block_on(self.daemon.set_auto_start(self.settings.auto_start));
+                    self.update_state(result);
+                }
+
+                let mut idle = self.settings.idle;
+                egui::ComboBox::from_label("When away from the computer")
+                    .selected_text(idle_policy_label(idle))
+                    .show_ui(ui, |ui| {
+                        for policy in IdlePolicy::ALL {
+                            ui.selectable_value(&mut idle, policy, idle_policy_label(policy));
+                        }
+                    });
+                if idle != self.settings.idle {
+                    self.settings.idle = idle;
+                    let result = self.runtime.block_on(self.daemon.set_idle_policy(idle));
+                    self.update_state(result);
+                }
+
+                ui.separator();
+                let alerts = &mut self.settings.alerts;
+                ui.checkbox(&mut alerts.do_not_disturb, "Do not disturb");
+                ui.add_enabled_ui(!alerts.do_not_disturb, |ui| {
+                    ui.label(format!("When a timer in {} runs out", self.state.split));
+                    let split = alerts.split_mut(&self.state.split);
+                    ui.checkbox(&mut split.notify, "Show a notification");
+                    ui.checkbox(&mut split.sound, "Play a sound");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Sound file");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut alerts.sound_file)
+                            .hint_text("Built-in chime"),
+                    );
+                    if ui.button("Test").clicked() {
+                        self.alerts.play_sound(&alerts.sound_file);
+                    }
+                });
+
+                ui.separator();
+                if ui
+                    .checkbox(
+                        &mut self.settings.global_shortcuts,
+                        "Global shortcuts (Ctrl+Alt+Space and Ctrl+Alt+Right)",
+                    )
+                    .changed()
+                {
+                    self.set_global_shortcuts(ctx, self.settings.global_shortcuts);
+                }
+                ui.label("Space starts and pauses, Right skips, 1 to 9 pick a split");
+                ui.label("Ctrl+Z undoes, Ctrl+Shift+Z redoes");
+                if ui
+                    .checkbox(&mut self.settings.tray, "Show a tray icon")
+                    .changed()
+                {
+                    self.set_tray(ctx, self.settings.tray);
+                }
+
+                ui.separator();
+                if ui
+                    .checkbox(
+                        &mut self.settings.clock_sync,
+                        "Sync the clock with the tinker terminal",
+                    )
+                    .changed()
+                {
+                    self.client.set_clock_sync(self.settings.clock_sync);
+                }
+                ui.add_enabled_ui(self.settings.clock_sync, |ui| {
+                    ui.horizontal(|ui| {
+                        match self.client.cached_clock_offset() {
+                            Some(offset) => ui.label(format!("Off by {:#}", offset)),
+                            None => ui.label("Not synced yet"),
+                        };
+                        if ui.button("Resync").clicked() {
+                            if let Err(e) =
+                                self.runtime.block_on(self.client.refresh_clock_offset())
+                            {
+                                tracing::error!(?e, "Failed to resync clock");
+                            }
+                        }
+                    });
+                });
+            });
+        self.settings_open = open;
+    }
+
+    /// Asks whether time away from the computer was work,
+    /// when the daemon has some to ask about
+    fn away_window(&mut self, ctx: &egui::Context) {
+        let Some(away) = self.state.away else {
+            return;
+        };
+
+        let mut keep = None;
+        egui::Window::new("Welcome Back")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_TOP, Vec2::new(0.0, 48.0))
+            .show(ctx, |ui| {
+                let since = away
+                    .since
+                    .to_zoned(jiff::tz::TimeZone::system())
+                    .strftime("%H:%M");
+                ui.label(format!(
+                    "You were away for {} from {} with the timer going",
+                    countdown(away.length()),
+                    since
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button("It was work").clicked() {
+                        keep = Some(true);
+                    }
+                    if ui.button("Count it as idle").clicked() {
+                        keep = Some(false);
+                    }
+                });
+            });
+        if let Some(keep) = keep {
+            let result = self.runtime.block_on(self.daemon.resolve_away(keep));
+            self.update_state(result);
+        }
+    }
+
+    /// Loads the last week of the timesheet into the editor
+    fn load_timesheet(&mut self) {
+        let since = jiff::Timestamp::now()
+            .checked_sub(timesheet::HISTORY)
+            .unwrap_or(jiff::Timestamp::UNIX_EPOCH);
+        match self.runtime.block_on(self.daemon.history(since)) {
+            Ok(entries) => self.timesheet.set_entries(entries),
+            Err(e) => {
+                tracing::error!(?e, "Failed to load timesheet");
+                self.timesheet.error = Some(e.to_string());
+            }
+        }
+    }
+
+    fn timesheet_window(&mut self, ctx: &egui::Context, now: jiff::Timestamp) {
+        let action = timesheet::show(ctx, &mut self.timesheet, &self.splits, now);
+        match action {
+            Some(TimesheetAction::Edit(method)) => {
+                let added = matches!(method, Method::AddSession(_));
+                match self.runtime.block_on(self.daemon.call::<()>(method)) {
+                    Ok(()) => {
+                        self.timesheet.error = None;
+                        if added {
+                            self.timesheet.added();
+                        }
+                    }
+                    // Refused edits say why, for people
+                    Err(DaemonError::Rpc { message, .. }) => self.timesheet.error = Some(message),
+                    Err(e) => {
+                        tracing::error!(?e, "Failed to edit timesheet");
+                        self.timesheet.error = Some(e.to_string());
+                    }
+                }
+                self.load_timesheet();
+            }
+            Some(TimesheetAction::Refresh) => self.load_timesheet(),
+            None => {}
+        }
+    }
+
+    fn room_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.room_dialog.open;
+        egui::Window::new("Room")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                if let Some(room) = &self.room {
+                    ui.label(format!("In room {}", room.code));
+                    ui.label(format!("{} members", room.members));
+                    if ui.button("Leave").clicked() {
+                        self.leave_room();
+                    }
+                } else {
+                    ui.horizontal(|ui| {
+                        ui.label("Code");
+                        ui.text_edit_singleline(&mut self.room_dialog.code);
+                    });
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(
+                                !self.room_dialog.code.trim().is_empty(),
+                                egui::Button::new("Join"),
+                            )
+                            .clicked()
+                        {
+                            let code = self.room_dialog.code.clone();
+                            self.join_room(ctx, Some(&code));
+                        }
+                        if ui.button("Create").clicked() {
+                            self.join_room(ctx, None);
+                        }
+                    });
+                }
+                if let Some(error) = &self.room_dialog.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+            });
+        self.room_dialog.open = open;
+    }
 }
 
 impl LockinspielApp {
@@ -72,22 +753,112 @@ impl LockinspielApp {
 
         // Load previous app state (if any).
         // Note that you must enable the `persistence` feature for this to work.
-        Self::default()
+        let settings = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, eframe::APP_KEY))
+            .unwrap_or_default();
+        let mut app = Self::load(&cc.egui_ctx, settings);
+        if app.settings.global_shortcuts {
+            app.set_global_shortcuts(&cc.egui_ctx, true);
+        }
+        if app.settings.tray {
+            app.set_tray(&cc.egui_ctx, true);
+        }
+        if app.settings.mini {
+            mini::apply(&cc.egui_ctx, true);
+        }
+        app
     }
 }
 
 impl eframe::App for LockinspielApp {
+    /// Called by the framework to save state before shutdown.
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, eframe::APP_KEY, &self.settings);
+    }
+
     /// Called each time the UI needs repainting, which may be many times per second.
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // Put your widgets into a `SidePanel`, `TopBottomPanel`, `CentralPanel`, `Window` or `Area`.
         // For inspiration and more examples, go to https://emilk.github.io/egui
 
+        let now = self.runtime.block_on(self.client.now());
+        self.poll_room();
+        self.poll_daemon(ctx);
+        let global = self
+            .global_shortcuts
+            .as_ref()
+            .and_then(GlobalShortcuts::poll);
+        if let Some(shortcut) = global.or_else(|| shortcuts::pressed(ctx)) {
+            self.shortcut(shortcut);
+        }
+        if let Some(action) = self.tray.as_ref().and_then(TrayIcon::poll) {
+            self.tray_action(ctx, action);
+        }
+        // Minimized windows still get frames, hidden
+        // ones don't on every platform
+        if ctx.input(|input| input.viewport().close_requested())
+            && self.tray.is_some()
+            && !self.quitting
+        {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(true));
+        }
+
+        let time_remaining = self.state.timer.remaining(now);
+        let status = self.tray_status(now);
+        if let Some(tray) = &mut self.tray {
+            tray.set_status(&self.runtime, status);
+        }
+        if let TimerState::Going(_) = self.state.timer.state {
+            // Repaint when the next second ticks over, or every
+            // second once a room's timer sits at zero
+            let until_tick = match time_remaining.as_millis() as u64 % 1000 {
+                0 => 1000,
+                millis => millis,
+            };
+            ctx.request_repaint_after(Duration::from_millis(until_tick));
+        }
+
+        if self.settings.mini {
+            match mini::show(ctx, &self.state.timer, time_remaining) {
+                Some(MiniAction::StartPause) => self.shortcut(Shortcut::StartPause),
+                Some(MiniAction::Expand) => self.set_mini(ctx, false),
+                None => {}
+            }
+            return;
+        }
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             // The top panel is often a good place for a menu bar:
 
             egui::MenuBar::new().ui(ui, |ui| {
                 // NOTE: no File->Quit on web pages!
-                if ui.button("Sign In").clicked() {}
+                if self.client.signed_in() {
+                    if ui.button("Sync").clicked() {
+                        self.sync();
+                    }
+                    if ui.button("Sign Out").clicked() {
+                        self.leave_room();
+                        self.client.sign_out();
+                    }
+                } else if ui.button("Sign In").clicked() {
+                    self.sign_in_dialog.open = true;
+                }
+                if ui.button("Room").clicked() {
+                    self.room_dialog.open = true;
+                }
+                if ui.button("Timesheet").clicked() {
+                    self.timesheet.open = true;
+                    self.timesheet.error = None;
+                    self.load_timesheet();
+                }
+                if ui.button("Settings").clicked() {
+                    self.settings_open = true;
+                }
+                if ui.button("Mini").clicked() {
+                    self.set_mini(ctx, true);
+                }
                 ui.add_space(16.0);
 
                 egui::widgets::global_theme_preference_buttons(ui);
@@ -99,18 +870,12 @@ impl eframe::App for LockinspielApp {
             egui::warn_if_debug_build(ui);
         });
 
-        let now = self.runtime.block_on(self.client.now());
-
-        let time_remaining = match self.timer_state {
-            TimerState::Going(timer_end) => {
-                let time_remaining = now.duration_until(timer_end);
-                ctx.request_repaint_after(Duration::from_millis(
-                    time_remaining.as_millis() as u64 % 1000,
-                ));
-                time_remaining
-            }
-            TimerState::Paused(duration) => duration,
-        };
+        self.sign_in_window(ctx);
+        self.room_window(ctx);
+        self.settings_window(ctx);
+        self.timesheet_window(ctx, now);
+        self.undo_toast(ctx);
+        self.away_window(ctx);
 
         egui::Window::new("Lockinspiel")
             .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
@@ -130,19 +895,14 @@ impl eframe::App for LockinspielApp {
                         ..default_style()
                     })
                     .show(|tui| {
-                        let time_remaining_secs = time_remaining.as_secs();
                         tui.style(taffy::Style {
                             align_self: Some(taffy::AlignItems::Center),
                             ..default_style()
                         })
                         .egui_layout(egui::Layout::default().with_cross_align(egui::Align::Center))
                         .label(
-                            RichText::new(format!(
-                                "{}:{:02}",
-                                time_remaining_secs / 60,
-                                time_remaining_secs % 60,
-                            ))
-                            .font(FontId::proportional(72.0)),
+                            RichText::new(countdown(time_remaining))
+                                .font(FontId::proportional(72.0)),
                         );
                         tui.style(Style {
                             flex_direction: taffy::FlexDirection::Row,
@@ -153,7 +913,7 @@ impl eframe::App for LockinspielApp {
                             // },
                             ..default_style()
                         })
-                        .add(|tui| match self.timer_state {
+                        .add(|tui| match self.state.timer.state {
                             TimerState::Going(_) => {
                                 if tui
                                     .style(Style {
@@ -163,9 +923,7 @@ impl eframe::App for LockinspielApp {
                                     .ui_add(egui::Button::new("Pause"))
                                     .clicked()
                                 {
-                                    let db = self.db.get().unwrap();
-                                    db.stop_timer(now).unwrap();
-                                    self.timer_state = TimerState::Paused(time_remaining);
+                                    self.act(RoomAction::Pause);
                                 }
                                 tui.enabled_ui(false)
                                     .style(Style {
@@ -174,7 +932,7 @@ impl eframe::App for LockinspielApp {
                                     })
                                     .ui_add(egui::Button::new(">>"));
                             }
-                            TimerState::Paused(timer_len) => {
+                            TimerState::Paused(_) => {
                                 if tui
                                     .style(Style {
                                         flex_grow: 1.,
@@ -183,18 +941,7 @@ impl eframe::App for LockinspielApp {
                                     .ui_add(egui::Button::new("Start"))
                                     .clicked()
                                 {
-                                    let db = self.db.get().unwrap();
-                                    let end_time = now + timer_len;
-                                    db.add_to_timesheet(lockinspiel_common::db::TimesheetRow {
-                                        group: *self.group.get_or_insert_with(|| {
-                                            db.next_timesheet_group().unwrap()
-                                        }),
-                                        start_time: JiffTimestamp(now),
-                                        end_time: JiffTimestamp(end_time),
-                                        activity: self.timer_on as i32 + 1,
-                                    })
-                                    .unwrap();
-                                    self.timer_state = TimerState::Going(end_time);
+                                    self.act(RoomAction::Start);
                                 }
                                 if tui
                                     .enabled_ui(true)
@@ -205,12 +952,7 @@ impl eframe::App for LockinspielApp {
                                     .ui_add(egui::Button::new(">>"))
                                     .clicked()
                                 {
-                                    self.timer_on += 1;
-                                    if self.timer_on >= self.timers.len() {
-                                        self.timer_on = 0;
-                                    }
-                                    self.timer_state =
-                                        TimerState::Paused(self.timers[self.timer_on]);
+                                    self.act(RoomAction::Skip);
                                 }
                             }
                         })
@@ -221,6 +963,52 @@ impl eframe::App for LockinspielApp {
     }
 }
 
+/// Subscribes to the daemon on a connection of its own,
+/// forwarding events to the app and waking it up for them
+fn subscribe(
+    ctx: &egui::Context,
+    runtime: &tokio::runtime::Runtime,
+) -> Result<(DaemonState, mpsc::Receiver<Event>), DaemonError> {
+    let (state, mut subscription) =
+        runtime.block_on(async { DaemonClient::connect().await?.subscribe().await })?;
+
+    let (tx, events) = mpsc::channel();
+    let ctx = ctx.clone();
+    runtime.spawn(async move {
+        while let Some(event) = subscription.next().await {
+            match event {
+                Ok(event) => {
+                    if tx.send(event).is_err() {
+                        break;
+                    }
+                    ctx.request_repaint();
+                }
+                Err(e) => {
+                    tracing::error!(?e, "Lost connection to daemon");
+                    break;
+                }
+            }
+        }
+        ctx.request_repaint();
+    });
+    Ok((state, events))
+}
+
+/// Minutes and seconds, like 4:05
+pub(crate) fn countdown(remaining: jiff::SignedDuration) -> String {
+    let secs = remaining.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+fn idle_policy_label(policy: IdlePolicy) -> &'static str {
+    match policy {
+        IdlePolicy::Ignore => "Keep the timer going",
+        IdlePolicy::Pause => "Pause the timer",
+        IdlePolicy::Ask => "Ask when I'm back",
+        IdlePolicy::Record => "Count it as idle",
+    }
+}
+
 fn powered_by_egui_and_eframe(ui: &mut egui::Ui) {
     ui.horizontal(|ui| {
         ui.spacing_mut().item_spacing.x = 0.0;
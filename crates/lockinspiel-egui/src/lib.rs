@@ -1,4 +1,11 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
+mod alerts;
 mod app;
+mod mini;
+mod settings;
+mod shortcuts;
+mod timesheet;
+mod tray;
 pub use app::LockinspielApp;
+pub use mini::viewport;
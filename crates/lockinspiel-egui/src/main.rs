@@ -28,14 +28,13 @@ fn main() -> eyre::Result<()> {
     lockinspiel_common::install_init_boilerplate(Some(cli.verbose.tracing_level_filter()))?;
 
     let native_options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default()
-            .with_inner_size([400.0, 300.0])
-            .with_min_inner_size([300.0, 220.0])
-            .with_icon(
-                // NOTE: Adding an icon is optional
-                eframe::icon_data::from_png_bytes(&include_bytes!("../assets/icon-256.png")[..])
-                    .expect("Failed to load icon"),
-            ),
+        // Mini mode is remembered with the rest of the settings,
+        // which aren't loaded yet, so the app switches to it
+        viewport: lockinspiel_egui::viewport(false).with_icon(
+            // NOTE: Adding an icon is optional
+            eframe::icon_data::from_png_bytes(&include_bytes!("../assets/icon-256.png")[..])
+                .expect("Failed to load icon"),
+        ),
         ..Default::default()
     };
     eframe::run_native(
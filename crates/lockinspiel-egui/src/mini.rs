@@ -0,0 +1,131 @@
+//! A small borderless countdown that stays on top
+//! of everything, for keeping an eye on the timer
+//! while working
+use std::f32::consts::TAU;
+
+use egui::{Align2, Color32, FontId, Pos2, Sense, Shape, Stroke, Vec2};
+use lockinspiel_common::timer::{Timer, TimerState};
+
+use crate::app::countdown;
+
+const WORK: Color32 = Color32::from_rgb(0xe0, 0x5a, 0x47);
+const BREAK: Color32 = Color32::from_rgb(0x4c, 0xaf, 0x50);
+
+/// The window the app opens with, or switches to
+pub fn viewport(mini: bool) -> egui::ViewportBuilder {
+    let viewport = egui::ViewportBuilder::default();
+    if mini {
+        viewport
+            .with_decorations(false)
+            .with_always_on_top()
+            .with_inner_size([140.0, 140.0])
+            .with_min_inner_size([100.0, 100.0])
+    } else {
+        viewport
+            .with_decorations(true)
+            .with_window_level(egui::WindowLevel::Normal)
+            .with_inner_size([400.0, 300.0])
+            .with_min_inner_size([300.0, 220.0])
+    }
+}
+
+/// Switches the open window in or out of mini mode
+pub fn apply(ctx: &egui::Context, mini: bool) {
+    let (commands, _) = egui::ViewportBuilder::default().patch(viewport(mini));
+    for command in commands {
+        ctx.send_viewport_cmd(command);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MiniAction {
+    StartPause,
+    /// Back to the full window
+    Expand,
+}
+
+/// Draws the ring and countdown over the whole window.
+/// Dragging moves the window, double clicking expands it
+/// and the rest is in the right click menu
+pub fn show(
+    ctx: &egui::Context,
+    timer: &Timer,
+    remaining: jiff::SignedDuration,
+) -> Option<MiniAction> {
+    let mut action = None;
+    egui::CentralPanel::default()
+        .frame(egui::Frame::NONE.fill(ctx.style().visuals.panel_fill))
+        .show(ctx, |ui| {
+            let rect = ui.max_rect();
+            let response = ui.interact(rect, ui.id().with("mini"), Sense::click_and_drag());
+            if response.drag_started() {
+                ctx.send_viewport_cmd(egui::ViewportCommand::StartDrag);
+            }
+            if response.double_clicked() {
+                action = Some(MiniAction::Expand);
+            }
+
+            let going = matches!(timer.state, TimerState::Going(_));
+            response.context_menu(|ui| {
+                if ui.button(if going { "Pause" } else { "Start" }).clicked() {
+                    action = Some(MiniAction::StartPause);
+                }
+                if ui.button("Full Window").clicked() {
+                    action = Some(MiniAction::Expand);
+                }
+            });
+
+            let current = timer.current();
+            let mut colour = if current.work { WORK } else { BREAK };
+            if !going {
+                colour = colour.gamma_multiply(0.5);
+            }
+            let progress = if current.len.is_positive() {
+                1.0 - (remaining.as_secs_f64() / current.len.as_secs_f64()) as f32
+            } else {
+                1.0
+            };
+
+            let painter = ui.painter_at(rect);
+            let center = rect.center();
+            let radius = rect.width().min(rect.height()) / 2.0 - 8.0;
+            let width = radius / 8.0;
+            painter.circle_stroke(
+                center,
+                radius,
+                Stroke::new(width, ui.visuals().widgets.inactive.bg_fill),
+            );
+            painter.add(Shape::line(
+                arc(center, radius, progress.clamp(0.0, 1.0)),
+                Stroke::new(width, colour),
+            ));
+            painter.text(
+                center,
+                Align2::CENTER_CENTER,
+                countdown(remaining),
+                FontId::proportional(radius / 2.5),
+                ui.visuals().strong_text_color(),
+            );
+            painter.text(
+                center + Vec2::new(0.0, radius / 2.5),
+                Align2::CENTER_CENTER,
+                &current.name,
+                FontId::proportional(radius / 6.0),
+                colour,
+            );
+        });
+
+    action
+}
+
+/// Points along the ring from the top, clockwise,
+/// covering `progress` of the way round
+fn arc(center: Pos2, radius: f32, progress: f32) -> Vec<Pos2> {
+    let steps = (64.0 * progress).ceil() as usize;
+    (0..=steps)
+        .map(|step| {
+            let angle = TAU * progress * step as f32 / steps.max(1) as f32 - TAU / 4.0;
+            center + radius * Vec2::angled(angle)
+        })
+        .collect()
+}
@@ -0,0 +1,41 @@
+//! What the app remembers between runs. eframe keeps
+//! this in its storage, along with the window size
+//! and egui's own options like the theme
+use lockinspiel_common::idle::IdlePolicy;
+use serde::{Deserialize, Serialize};
+
+use crate::alerts::AlertSettings;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Start the next timer as soon as one runs out
+    pub auto_start: bool,
+    /// What the daemon does while the computer
+    /// sleeps or sits idle
+    pub idle: IdlePolicy,
+    pub alerts: AlertSettings,
+    /// Correct the clock with the tinker terminal's
+    pub clock_sync: bool,
+    /// Bind hotkeys that work outside the window
+    pub global_shortcuts: bool,
+    /// Show the countdown in the tray, and keep
+    /// running there when the window is closed
+    pub tray: bool,
+    /// Shrink the window to an always on top countdown
+    pub mini: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            auto_start: false,
+            idle: IdlePolicy::Ask,
+            alerts: AlertSettings::default(),
+            clock_sync: true,
+            global_shortcuts: false,
+            tray: true,
+            mini: false,
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Keyboard control of the timer. Keys pressed in the
+//! window work everywhere, global hotkeys go through the
+//! XDG GlobalShortcuts portal so they only work on Linux
+use std::sync::mpsc;
+
+use egui::{Key, Modifiers};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shortcut {
+    StartPause,
+    Skip,
+    Undo,
+    Redo,
+    /// Index into the app's splits
+    Split(usize),
+}
+
+const SPLIT_KEYS: [Key; 9] = [
+    Key::Num1,
+    Key::Num2,
+    Key::Num3,
+    Key::Num4,
+    Key::Num5,
+    Key::Num6,
+    Key::Num7,
+    Key::Num8,
+    Key::Num9,
+];
+
+/// The shortcut pressed in the window this frame, if any.
+/// Nothing counts while a text field is being typed in
+pub fn pressed(ctx: &egui::Context) -> Option<Shortcut> {
+    if ctx.wants_keyboard_input() {
+        return None;
+    }
+
+    ctx.input_mut(|input| {
+        if input.consume_key(Modifiers::NONE, Key::Space) {
+            return Some(Shortcut::StartPause);
+        }
+        if input.consume_key(Modifiers::NONE, Key::ArrowRight) {
+            return Some(Shortcut::Skip);
+        }
+        // Shift is ignored when it isn't asked for, so
+        // redo has to be checked first
+        if input.consume_key(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z)
+            || input.consume_key(Modifiers::COMMAND, Key::Y)
+        {
+            return Some(Shortcut::Redo);
+        }
+        if input.consume_key(Modifiers::COMMAND, Key::Z) {
+            return Some(Shortcut::Undo);
+        }
+        SPLIT_KEYS
+            .iter()
+            .position(|key| input.consume_key(Modifiers::NONE, *key))
+            .map(Shortcut::Split)
+    })
+}
+
+/// Hotkeys bound through the portal. Dropping this
+/// stops listening for them
+pub struct GlobalShortcuts {
+    shortcuts: mpsc::Receiver<Shortcut>,
+    #[cfg(target_os = "linux")]
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl GlobalShortcuts {
+    #[cfg(target_os = "linux")]
+    pub fn bind(ctx: &egui::Context, runtime: &tokio::runtime::Runtime) -> Self {
+        let (tx, shortcuts) = mpsc::channel();
+        let ctx = ctx.clone();
+        let task = runtime.spawn(async move {
+            if let Err(e) = portal::listen(tx, ctx).await {
+                tracing::error!(?e, "Failed to bind global shortcuts");
+            }
+        });
+
+        Self { shortcuts, task }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn bind(_ctx: &egui::Context, _runtime: &tokio::runtime::Runtime) -> Self {
+        tracing::warn!("Global shortcuts are only supported on Linux");
+        let (_, shortcuts) = mpsc::channel();
+        Self { shortcuts }
+    }
+
+    /// A hotkey pressed since the last frame, if any
+    pub fn poll(&self) -> Option<Shortcut> {
+        self.shortcuts.try_recv().ok()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for GlobalShortcuts {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod portal {
+    use std::sync::mpsc;
+
+    use ashpd::desktop::global_shortcuts::{GlobalShortcuts, NewShortcut};
+    use futures_util::StreamExt;
+
+    use super::Shortcut;
+
+    const START_PAUSE: &str = "start-pause";
+    const SKIP: &str = "skip";
+
+    pub async fn listen(tx: mpsc::Sender<Shortcut>, ctx: egui::Context) -> ashpd::Result<()> {
+        let portal = GlobalShortcuts::new().await?;
+        // The shortcuts stay bound for as long as the session lives
+        let session = portal.create_session().await?;
+        // The desktop may ask the user to confirm these,
+        // or pick different keys
+        let bound = portal
+            .bind_shortcuts(
+                &session,
+                &[
+                    NewShortcut::new(START_PAUSE, "Start or pause the timer")
+                        .preferred_trigger("CTRL+ALT+space"),
+                    NewShortcut::new(SKIP, "Skip to the next timer")
+                        .preferred_trigger("CTRL+ALT+Right"),
+                ],
+                None,
+            )
+            .await?
+            .response()?;
+        for shortcut in bound.shortcuts() {
+            tracing::info!(
+                id = shortcut.id(),
+                trigger = shortcut.trigger_description(),
+                "Bound global shortcut"
+            );
+        }
+
+        let mut activated = portal.receive_activated().await?;
+        while let Some(activated) = activated.next().await {
+            let shortcut = match activated.shortcut_id() {
+                START_PAUSE => Shortcut::StartPause,
+                SKIP => Shortcut::Skip,
+                id => {
+                    tracing::warn!(id, "Unknown global shortcut");
+                    continue;
+                }
+            };
+            if tx.send(shortcut).is_err() {
+                break;
+            }
+            ctx.request_repaint();
+        }
+
+        session.close().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+//! Fixing up the last week of the timesheet, for when a
+//! timer was left going or never started. The daemon does
+//! the editing, this only turns clicks into calls
+use egui::RichText;
+use jiff::{
+    SignedDuration, Timestamp,
+    civil::{DateTime, Time},
+    tz::TimeZone,
+};
+use lockinspiel_common::{daemon::Method, edit::NewSession, history::Entry, sync::SyncedSplit};
+
+use crate::app::countdown;
+
+/// How far back the editor goes
+pub const HISTORY: SignedDuration = SignedDuration::from_hours(7 * 24);
+
+/// Times as they're typed in
+const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+pub enum TimesheetAction {
+    /// A call that changes the timesheet
+    Edit(Method),
+    /// Load the entries again
+    Refresh,
+}
+
+#[derive(Default)]
+pub struct Timesheet {
+    pub open: bool,
+    /// Oldest first, so merging is with the row below
+    entries: Vec<Entry>,
+    rows: Vec<RowEdit>,
+    add: AddForm,
+    pub error: Option<String>,
+}
+
+/// What's typed in for an entry
+struct RowEdit {
+    start: String,
+    end: String,
+    split_at: String,
+}
+
+struct AddForm {
+    start: String,
+    end: String,
+    work: bool,
+    split: String,
+    /// Comma separated
+    tags: String,
+}
+
+impl Default for AddForm {
+    fn default() -> Self {
+        Self {
+            start: String::new(),
+            end: String::new(),
+            work: true,
+            split: String::new(),
+            tags: String::new(),
+        }
+    }
+}
+
+impl Timesheet {
+    pub fn set_entries(&mut self, entries: Vec<Entry>) {
+        let tz = TimeZone::system();
+        self.rows = entries
+            .iter()
+            .map(|entry| RowEdit {
+                start: format_time(entry.start_time, &tz),
+                end: format_time(entry.end_time, &tz),
+                split_at: String::new(),
+            })
+            .collect();
+        self.entries = entries;
+    }
+
+    /// Clears the form after a session was added
+    pub fn added(&mut self) {
+        self.add.start.clear();
+        self.add.end.clear();
+        self.add.tags.clear();
+    }
+}
+
+/// Draws the editor, returning the edit to send to
+/// the daemon if one was asked for. Times that don't
+/// parse end up in `error` instead
+pub fn show(
+    ctx: &egui::Context,
+    timesheet: &mut Timesheet,
+    splits: &[SyncedSplit],
+    now: Timestamp,
+) -> Option<TimesheetAction> {
+    let tz = TimeZone::system();
+    let mut open = timesheet.open;
+    let mut edit = None;
+    let mut refresh = false;
+    egui::Window::new("Timesheet")
+        .open(&mut open)
+        .collapsible(false)
+        .default_width(720.0)
+        .show(ctx, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(360.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("timesheet_grid")
+                        .striped(true)
+                        .show(ui, |ui| {
+                            ui.strong("Start");
+                            ui.strong("End");
+                            ui.strong("Length");
+                            ui.strong("");
+                            ui.strong("Split");
+                            ui.strong("Tags");
+                            ui.end_row();
+
+                            if timesheet.entries.is_empty() {
+                                ui.label("Nothing in the last week");
+                                ui.end_row();
+                            }
+                            let entries = &timesheet.entries;
+                            for (index, (entry, row)) in
+                                entries.iter().zip(&mut timesheet.rows).enumerate()
+                            {
+                                let going = entry.end_time > now;
+                                ui.add_enabled(
+                                    !going,
+                                    egui::TextEdit::singleline(&mut row.start).desired_width(140.0),
+                                );
+                                ui.add_enabled(
+                                    !going,
+                                    egui::TextEdit::singleline(&mut row.end).desired_width(140.0),
+                                );
+                                let length = countdown(entry.length(now));
+                                if entry.idle.is_zero() {
+                                    ui.label(length);
+                                } else {
+                                    ui.label(format!(
+                                        "{} ({} idle)",
+                                        length,
+                                        countdown(entry.idle)
+                                    ));
+                                }
+                                ui.label(if entry.work { "Work" } else { "Break" });
+                                ui.label(entry.split.as_deref().unwrap_or("-"));
+                                ui.label(entry.tags.join(", "));
+
+                                if going {
+                                    ui.label(RichText::new("Still going").weak());
+                                    ui.end_row();
+                                    continue;
+                                }
+                                ui.horizontal(|ui| {
+                                    let changed = row.start != format_time(entry.start_time, &tz)
+                                        || row.end != format_time(entry.end_time, &tz);
+                                    if ui.add_enabled(changed, egui::Button::new("Save")).clicked()
+                                    {
+                                        edit = Some(adjust(entry, row, &tz));
+                                    }
+                                    ui.add(
+                                        egui::TextEdit::singleline(&mut row.split_at)
+                                            .hint_text("HH:MM")
+                                            .desired_width(48.0),
+                                    );
+                                    if ui
+                                        .add_enabled(
+                                            !row.split_at.trim().is_empty(),
+                                            egui::Button::new("Split"),
+                                        )
+                                        .on_hover_text("Cut the session in two at this time")
+                                        .clicked()
+                                    {
+                                        edit =
+                                            Some(split_at(&row.split_at, entry, &tz).map(|at| {
+                                                Method::SplitSession {
+                                                    start_time: entry.start_time,
+                                                    at,
+                                                }
+                                            }));
+                                    }
+                                    let next =
+                                        entries.get(index + 1).filter(|next| next.end_time <= now);
+                                    let merge = ui
+                                        .add_enabled(next.is_some(), egui::Button::new("Merge"))
+                                        .on_hover_text("Join with the session below");
+                                    if let Some(next) = next.filter(|_| merge.clicked()) {
+                                        edit = Some(Ok(Method::MergeSessions {
+                                            first: entry.start_time,
+                                            second: next.start_time,
+                                        }));
+                                    }
+                                    if ui.button("Delete").clicked() {
+                                        edit = Some(Ok(Method::DeleteSession {
+                                            start_time: entry.start_time,
+                                        }));
+                                    }
+                                });
+                                ui.end_row();
+                            }
+                        });
+                });
+
+            ui.separator();
+            ui.label("Add a session that happened without the timer");
+            let add = &mut timesheet.add;
+            if add.split.is_empty() {
+                add.split = splits
+                    .first()
+                    .map(|split| split.name.clone())
+                    .unwrap_or_default();
+            }
+            egui::Grid::new("timesheet_add_grid").show(ui, |ui| {
+                ui.label("Start");
+                ui.add(
+                    egui::TextEdit::singleline(&mut add.start)
+                        .hint_text("2025-01-31 09:30")
+                        .desired_width(140.0),
+                );
+                ui.label("End");
+                ui.add(
+                    egui::TextEdit::singleline(&mut add.end)
+                        .hint_text("2025-01-31 09:30")
+                        .desired_width(140.0),
+                );
+                ui.end_row();
+                ui.label("Split");
+                egui::ComboBox::from_id_salt("timesheet_add_split")
+                    .selected_text(&add.split)
+                    .show_ui(ui, |ui| {
+                        for split in splits {
+                            ui.selectable_value(&mut add.split, split.name.clone(), &split.name);
+                        }
+                    });
+                ui.label("Tags");
+                ui.add(
+                    egui::TextEdit::singleline(&mut add.tags)
+                        .hint_text("Comma separated")
+                        .desired_width(140.0),
+                );
+                ui.end_row();
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut add.work, "Work");
+                if ui.button("Add").clicked() {
+                    edit = Some(add_session(add, &tz));
+                }
+                if ui.button("Refresh").clicked() {
+                    refresh = true;
+                }
+            });
+
+            if let Some(error) = &timesheet.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+        });
+    timesheet.open = open;
+
+    match edit {
+        Some(Ok(method)) => Some(TimesheetAction::Edit(method)),
+        Some(Err(error)) => {
+            timesheet.error = Some(error);
+            None
+        }
+        None => refresh.then_some(TimesheetAction::Refresh),
+    }
+}
+
+fn adjust(entry: &Entry, row: &RowEdit, tz: &TimeZone) -> Result<Method, String> {
+    Ok(Method::AdjustSession {
+        start_time: entry.start_time,
+        new_start_time: parse_time(&row.start, entry.start_time, tz)?,
+        new_end_time: parse_time(&row.end, entry.end_time, tz)?,
+    })
+}
+
+fn add_session(add: &AddForm, tz: &TimeZone) -> Result<Method, String> {
+    let start_time = parse_time(&add.start, Timestamp::UNIX_EPOCH, tz)?;
+    let end_time = parse_time(&add.end, Timestamp::UNIX_EPOCH, tz)?;
+    Ok(Method::AddSession(NewSession {
+        start_time,
+        end_time,
+        work: add.work,
+        split: add.split.clone(),
+        tags: add
+            .tags
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_owned)
+            .collect(),
+    }))
+}
+
+fn format_time(timestamp: Timestamp, tz: &TimeZone) -> String {
+    timestamp.to_zoned(tz.clone()).strftime(FORMAT).to_string()
+}
+
+/// A local time as typed in. Left as it was shown, it's
+/// `original`, so milliseconds the field doesn't show
+/// aren't lost
+fn parse_time(text: &str, original: Timestamp, tz: &TimeZone) -> Result<Timestamp, String> {
+    if text == format_time(original, tz) {
+        return Ok(original);
+    }
+    text.trim()
+        .parse::<DateTime>()
+        .and_then(|time| time.to_zoned(tz.clone()))
+        .map(|time| time.timestamp())
+        .map_err(|_| format!("{} isn't a time like 2025-01-31 09:30", text.trim()))
+}
+
+/// A time of day during `entry`, as HH:MM or a whole date
+/// and time. Sessions that go past midnight are split on
+/// whichever day has that time in them
+fn split_at(text: &str, entry: &Entry, tz: &TimeZone) -> Result<Timestamp, String> {
+    let text = text.trim();
+    if text.parse::<DateTime>().is_ok() {
+        return parse_time(text, Timestamp::UNIX_EPOCH, tz);
+    }
+    let time = text
+        .parse::<Time>()
+        .map_err(|_| format!("{} isn't a time like 09:30", text))?;
+    let start = entry.start_time.to_zoned(tz.clone());
+    [
+        start.date(),
+        start.date().tomorrow().unwrap_or(start.date()),
+    ]
+    .into_iter()
+    .filter_map(|date| date.to_datetime(time).to_zoned(tz.clone()).ok())
+    .map(|time| time.timestamp())
+    .find(|at| *at > entry.start_time && *at < entry.end_time)
+    .ok_or_else(|| format!("{} isn't during the session", text))
+}
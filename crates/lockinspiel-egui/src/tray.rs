@@ -0,0 +1,222 @@
+//! Tray icon showing the countdown, through the
+//! StatusNotifierItem spec so it only exists on Linux.
+//! While it's up, closing the window minimizes it
+//! instead of quitting
+use std::sync::mpsc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrayAction {
+    StartPause,
+    Skip,
+    Show,
+    Quit,
+}
+
+/// What the tray shows, rebuilt every frame and
+/// sent on to the tray when it changes
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrayStatus {
+    /// Name of the current timer
+    pub phase: String,
+    /// Formatted like the window's countdown
+    pub remaining: String,
+    pub going: bool,
+}
+
+pub struct TrayIcon {
+    actions: mpsc::Receiver<TrayAction>,
+    status: TrayStatus,
+    #[cfg(target_os = "linux")]
+    handle: ksni::Handle<sni::LockinspielTray>,
+}
+
+impl TrayIcon {
+    /// `None` when there's nothing to show a tray icon in
+    #[cfg(target_os = "linux")]
+    pub fn spawn(
+        ctx: &egui::Context,
+        runtime: &tokio::runtime::Runtime,
+        status: TrayStatus,
+    ) -> Option<Self> {
+        use ksni::TrayMethods;
+
+        let (tx, actions) = mpsc::channel();
+        let tray = sni::LockinspielTray::new(status.clone(), tx, ctx.clone());
+        match runtime.block_on(tray.spawn()) {
+            Ok(handle) => Some(Self {
+                actions,
+                status,
+                handle,
+            }),
+            Err(e) => {
+                tracing::error!(?e, "Failed to show tray icon");
+                None
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn spawn(
+        _ctx: &egui::Context,
+        _runtime: &tokio::runtime::Runtime,
+        _status: TrayStatus,
+    ) -> Option<Self> {
+        tracing::warn!("The tray icon is only supported on Linux");
+        None
+    }
+
+    /// An action picked from the tray since the last frame, if any
+    pub fn poll(&self) -> Option<TrayAction> {
+        self.actions.try_recv().ok()
+    }
+
+    pub fn set_status(&mut self, runtime: &tokio::runtime::Runtime, status: TrayStatus) {
+        if self.status == status {
+            return;
+        }
+        self.status = status;
+
+        #[cfg(target_os = "linux")]
+        {
+            // Updating waits on D-Bus, which shouldn't hold up a frame
+            let handle = self.handle.clone();
+            let status = self.status.clone();
+            runtime.spawn(async move {
+                handle.update(|tray| tray.status = status).await;
+            });
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = runtime;
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for TrayIcon {
+    fn drop(&mut self) {
+        // Nothing to wait for, the service goes away on its own
+        let _ = self.handle.shutdown();
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sni {
+    use std::sync::mpsc;
+
+    use ksni::{Icon, MenuItem, ToolTip, menu::StandardItem};
+
+    use super::{TrayAction, TrayStatus};
+
+    pub struct LockinspielTray {
+        pub status: TrayStatus,
+        icon: Vec<Icon>,
+        actions: mpsc::Sender<TrayAction>,
+        ctx: egui::Context,
+    }
+
+    impl LockinspielTray {
+        pub fn new(
+            status: TrayStatus,
+            actions: mpsc::Sender<TrayAction>,
+            ctx: egui::Context,
+        ) -> Self {
+            Self {
+                status,
+                icon: icon(),
+                actions,
+                ctx,
+            }
+        }
+
+        fn send(&self, action: TrayAction) {
+            // The app only reads actions while it's drawing
+            if self.actions.send(action).is_ok() {
+                self.ctx.request_repaint();
+            }
+        }
+    }
+
+    /// The app icon, converted from RGBA to ARGB
+    fn icon() -> Vec<Icon> {
+        let icon = eframe::icon_data::from_png_bytes(&include_bytes!("../assets/icon-256.png")[..])
+            .expect("Failed to load icon");
+        let mut data = icon.rgba;
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.rotate_right(1);
+        }
+        vec![Icon {
+            width: icon.width as i32,
+            height: icon.height as i32,
+            data,
+        }]
+    }
+
+    impl ksni::Tray for LockinspielTray {
+        fn id(&self) -> String {
+            env!("CARGO_PKG_NAME").into()
+        }
+
+        fn title(&self) -> String {
+            format!("{} {}", self.status.phase, self.status.remaining)
+        }
+
+        fn icon_pixmap(&self) -> Vec<Icon> {
+            self.icon.clone()
+        }
+
+        fn tool_tip(&self) -> ToolTip {
+            ToolTip {
+                title: self.title(),
+                description: if self.status.going {
+                    "Running".to_owned()
+                } else {
+                    "Paused".to_owned()
+                },
+                ..Default::default()
+            }
+        }
+
+        fn activate(&mut self, _x: i32, _y: i32) {
+            self.send(TrayAction::Show);
+        }
+
+        fn menu(&self) -> Vec<MenuItem<Self>> {
+            vec![
+                StandardItem {
+                    label: self.title(),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into(),
+                MenuItem::Separator,
+                StandardItem {
+                    label: if self.status.going { "Pause" } else { "Start" }.to_owned(),
+                    activate: Box::new(|tray: &mut Self| tray.send(TrayAction::StartPause)),
+                    ..Default::default()
+                }
+                .into(),
+                StandardItem {
+                    label: "Skip".to_owned(),
+                    // Same as the button, skipping only works while paused
+                    enabled: !self.status.going,
+                    activate: Box::new(|tray: &mut Self| tray.send(TrayAction::Skip)),
+                    ..Default::default()
+                }
+                .into(),
+                MenuItem::Separator,
+                StandardItem {
+                    label: "Show Window".to_owned(),
+                    activate: Box::new(|tray: &mut Self| tray.send(TrayAction::Show)),
+                    ..Default::default()
+                }
+                .into(),
+                StandardItem {
+                    label: "Quit".to_owned(),
+                    icon_name: "application-exit".to_owned(),
+                    activate: Box::new(|tray: &mut Self| tray.send(TrayAction::Quit)),
+                    ..Default::default()
+                }
+                .into(),
+            ]
+        }
+    }
+}
@@ -0,0 +1,4 @@
+fn main() {
+    // Version and commit for the about dialog
+    shadow_rs::ShadowBuilder::builder().build().unwrap();
+}
@@ -0,0 +1,68 @@
+//! Calls to the daemon made from GTK's main loop. They
+//! run on the tokio runtime and are awaited from there,
+//! so the window never blocks on the socket
+use std::sync::Arc;
+
+use lockinspiel_common::daemon::{DaemonClient, DaemonError, Event, Method};
+use serde::de::DeserializeOwned;
+use tokio::{runtime::Handle, sync::Mutex, sync::mpsc};
+
+#[derive(Clone)]
+pub struct Daemon {
+    runtime: Handle,
+    client: Arc<Mutex<DaemonClient>>,
+}
+
+impl Daemon {
+    pub fn new(runtime: Handle, client: DaemonClient) -> Self {
+        Self {
+            runtime,
+            client: Arc::new(Mutex::new(client)),
+        }
+    }
+
+    pub async fn call<T>(&self, method: Method) -> Result<T, DaemonError>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let client = self.client.clone();
+        self.runtime
+            .spawn(async move { client.lock().await.call(method).await })
+            .await
+            .expect("Call to daemon panicked")
+    }
+
+    /// Events on a connection of their own, starting
+    /// with the state as of subscribing. Ends when the
+    /// daemon goes away
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<Event> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.runtime.spawn(async move {
+            let subscribed = async { DaemonClient::connect().await?.subscribe().await };
+            let (state, mut subscription) = match subscribed.await {
+                Ok(subscribed) => subscribed,
+                Err(e) => {
+                    tracing::error!(?e, "Failed to subscribe to daemon");
+                    return;
+                }
+            };
+            if tx.send(Event::Changed(state)).is_err() {
+                return;
+            }
+            while let Some(event) = subscription.next().await {
+                match event {
+                    Ok(event) => {
+                        if tx.send(event).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!(?e, "Lost connection to daemon");
+                        return;
+                    }
+                }
+            }
+        });
+        rx
+    }
+}
@@ -0,0 +1,49 @@
+use adw::prelude::*;
+use clap::Parser;
+use clap_verbosity_flag::InfoLevel;
+use color_eyre::eyre::{self, Context};
+use gettextrs::{LocaleCategory, setlocale, textdomain};
+use lockinspiel_common::daemon::DaemonClient;
+
+mod daemon;
+mod window;
+
+shadow_rs::shadow!(build);
+
+const APP_ID: &str = "live.Lockinspiel.Gtk";
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+#[command(propagate_version = true)]
+struct Cli {
+    #[command(flatten)]
+    verbose: clap_verbosity_flag::Verbosity<InfoLevel>,
+}
+
+fn main() -> eyre::Result<()> {
+    let cli = Cli::parse();
+
+    lockinspiel_common::install_init_boilerplate(Some(cli.verbose.tracing_level_filter()))?;
+
+    setlocale(LocaleCategory::LcAll, "");
+    textdomain("lockinspiel").wrap_err("Failed to set the text domain")?;
+
+    // GTK runs on the main thread, talking to the
+    // daemon happens over here
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .wrap_err("Failed to start the tokio runtime")?;
+    let client = runtime
+        .block_on(DaemonClient::connect_or_spawn())
+        .wrap_err("Failed to connect to lockinspield")?;
+    let daemon = daemon::Daemon::new(runtime.handle().clone(), client);
+
+    let app = adw::Application::builder().application_id(APP_ID).build();
+    app.connect_startup(|_| window::load_css());
+    app.connect_activate(move |app| window::Window::present(app, daemon.clone()));
+
+    // clap already had the arguments
+    app.run_with_args::<&str>(&[]);
+    Ok(())
+}
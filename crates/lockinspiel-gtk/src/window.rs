@@ -0,0 +1,489 @@
+//! The one window: the timer with its split picker on
+//! one page and the last week of the timesheet on the
+//! other. Everything shown comes from the daemon, the
+//! window only keeps the last state it was sent
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    time::Duration,
+};
+
+use adw::prelude::*;
+use gtk::{gdk, gio, glib};
+use jiff::{SignedDuration, Timestamp, tz::TimeZone};
+use lockinspiel_common::{
+    daemon::{DaemonError, DaemonState, Event, Method},
+    history::Entry,
+    sync::SyncedSplit,
+    timer::TimerState,
+};
+
+use crate::{build, daemon::Daemon};
+
+const HISTORY: SignedDuration = SignedDuration::from_hours(7 * 24);
+
+const CSS: &str = "
+.countdown {
+    font-size: 64pt;
+    font-weight: 300;
+    font-feature-settings: \"tnum\";
+}
+";
+
+pub fn load_css() {
+    let provider = gtk::CssProvider::new();
+    provider.load_from_data(CSS);
+    gtk::style_context_add_provider_for_display(
+        &gdk::Display::default().expect("No display to style"),
+        &provider,
+        gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+    );
+}
+
+pub struct Window {
+    daemon: Daemon,
+    window: adw::ApplicationWindow,
+    toasts: adw::ToastOverlay,
+    split: gtk::Label,
+    countdown: gtk::Label,
+    phase: gtk::Label,
+    progress: gtk::ProgressBar,
+    start: gtk::Button,
+    skip: gtk::Button,
+    split_row: adw::ComboRow,
+    split_names: gtk::StringList,
+    history: gtk::ListBox,
+    state: RefCell<Option<DaemonState>>,
+    splits: RefCell<Vec<SyncedSplit>>,
+    /// Set while the combo row is being filled in from
+    /// the daemon, so it doesn't select a split back
+    syncing: Cell<bool>,
+}
+
+impl Window {
+    pub fn present(app: &adw::Application, daemon: Daemon) {
+        // Activating again, like launching it a second
+        // time, brings the open window up
+        if let Some(window) = app.active_window() {
+            window.present();
+            return;
+        }
+
+        let split = gtk::Label::builder().css_classes(["title-2"]).build();
+        let countdown = gtk::Label::builder()
+            .label("--:--")
+            .css_classes(["countdown"])
+            .build();
+        let phase = gtk::Label::builder().css_classes(["dim-label"]).build();
+        let progress = gtk::ProgressBar::builder().margin_top(6).build();
+
+        let start = gtk::Button::builder()
+            .label("Start")
+            .css_classes(["pill", "suggested-action"])
+            .build();
+        let skip = gtk::Button::builder()
+            .label("Skip")
+            .css_classes(["pill"])
+            .build();
+        let buttons = gtk::Box::builder()
+            .spacing(12)
+            .halign(gtk::Align::Center)
+            .margin_top(12)
+            .build();
+        buttons.append(&start);
+        buttons.append(&skip);
+
+        let split_names = gtk::StringList::new(&[]);
+        let split_row = adw::ComboRow::builder()
+            .title("Split")
+            .model(&split_names)
+            .build();
+        let split_list = gtk::ListBox::builder()
+            .selection_mode(gtk::SelectionMode::None)
+            .css_classes(["boxed-list"])
+            .margin_top(24)
+            .build();
+        split_list.append(&split_row);
+
+        let timer_page = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(6)
+            .valign(gtk::Align::Center)
+            .build();
+        timer_page.append(&split);
+        timer_page.append(&countdown);
+        timer_page.append(&phase);
+        timer_page.append(&progress);
+        timer_page.append(&buttons);
+        timer_page.append(&split_list);
+        let timer_page = adw::Clamp::builder()
+            .maximum_size(420)
+            .margin_start(12)
+            .margin_end(12)
+            .margin_top(12)
+            .margin_bottom(12)
+            .child(&timer_page)
+            .build();
+
+        let history = gtk::ListBox::builder()
+            .selection_mode(gtk::SelectionMode::None)
+            .css_classes(["boxed-list"])
+            .valign(gtk::Align::Start)
+            .build();
+        history.set_placeholder(Some(
+            &gtk::Label::builder()
+                .label("Nothing in the last week")
+                .css_classes(["dim-label"])
+                .margin_top(12)
+                .margin_bottom(12)
+                .build(),
+        ));
+        let history_page = gtk::ScrolledWindow::builder()
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .child(
+                &adw::Clamp::builder()
+                    .maximum_size(600)
+                    .margin_start(12)
+                    .margin_end(12)
+                    .margin_top(12)
+                    .margin_bottom(12)
+                    .child(&history)
+                    .build(),
+            )
+            .build();
+
+        let stack = adw::ViewStack::new();
+        stack.add_titled_with_icon(&timer_page, Some("timer"), "Timer", "alarm-symbolic");
+        stack.add_titled_with_icon(
+            &history_page,
+            Some("history"),
+            "History",
+            "document-open-recent-symbolic",
+        );
+
+        let menu = gio::Menu::new();
+        menu.append(Some("About Lockinspiel"), Some("win.about"));
+        let header = adw::HeaderBar::builder()
+            .title_widget(
+                &adw::ViewSwitcher::builder()
+                    .stack(&stack)
+                    .policy(adw::ViewSwitcherPolicy::Wide)
+                    .build(),
+            )
+            .build();
+        header.pack_end(
+            &gtk::MenuButton::builder()
+                .icon_name("open-menu-symbolic")
+                .menu_model(&menu)
+                .build(),
+        );
+
+        let toolbar = adw::ToolbarView::new();
+        toolbar.add_top_bar(&header);
+        toolbar.set_content(Some(&stack));
+        let toasts = adw::ToastOverlay::builder().child(&toolbar).build();
+
+        let window = adw::ApplicationWindow::builder()
+            .application(app)
+            .title("Lockinspiel")
+            .default_width(480)
+            .default_height(640)
+            .content(&toasts)
+            .build();
+
+        let this = Rc::new(Self {
+            daemon,
+            window,
+            toasts,
+            split,
+            countdown,
+            phase,
+            progress,
+            start,
+            skip,
+            split_row,
+            split_names,
+            history,
+            state: RefCell::new(None),
+            splits: RefCell::new(Vec::new()),
+            syncing: Cell::new(false),
+        });
+        this.connect();
+        this.window.present();
+    }
+
+    /// Handlers on the widgets keep the window alive and
+    /// go when it's closed. The countdown and events only
+    /// hold on weakly, so they stop along with it
+    fn connect(self: &Rc<Self>) {
+        let about = gio::SimpleAction::new("about", None);
+        about.connect_activate({
+            let this = self.clone();
+            move |_, _| this.about()
+        });
+        self.window.add_action(&about);
+
+        self.start.connect_clicked({
+            let this = self.clone();
+            move |_| {
+                let going = this
+                    .state
+                    .borrow()
+                    .as_ref()
+                    .is_some_and(|state| matches!(state.timer.state, TimerState::Going(_)));
+                this.call(if going {
+                    Method::Pause
+                } else {
+                    Method::Start { split: None }
+                });
+            }
+        });
+        self.skip.connect_clicked({
+            let this = self.clone();
+            move |_| this.call(Method::Skip)
+        });
+        self.split_row.connect_selected_notify({
+            let this = self.clone();
+            move |row| {
+                if this.syncing.get() {
+                    return;
+                }
+                let split = this
+                    .splits
+                    .borrow()
+                    .get(row.selected() as usize)
+                    .map(|split| split.name.clone());
+                if let Some(split) = split {
+                    this.call(Method::SelectSplit { split });
+                }
+            }
+        });
+
+        let this = Rc::downgrade(self);
+        // The countdown is worked out locally between
+        // events, the daemon only says when things change
+        glib::timeout_add_local(Duration::from_millis(250), {
+            let this = this.clone();
+            move || match this.upgrade() {
+                Some(this) => {
+                    this.tick(Timestamp::now());
+                    glib::ControlFlow::Continue
+                }
+                None => glib::ControlFlow::Break,
+            }
+        });
+
+        glib::spawn_future_local(async move {
+            let Some(daemon) = this.upgrade().map(|this| this.daemon.clone()) else {
+                return;
+            };
+            let splits = daemon.call::<Vec<SyncedSplit>>(Method::Splits).await;
+            if let Some(this) = this.upgrade() {
+                match splits {
+                    Ok(splits) => this.set_splits(splits),
+                    Err(e) => this.error("Failed to load splits", e),
+                }
+            }
+
+            let mut events = daemon.subscribe();
+            while let Some(event) = events.recv().await {
+                let Some(this) = this.upgrade() else { return };
+                if let Event::Expired { ended, state } = &event {
+                    this.toasts.add_toast(adw::Toast::new(&format!(
+                        "{} is over, {} is up",
+                        ended,
+                        state.timer.current().name
+                    )));
+                }
+                this.set_state(event.state().clone());
+            }
+            if let Some(this) = this.upgrade() {
+                this.toasts.add_toast(
+                    adw::Toast::builder()
+                        .title("Lost connection to lockinspield")
+                        .timeout(0)
+                        .build(),
+                );
+                this.start.set_sensitive(false);
+                this.skip.set_sensitive(false);
+                this.split_row.set_sensitive(false);
+            }
+        });
+    }
+
+    /// Calls the daemon in the background. The state it
+    /// leaves comes back as an event, so only errors are
+    /// dealt with here
+    fn call(&self, method: Method) {
+        let daemon = self.daemon.clone();
+        let toasts = self.toasts.clone();
+        glib::spawn_future_local(async move {
+            if let Err(e) = daemon.call::<DaemonState>(method).await {
+                tracing::warn!(?e, "Call to daemon failed");
+                toasts.add_toast(adw::Toast::new(&message(&e)));
+            }
+        });
+    }
+
+    fn error(&self, context: &str, e: DaemonError) {
+        tracing::error!(?e, "{}", context);
+        self.toasts.add_toast(adw::Toast::new(context));
+    }
+
+    fn set_splits(&self, splits: Vec<SyncedSplit>) {
+        let names: Vec<&str> = splits.iter().map(|split| split.name.as_str()).collect();
+        self.syncing.set(true);
+        self.split_names
+            .splice(0, self.split_names.n_items(), &names);
+        self.syncing.set(false);
+        *self.splits.borrow_mut() = splits;
+        self.select_current_split();
+    }
+
+    fn select_current_split(&self) {
+        let state = self.state.borrow();
+        let Some(state) = state.as_ref() else { return };
+        let position = self
+            .splits
+            .borrow()
+            .iter()
+            .position(|split| split.name == state.split);
+        if let Some(position) = position {
+            self.syncing.set(true);
+            self.split_row.set_selected(position as u32);
+            self.syncing.set(false);
+        }
+    }
+
+    fn set_state(self: &Rc<Self>, state: DaemonState) {
+        let old = self.state.replace(Some(state.clone()));
+        let going = matches!(state.timer.state, TimerState::Going(_));
+
+        self.split.set_label(&state.split);
+        self.start.set_label(if going { "Pause" } else { "Start" });
+        // Same rules as the daemon, so the buttons
+        // don't offer what would be refused
+        self.skip.set_sensitive(!going);
+        self.split_row.set_sensitive(!going);
+        self.select_current_split();
+        self.tick(Timestamp::now());
+
+        // Rows only come and go when the timer does
+        let timesheet_changed = old.is_none_or(|old| {
+            old.timer.state != state.timer.state
+                || old.timer.timer_on != state.timer.timer_on
+                || old.split != state.split
+                || old.tags != state.tags
+        });
+        if timesheet_changed {
+            self.load_history();
+        }
+    }
+
+    fn tick(&self, now: Timestamp) {
+        let state = self.state.borrow();
+        let Some(state) = state.as_ref() else { return };
+        let timer = &state.timer;
+        let current = timer.current();
+
+        let secs = timer.remaining(now).as_secs().max(0);
+        self.countdown
+            .set_label(&format!("{}:{:02}", secs / 60, secs % 60));
+
+        let mut phase = format!(
+            "{}, {} of {}",
+            current.name,
+            timer.timer_on + 1,
+            timer.timers.len()
+        );
+        if !state.tags.is_empty() {
+            phase.push_str(" · ");
+            phase.push_str(&state.tags.join(", "));
+        }
+        self.phase.set_label(&phase);
+
+        let total = current.len.as_secs_f64();
+        self.progress.set_fraction(if total > 0.0 {
+            (1.0 - secs as f64 / total).clamp(0.0, 1.0)
+        } else {
+            1.0
+        });
+    }
+
+    fn load_history(self: &Rc<Self>) {
+        let this = Rc::downgrade(self);
+        let daemon = self.daemon.clone();
+        glib::spawn_future_local(async move {
+            let since = Timestamp::now()
+                .checked_sub(HISTORY)
+                .unwrap_or(Timestamp::UNIX_EPOCH);
+            let history = daemon.call::<Vec<Entry>>(Method::History { since }).await;
+            let Some(this) = this.upgrade() else { return };
+            match history {
+                Ok(history) => this.set_history(&history),
+                Err(e) => this.error("Failed to load history", e),
+            }
+        });
+    }
+
+    fn set_history(&self, history: &[Entry]) {
+        self.history.remove_all();
+
+        let now = Timestamp::now();
+        let tz = TimeZone::system();
+        for entry in history.iter().rev() {
+            let length = entry.length(now).as_secs();
+            let mut subtitle = format!(
+                "{} – {}",
+                entry
+                    .start_time
+                    .to_zoned(tz.clone())
+                    .strftime("%a %b %-d %H:%M"),
+                entry.end_time.to_zoned(tz.clone()).strftime("%H:%M"),
+            );
+            if !entry.tags.is_empty() {
+                subtitle.push_str(" · ");
+                subtitle.push_str(&entry.tags.join(", "));
+            }
+
+            let row = adw::ActionRow::builder()
+                .title(format!(
+                    "{} · {}",
+                    if entry.work { "Work" } else { "Break" },
+                    entry.split.as_deref().unwrap_or("No split")
+                ))
+                .subtitle(subtitle)
+                .build();
+            row.add_suffix(
+                &gtk::Label::builder()
+                    .label(format!("{}:{:02}", length / 60, length % 60))
+                    .css_classes(["numeric"])
+                    .build(),
+            );
+            // Paused or skipped before running out
+            if !entry.completed && entry.end_time <= now {
+                row.add_css_class("dim-label");
+            }
+            self.history.append(&row);
+        }
+    }
+
+    fn about(&self) {
+        adw::AboutDialog::builder()
+            .application_name("Lockinspiel")
+            .application_icon("alarm-symbolic")
+            .version(build::PKG_VERSION)
+            .comments(format!("Built from {}", build::SHORT_COMMIT))
+            .website("https://lockinspiel.live")
+            .license_type(gtk::License::Gpl30)
+            .build()
+            .present(Some(&self.window));
+    }
+}
+
+fn message(e: &DaemonError) -> String {
+    match e {
+        DaemonError::Rpc { message, .. } => message.clone(),
+        e => e.to_string(),
+    }
+}
@@ -0,0 +1,55 @@
+-- Users are identified by their Supabase user ID
+
+CREATE TABLE synced_timesheet(
+    user_id VARCHAR NOT NULL,
+    timesheet_group BIGINT NOT NULL,
+    start_time TIMESTAMP_MS NOT NULL,
+    end_time TIMESTAMP_MS NOT NULL,
+    work BOOLEAN NOT NULL,
+    PRIMARY KEY (user_id, start_time)
+);
+
+CREATE TABLE synced_tag(
+    user_id VARCHAR NOT NULL,
+    timesheet_group BIGINT NOT NULL,
+    tag VARCHAR NOT NULL,
+    PRIMARY KEY (user_id, timesheet_group, tag)
+);
+
+CREATE TABLE synced_split(
+    user_id VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    description VARCHAR,
+    -- JSON array of `SplitTimer`s
+    timers VARCHAR NOT NULL,
+    PRIMARY KEY (user_id, name)
+);
+
+CREATE TABLE synced_group(
+    user_id VARCHAR NOT NULL,
+    timesheet_group BIGINT NOT NULL,
+    split VARCHAR NOT NULL,
+    PRIMARY KEY (user_id, timesheet_group)
+);
+
+-- Codes are reused once a room closes, so a
+-- room is its code and when it opened
+CREATE TABLE room(
+    code VARCHAR NOT NULL,
+    opened_at TIMESTAMP_MS NOT NULL,
+    closed_at TIMESTAMP_MS,
+    owner VARCHAR NOT NULL,
+    split VARCHAR NOT NULL,
+    PRIMARY KEY (code, opened_at)
+);
+
+CREATE TABLE room_event(
+    code VARCHAR NOT NULL,
+    opened_at TIMESTAMP_MS NOT NULL,
+    at TIMESTAMP_MS NOT NULL,
+    user_id VARCHAR NOT NULL,
+    -- join, leave, start, pause or skip
+    event VARCHAR NOT NULL,
+    -- JSON `RoomState` after the event
+    state VARCHAR NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE synced_timesheet ADD COLUMN completed BOOLEAN DEFAULT false;
@@ -0,0 +1,26 @@
+-- Timesheet groups are numbered by each device on its
+-- own, so two devices of one user would clash over them.
+-- Rows carry their split and tags instead, keyed like
+-- the rows themselves
+ALTER TABLE synced_timesheet ADD COLUMN split VARCHAR;
+
+UPDATE synced_timesheet SET split = g.split
+FROM synced_group g
+WHERE g.user_id = synced_timesheet.user_id
+    AND g.timesheet_group = synced_timesheet.timesheet_group;
+
+CREATE TABLE synced_row_tag(
+    user_id VARCHAR NOT NULL,
+    start_time TIMESTAMP_MS NOT NULL,
+    tag VARCHAR NOT NULL,
+    PRIMARY KEY (user_id, start_time, tag)
+);
+
+INSERT OR IGNORE INTO synced_row_tag
+SELECT t.user_id, t.start_time, s.tag
+FROM synced_timesheet t
+JOIN synced_tag s ON s.user_id = t.user_id AND s.timesheet_group = t.timesheet_group;
+
+DROP TABLE synced_tag;
+DROP TABLE synced_group;
+ALTER TABLE synced_row_tag RENAME TO synced_tag;
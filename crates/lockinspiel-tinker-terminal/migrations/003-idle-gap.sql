@@ -0,0 +1,8 @@
+-- Time users were away from the computer while a session
+-- was going. Rows it overlaps don't count it as worked
+CREATE TABLE synced_idle_gap(
+    user_id VARCHAR NOT NULL,
+    start_time TIMESTAMP_MS NOT NULL,
+    end_time TIMESTAMP_MS NOT NULL,
+    PRIMARY KEY (user_id, start_time)
+);
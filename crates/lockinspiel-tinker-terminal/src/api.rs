@@ -0,0 +1,142 @@
+//! JSON API over the timesheets users have synced,
+//! for scripts and integrations. Everything is
+//! scoped to the user the access token belongs to.
+use axum::{
+    Json, Router,
+    extract::{FromRef, State},
+    http::StatusCode,
+    routing::get,
+};
+use color_eyre::eyre::eyre;
+use jiff::Timestamp;
+use lockinspiel_common::{history::TagTotal, sync::SyncedSplit};
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    auth::{Auth, AuthUser},
+    error::{self, WithStatusCode},
+    extract::Query,
+    store::{Page, Session, Store, TimeRange},
+};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct Pagination {
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_limit() -> usize {
+    DEFAULT_LIMIT
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TimesheetFilter {
+    tag: Option<String>,
+    split: Option<String>,
+}
+
+pub fn router<S>() -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+    Store: FromRef<S>,
+    Auth: FromRef<S>,
+{
+    Router::new().nest(
+        "/api/v1",
+        Router::new()
+            .route("/timesheets", get(timesheets))
+            .route("/splits", get(splits))
+            .route("/tags", get(tags)),
+    )
+}
+
+impl Pagination {
+    fn check(&self) -> error::Result<()> {
+        if self.limit == 0 || self.limit > MAX_LIMIT {
+            return Err(error::Error(
+                StatusCode::BAD_REQUEST,
+                eyre!("limit must be between 1 and {}", MAX_LIMIT),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn check_range(range: &TimeRange) -> error::Result<()> {
+    if range.is_backwards() {
+        return Err(error::Error(
+            StatusCode::BAD_REQUEST,
+            eyre!("from must not be after to"),
+        ));
+    }
+    Ok(())
+}
+
+#[instrument(skip(store, user))]
+async fn timesheets(
+    State(store): State<Store>,
+    user: AuthUser,
+    Query(range): Query<TimeRange>,
+    Query(filter): Query<TimesheetFilter>,
+    Query(pagination): Query<Pagination>,
+) -> error::Result<Json<Page<Session>>> {
+    check_range(&range)?;
+    pagination.check()?;
+    let page = store
+        .timesheets(
+            user.id,
+            range,
+            filter.tag,
+            filter.split,
+            pagination.offset,
+            pagination.limit,
+        )
+        .await
+        .with_status_code(StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(page))
+}
+
+#[instrument(skip(store, user))]
+async fn splits(
+    State(store): State<Store>,
+    user: AuthUser,
+    Query(pagination): Query<Pagination>,
+) -> error::Result<Json<Page<SyncedSplit>>> {
+    pagination.check()?;
+    let page = store
+        .splits(user.id, pagination.offset, pagination.limit)
+        .await
+        .with_status_code(StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(page))
+}
+
+#[instrument(skip(store, user))]
+async fn tags(
+    State(store): State<Store>,
+    user: AuthUser,
+    Query(range): Query<TimeRange>,
+    Query(pagination): Query<Pagination>,
+) -> error::Result<Json<Page<TagTotal>>> {
+    check_range(&range)?;
+    pagination.check()?;
+    let page = store
+        .tags(
+            user.id,
+            range,
+            Timestamp::now(),
+            pagination.offset,
+            pagination.limit,
+        )
+        .await
+        .with_status_code(StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(page))
+}
@@ -0,0 +1,554 @@
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{AUTHORIZATION, COOKIE, WWW_AUTHENTICATE},
+        request::Parts,
+    },
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::{self, Context, eyre};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation,
+    jwk::{Jwk, JwkSet},
+};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use crate::error::{self, WithStatusCode};
+
+/// Supabase puts this in `aud` for signed in users
+const AUDIENCE: &str = "authenticated";
+
+/// Cookie the dashboard keeps the access token in,
+/// since browsers can't send a bearer token by themselves
+pub const TOKEN_COOKIE: &str = "lockinspiel_access_token";
+
+/// Tokens signed with a key we don't know trigger a JWKS
+/// refetch, but not more often than this
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: uuid::Uuid,
+    email: Option<String>,
+}
+
+struct JwksCache {
+    keys: JwkSet,
+    fetched_at: Option<Instant>,
+}
+
+enum Keys {
+    /// Nothing to check tokens with, so nobody gets in
+    Unconfigured,
+    /// The project's JWT secret, for HS256 tokens
+    Secret(DecodingKey),
+    /// The project's public signing keys
+    Jwks {
+        url: String,
+        client: reqwest::Client,
+        cache: RwLock<JwksCache>,
+    },
+}
+
+/// Verifies access tokens issued by Supabase Auth
+#[derive(Clone)]
+pub struct Auth(Arc<Keys>);
+
+impl Auth {
+    /// For servers only there for `/time_sync`. Everything
+    /// that needs a user answers 503
+    pub fn unconfigured() -> Self {
+        Self(Arc::new(Keys::Unconfigured))
+    }
+
+    pub fn secret(secret: &str) -> Self {
+        Self(Arc::new(Keys::Secret(DecodingKey::from_secret(
+            secret.as_bytes(),
+        ))))
+    }
+
+    /// Verifies tokens with the keys served at `url`,
+    /// usually `<SUPABASE_URL>/auth/v1/.well-known/jwks.json`
+    pub fn jwks(url: String) -> Self {
+        Self(Arc::new(Keys::Jwks {
+            url,
+            client: reqwest::Client::new(),
+            cache: RwLock::new(JwksCache {
+                keys: JwkSet { keys: Vec::new() },
+                fetched_at: None,
+            }),
+        }))
+    }
+
+    /// Fails with 401 for tokens that don't check out, and
+    /// something else when it's us that can't check them
+    async fn verify(&self, token: &str) -> error::Result<Claims> {
+        let header = jsonwebtoken::decode_header(token)
+            .wrap_err("Malformed access token")
+            .with_status_code(StatusCode::UNAUTHORIZED)?;
+        // The key decides the algorithm, never the token
+        let (key, algorithm) = match &*self.0 {
+            Keys::Unconfigured => return Err(unconfigured()),
+            Keys::Secret(key) => (key.clone(), Algorithm::HS256),
+            Keys::Jwks { url, client, cache } => {
+                let kid = header
+                    .kid
+                    .as_deref()
+                    .ok_or_else(|| eyre!("Access token has no key ID"))
+                    .with_status_code(StatusCode::UNAUTHORIZED)?;
+                jwks_key(url, client, cache, kid).await?
+            }
+        };
+        if header.alg != algorithm {
+            return Err(error::Error(
+                StatusCode::UNAUTHORIZED,
+                eyre!("Access token isn't signed with {:?}", algorithm),
+            ));
+        }
+
+        let mut validation = Validation::new(algorithm);
+        validation.set_audience(&[AUDIENCE]);
+        let token = jsonwebtoken::decode::<Claims>(token, &key, &validation)
+            .wrap_err("Invalid access token")
+            .with_status_code(StatusCode::UNAUTHORIZED)?;
+
+        Ok(token.claims)
+    }
+}
+
+async fn jwks_key(
+    url: &str,
+    client: &reqwest::Client,
+    cache: &RwLock<JwksCache>,
+    kid: &str,
+) -> error::Result<(DecodingKey, Algorithm)> {
+    if let Some(jwk) = cache.read().await.keys.find(kid) {
+        return decoding_key(jwk).with_status_code(StatusCode::UNAUTHORIZED);
+    }
+
+    // Supabase may have rotated its keys since we last looked
+    let mut cache = cache.write().await;
+    let stale = cache
+        .fetched_at
+        .is_none_or(|fetched_at| fetched_at.elapsed() >= JWKS_REFRESH_INTERVAL);
+    if stale {
+        cache.keys = client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .wrap_err("Failed to fetch JWKS")
+            .with_status_code(StatusCode::BAD_GATEWAY)?
+            .json()
+            .await
+            .wrap_err("Failed to parse JWKS")
+            .with_status_code(StatusCode::BAD_GATEWAY)?;
+        cache.fetched_at = Some(Instant::now());
+        tracing::info!(keys = cache.keys.keys.len(), "Fetched JWKS");
+    }
+
+    let jwk = cache
+        .keys
+        .find(kid)
+        .ok_or_else(|| eyre!("Access token is signed with an unknown key"))
+        .with_status_code(StatusCode::UNAUTHORIZED)?;
+    decoding_key(jwk).with_status_code(StatusCode::UNAUTHORIZED)
+}
+
+/// The key along with the algorithm it's for. Keys that
+/// don't say aren't trusted with whatever a token claims
+fn decoding_key(jwk: &Jwk) -> eyre::Result<(DecodingKey, Algorithm)> {
+    let algorithm = jwk
+        .common
+        .key_algorithm
+        .ok_or_else(|| eyre!("Signing key doesn't say its algorithm"))?;
+    let algorithm = Algorithm::from_str(&algorithm.to_string())
+        .wrap_err_with(|| format!("Signing key is for {}, which can't sign tokens", algorithm))?;
+    let key = DecodingKey::from_jwk(jwk).wrap_err("Unusable signing key")?;
+    Ok((key, algorithm))
+}
+
+/// A user signed in with Supabase. Extracting this
+/// rejects requests without a valid access token
+/// in their `Authorization` header or token cookie,
+/// and everything when auth isn't set up
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: uuid::Uuid,
+    pub email: Option<String>,
+}
+
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+    Auth: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let auth = Auth::from_ref(state);
+        if let Keys::Unconfigured = *auth.0 {
+            return Err(unconfigured().into_response());
+        }
+        let token = bearer_token(&parts.headers)
+            .or_else(|| cookie_token(&parts.headers))
+            .ok_or_else(|| {
+                rejection(error::Error(
+                    StatusCode::UNAUTHORIZED,
+                    eyre!("Missing bearer token"),
+                ))
+            })?;
+        let claims = auth.verify(token).await.map_err(rejection)?;
+
+        Ok(AuthUser {
+            id: claims.sub,
+            email: claims.email,
+        })
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+fn cookie_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| {
+            let (name, value) = cookie.trim().split_once('=')?;
+            (name == TOKEN_COOKIE).then_some(value)
+        })
+}
+
+fn unconfigured() -> error::Error {
+    error::Error(
+        StatusCode::SERVICE_UNAVAILABLE,
+        eyre!("This server isn't set up to sign anybody in"),
+    )
+}
+
+/// Only a bad token is worth asking for another
+fn rejection(error: error::Error) -> Response {
+    let unauthorized = error.0 == StatusCode::UNAUTHORIZED;
+    let mut response = error.into_response();
+    if unauthorized {
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use axum::{Json, Router, http::Request, routing::get};
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde::Serialize;
+
+    use super::*;
+
+    const SECRET: &str = "super-secret-jwt-token-with-at-least-32-characters";
+
+    /// Served as an HS256 key in the JWKS tests, with
+    /// `JWKS_SECRET_B64` being it base64url encoded
+    const JWKS_SECRET: &str = "jwks-secret-for-the-tests-of-at-least-32-b";
+    const JWKS_SECRET_B64: &str = "andrcy1zZWNyZXQtZm9yLXRoZS10ZXN0cy1vZi1hdC1sZWFzdC0zMi1i";
+
+    #[derive(Serialize)]
+    struct TestClaims {
+        sub: uuid::Uuid,
+        email: &'static str,
+        aud: &'static str,
+        exp: u64,
+    }
+
+    fn token(secret: &str, aud: &'static str, exp: jiff::Timestamp) -> (uuid::Uuid, String) {
+        signed(Header::new(Algorithm::HS256), secret, aud, exp)
+    }
+
+    /// An HS256 token naming the JWKS key `kid`
+    fn jwks_token(kid: &str) -> (uuid::Uuid, String) {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(kid.to_owned());
+        signed(header, JWKS_SECRET, AUDIENCE, in_an_hour())
+    }
+
+    fn signed(
+        header: Header,
+        secret: &str,
+        aud: &'static str,
+        exp: jiff::Timestamp,
+    ) -> (uuid::Uuid, String) {
+        let sub = uuid::Uuid::new_v4();
+        let claims = TestClaims {
+            sub,
+            email: "tinker@example.com",
+            aud,
+            exp: exp.as_second() as u64,
+        };
+        let token = jsonwebtoken::encode(
+            &header,
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap();
+        (sub, token)
+    }
+
+    async fn extract(token: &str) -> Result<AuthUser, Response> {
+        extract_with(&Auth::secret(SECRET), token).await
+    }
+
+    async fn extract_with(auth: &Auth, token: &str) -> Result<AuthUser, Response> {
+        let (mut parts, ()) = Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {}", token))
+            .body(())
+            .unwrap()
+            .into_parts();
+        AuthUser::from_request_parts(&mut parts, auth).await
+    }
+
+    /// The JWKS key `kid`, signing with `alg` if given
+    fn oct_key(kid: &str, alg: Option<&str>) -> serde_json::Value {
+        let mut key = serde_json::json!({
+            "kty": "oct",
+            "kid": kid,
+            "k": JWKS_SECRET_B64,
+        });
+        if let Some(alg) = alg {
+            key["alg"] = alg.into();
+        }
+        key
+    }
+
+    /// Serves `keys` as a JWKS, counting how often it's fetched
+    struct JwksServer {
+        url: String,
+        keys: Arc<Mutex<Vec<serde_json::Value>>>,
+        fetches: Arc<AtomicUsize>,
+    }
+
+    impl JwksServer {
+        async fn start(keys: Vec<serde_json::Value>) -> Self {
+            let keys = Arc::new(Mutex::new(keys));
+            let fetches = Arc::new(AtomicUsize::new(0));
+            let app = Router::new().route(
+                "/jwks.json",
+                get({
+                    let keys = keys.clone();
+                    let fetches = fetches.clone();
+                    move || async move {
+                        fetches.fetch_add(1, Ordering::Relaxed);
+                        Json(serde_json::json!({ "keys": *keys.lock().unwrap() }))
+                    }
+                }),
+            );
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/jwks.json", listener.local_addr().unwrap());
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+            Self { url, keys, fetches }
+        }
+
+        fn fetches(&self) -> usize {
+            self.fetches.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Makes it look like the JWKS was fetched long
+    /// enough ago to be fetched again
+    async fn age_jwks(auth: &Auth) {
+        let Keys::Jwks { cache, .. } = &*auth.0 else {
+            unreachable!();
+        };
+        let long_ago = Instant::now().checked_sub(JWKS_REFRESH_INTERVAL).unwrap();
+        cache.write().await.fetched_at = Some(long_ago);
+    }
+
+    fn in_an_hour() -> jiff::Timestamp {
+        jiff::Timestamp::now() + jiff::SignedDuration::from_hours(1)
+    }
+
+    #[tokio::test]
+    async fn valid_token() {
+        let (sub, token) = token(SECRET, AUDIENCE, in_an_hour());
+        let user = extract(&token).await.unwrap();
+        assert_eq!(user.id, sub);
+        assert_eq!(user.email.as_deref(), Some("tinker@example.com"));
+    }
+
+    #[tokio::test]
+    async fn valid_token_in_cookie() {
+        let (sub, token) = token(SECRET, AUDIENCE, in_an_hour());
+        let (mut parts, ()) = Request::builder()
+            .header(COOKIE, format!("theme=dark; {}={}", TOKEN_COOKIE, token))
+            .body(())
+            .unwrap()
+            .into_parts();
+        let user = AuthUser::from_request_parts(&mut parts, &Auth::secret(SECRET))
+            .await
+            .unwrap();
+        assert_eq!(user.id, sub);
+    }
+
+    #[tokio::test]
+    async fn expired_token() {
+        // Past the default minute of leeway
+        let (_, token) = token(
+            SECRET,
+            AUDIENCE,
+            jiff::Timestamp::now() - jiff::SignedDuration::from_mins(5),
+        );
+        let response = extract(&token).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn wrong_audience() {
+        let (_, token) = token(SECRET, "anon", in_an_hour());
+        let response = extract(&token).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn wrong_secret() {
+        let (_, token) = token(
+            "some-other-projects-secret-that-is-long-enough",
+            AUDIENCE,
+            in_an_hour(),
+        );
+        let response = extract(&token).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn missing_token() {
+        let (mut parts, ()) = Request::builder().body(()).unwrap().into_parts();
+        let response = AuthUser::from_request_parts(&mut parts, &Auth::secret(SECRET))
+            .await
+            .unwrap_err();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer");
+    }
+
+    #[tokio::test]
+    async fn none_algorithm() {
+        // `{"alg":"none","typ":"JWT"}`, with no signature
+        let (_, token) = token(SECRET, AUDIENCE, in_an_hour());
+        let claims = token.split('.').nth(1).unwrap();
+        let token = format!("eyJhbGciOiJub25lIiwidHlwIjoiSldUIn0.{}.", claims);
+        let response = extract(&token).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let server = JwksServer::start(vec![oct_key("k1", Some("HS256"))]).await;
+        let auth = Auth::jwks(server.url.clone());
+        let response = extract_with(&auth, &token).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn jwks_valid_token() {
+        let server = JwksServer::start(vec![oct_key("k1", Some("HS256"))]).await;
+        let auth = Auth::jwks(server.url.clone());
+        let (sub, token) = jwks_token("k1");
+        assert_eq!(extract_with(&auth, &token).await.unwrap().id, sub);
+        // Known keys come from the cache
+        extract_with(&auth, &token).await.unwrap();
+        assert_eq!(server.fetches(), 1);
+    }
+
+    #[tokio::test]
+    async fn jwks_algorithm_mismatch() {
+        // An RSA public key, which an HS256 token could
+        // otherwise be "signed" with
+        let server = JwksServer::start(vec![serde_json::json!({
+            "kty": "RSA",
+            "kid": "k1",
+            "alg": "RS256",
+            "n": "AQAB",
+            "e": "AQAB",
+        })])
+        .await;
+        let auth = Auth::jwks(server.url.clone());
+        let (_, token) = jwks_token("k1");
+        let response = extract_with(&auth, &token).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn jwks_key_without_algorithm() {
+        let server = JwksServer::start(vec![oct_key("k1", None)]).await;
+        let auth = Auth::jwks(server.url.clone());
+        let (_, token) = jwks_token("k1");
+        let response = extract_with(&auth, &token).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn jwks_refetches_for_unknown_keys() {
+        let server = JwksServer::start(vec![oct_key("k1", Some("HS256"))]).await;
+        let auth = Auth::jwks(server.url.clone());
+        extract_with(&auth, &jwks_token("k1").1).await.unwrap();
+        assert_eq!(server.fetches(), 1);
+
+        // Rotated, but it was only just fetched
+        server
+            .keys
+            .lock()
+            .unwrap()
+            .push(oct_key("k2", Some("HS256")));
+        let (sub, token) = jwks_token("k2");
+        let response = extract_with(&auth, &token).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(server.fetches(), 1);
+
+        age_jwks(&auth).await;
+        assert_eq!(extract_with(&auth, &token).await.unwrap().id, sub);
+        assert_eq!(server.fetches(), 2);
+
+        // Still unknown after the refetch, which isn't
+        // repeated right away
+        let response = extract_with(&auth, &jwks_token("k3").1).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(server.fetches(), 2);
+    }
+
+    #[tokio::test]
+    async fn jwks_unreachable() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/jwks.json", listener.local_addr().unwrap());
+        drop(listener);
+        let auth = Auth::jwks(url);
+        let response = extract_with(&auth, &jwks_token("k1").1).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert!(!response.headers().contains_key(WWW_AUTHENTICATE));
+    }
+
+    #[tokio::test]
+    async fn unconfigured_auth() {
+        let (_, token) = token(SECRET, AUDIENCE, in_an_hour());
+        let response = extract_with(&Auth::unconfigured(), &token)
+            .await
+            .unwrap_err();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,273 @@
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    extract::{FromRef, State},
+    http::{StatusCode, header::SET_COOKIE},
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
+use jiff::{SignedDuration, Timestamp, tz::TimeZone};
+use lockinspiel_common::timer::TimerState;
+use maud::{DOCTYPE, Markup, html};
+use serde::Deserialize;
+use supabase_auth::models::AuthClient;
+use tracing::instrument;
+
+use crate::{
+    auth::{Auth, AuthUser, TOKEN_COOKIE},
+    error::{self, WithStatusCode},
+    extract::Form,
+    rooms::Rooms,
+    store::Store,
+};
+
+/// Sessions shown on the dashboard
+const HISTORY_LEN: usize = 50;
+
+#[derive(Clone)]
+pub struct Dashboard {
+    /// Signs people in with their password. `None`
+    /// when Supabase isn't configured
+    pub login: Option<Arc<AuthClient>>,
+    /// Only send the token cookie over HTTPS
+    pub secure_cookies: bool,
+    pub time_zone: TimeZone,
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    email: String,
+    password: String,
+}
+
+pub fn router<S>() -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+    Dashboard: FromRef<S>,
+    Store: FromRef<S>,
+    Rooms: FromRef<S>,
+    Auth: FromRef<S>,
+{
+    Router::new()
+        .route("/login", get(login_page).post(login))
+        .route("/logout", post(logout))
+        .route("/dashboard", get(dashboard))
+}
+
+/// Wraps `body` in a page. Pages with a `refresh`
+/// reload themselves that many seconds later
+fn page(title: &str, refresh: Option<u32>, body: Markup) -> Markup {
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                meta charset="utf8";
+                @if let Some(refresh) = refresh {
+                    meta http-equiv="refresh" content=(refresh);
+                }
+                meta name="viewport" content="width=device-width, initial-scale=1";
+                title { (title) " · Lockinspiel" }
+                style {
+                    "body { font-family: sans-serif; max-width: 48rem; margin: 2rem auto; padding: 0 1rem; }"
+                    "table { border-collapse: collapse; width: 100%; }"
+                    "td, th { text-align: left; padding: 0.25rem 0.5rem; border-bottom: 1px solid #ccc; }"
+                    ".stats { display: flex; gap: 2rem; }"
+                    ".error { color: #b00020; }"
+                }
+            }
+            body { (body) }
+        }
+    }
+}
+
+fn hours_minutes(duration: SignedDuration) -> String {
+    let mins = duration.as_mins().max(0);
+    format!("{}h {:02}m", mins / 60, mins % 60)
+}
+
+fn login_form(error: Option<&str>) -> Markup {
+    page(
+        "Sign In",
+        None,
+        html! {
+            h1 { "Sign In" }
+            @if let Some(error) = error {
+                p.error { (error) }
+            }
+            form method="post" action="/login" {
+                p { label { "Email " input type="email" name="email" required; } }
+                p { label { "Password " input type="password" name="password" required; } }
+                button type="submit" { "Sign In" }
+            }
+        },
+    )
+}
+
+#[instrument]
+async fn login_page() -> Markup {
+    login_form(None)
+}
+
+#[instrument(skip_all)]
+async fn login(State(dashboard): State<Dashboard>, Form(form): Form<LoginForm>) -> Response {
+    let Some(client) = &dashboard.login else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            login_form(Some("Signing in isn't set up on this server")),
+        )
+            .into_response();
+    };
+
+    match client.login_with_email(&form.email, &form.password).await {
+        Ok(session) => {
+            let cookie = format!(
+                "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}{}",
+                TOKEN_COOKIE,
+                session.access_token,
+                session.expires_in,
+                if dashboard.secure_cookies {
+                    "; Secure"
+                } else {
+                    ""
+                }
+            );
+            ([(SET_COOKIE, cookie)], Redirect::to("/dashboard")).into_response()
+        }
+        Err(e) => {
+            tracing::info!(?e, "Dashboard sign in failed");
+            (
+                StatusCode::UNAUTHORIZED,
+                login_form(Some("Wrong email or password")),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[instrument]
+async fn logout() -> Response {
+    let cookie = format!(
+        "{}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0",
+        TOKEN_COOKIE
+    );
+    ([(SET_COOKIE, cookie)], Redirect::to("/login")).into_response()
+}
+
+#[instrument(skip_all)]
+async fn dashboard(
+    State(dashboard): State<Dashboard>,
+    State(store): State<Store>,
+    State(rooms): State<Rooms>,
+    user: Result<AuthUser, Response>,
+) -> error::Result<Response> {
+    let user = match user {
+        Ok(user) => user,
+        Err(response) if response.status() == StatusCode::UNAUTHORIZED => {
+            return Ok(Redirect::to("/login").into_response());
+        }
+        // Like not being able to get at the signing keys,
+        // signing in again wouldn't help
+        Err(response) => return Ok(response),
+    };
+
+    let now = Timestamp::now();
+    let tz = &dashboard.time_zone;
+    let stats = store
+        .stats(user.id, now, tz.clone())
+        .await
+        .with_status_code(StatusCode::INTERNAL_SERVER_ERROR)?;
+    let history = store
+        .history(user.id, HISTORY_LEN)
+        .await
+        .with_status_code(StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rooms = rooms.for_user(user.id);
+    let time = |timestamp: Timestamp| {
+        timestamp
+            .to_zoned(tz.clone())
+            .strftime("%a %b %-d, %H:%M")
+            .to_string()
+    };
+
+    let body = html! {
+        form method="post" action="/logout" style="float: right" {
+            button type="submit" { "Sign Out" }
+        }
+        h1 { "Lockinspiel" }
+        @if let Some(email) = &user.email {
+            p { "Signed in as " (email) }
+        }
+
+        div.stats {
+            div { h3 { "Today" } p { (hours_minutes(stats.totals.today)) } }
+            div { h3 { "Last 7 days" } p { (hours_minutes(stats.totals.week)) } }
+            div { h3 { "Streak" } p { (stats.totals.streak) " days" } }
+        }
+
+        h2 { "Rooms" }
+        @if rooms.is_empty() {
+            p { "You're not in any rooms." }
+        } @else {
+            table {
+                tr { th { "Code" } th { "Split" } th { "Timer" } th { "Members" } }
+                @for room in &rooms {
+                    tr {
+                        td { code { (room.code) } }
+                        td { (room.split) }
+                        td {
+                            (room.timer.current().name) ": "
+                            @match room.timer.state {
+                                TimerState::Going(end_time) => {
+                                    (hours_minutes(room.timer.remaining(now))) " left, ends at " (time(end_time))
+                                }
+                                TimerState::Paused(remaining) => {
+                                    "paused with " (hours_minutes(remaining)) " left"
+                                }
+                            }
+                        }
+                        td { (room.members) }
+                    }
+                }
+            }
+        }
+
+        h2 { "Tags" }
+        @if stats.tags.is_empty() {
+            p { "No tagged work yet." }
+        } @else {
+            table {
+                tr { th { "Tag" } th { "Total" } }
+                @for total in &stats.tags {
+                    tr { td { (total.tag) } td { (hours_minutes(total.worked)) } }
+                }
+            }
+        }
+
+        h2 { "History" }
+        @if history.is_empty() {
+            p { "Nothing synced yet. Sign in on the app and sync to see your history here." }
+        } @else {
+            table {
+                tr { th { "Started" } th { "Length" } th { "" } th { "Tags" } }
+                @for session in &history {
+                    tr {
+                        td { (time(session.row.start_time.0)) }
+                        td {
+                            (hours_minutes(session.row.start_time.0.duration_until(session.row.end_time.0) - session.idle))
+                            @if session.row.end_time.0 > now {
+                                " (running)"
+                            } @else if !session.row.completed {
+                                " (cut short)"
+                            }
+                        }
+                        td { @if session.row.work { "Work" } @else { "Break" } }
+                        td { (session.tags.join(", ")) }
+                    }
+                }
+            }
+        }
+    };
+
+    // Refreshing keeps room timers and running sessions current
+    Ok(page("Dashboard", Some(30), body).into_response())
+}
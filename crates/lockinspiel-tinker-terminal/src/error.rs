@@ -3,22 +3,54 @@
 //!
 //! This crate uses `eyre` to capture the error,
 //! the error is then returned to the browser or
-//! whatever it is, it's then nicely formatted to
-//! a webpage using `ansi_to_html`
+//! whatever it is. `negotiate` picks how: browsers
+//! get a webpage nicely formatted with `ansi_to_html`,
+//! everything else gets RFC 7807 `application/problem+json`.
+//! Every error is logged with a correlation ID that is
+//! also sent back, so reports can be matched to logs
+//! even when `production` hides the details.
+//! Unknown routes and methods go through `Error` too,
+//! as do rejections from the extractors in `extract`.
 use std::fmt::{Debug, Display};
 
 use axum::{
     body::Body,
-    http::StatusCode,
-    response::{Html, IntoResponse},
+    extract::{Request, State},
+    http::{
+        HeaderMap, HeaderValue, Method, StatusCode, Uri,
+        header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
 };
 use color_eyre::eyre::eyre;
 use tower_http::catch_panic::ResponseForPanic;
+use tracing::Instrument;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 pub struct Error(pub StatusCode, pub color_eyre::eyre::Report);
 
+/// Header carrying the correlation ID of every response
+pub const CORRELATION_ID: &str = "x-correlation-id";
+
+#[derive(Clone, Copy, Debug)]
+pub struct ErrorConfig {
+    /// Hide error reports, backtraces and span traces from
+    /// responses. They are still logged.
+    pub production: bool,
+}
+
+/// An error waiting for `negotiate` to render it.
+/// `Error` leaves this in the response extensions
+/// instead of a body, because it can't see the
+/// request's `Accept` header
+#[derive(Clone)]
+struct ErrorReport {
+    message: String,
+    report: String,
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.1.handler().display(self.1.as_ref(), f)
@@ -33,17 +65,13 @@ impl Debug for Error {
 
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        let ansi_string = format!("{:?}", self);
-        let error = ansi_to_html::convert(&ansi_string).unwrap();
-
-        (
-            self.0,
-            Html(format!(
-                "<!DOCTYPE html><html><head><meta charset=\"utf8\"></head><body><pre><code>{}</code></pre></body></html>",
-                error
-            )),
-        )
-            .into_response()
+        let report = ErrorReport {
+            message: self.to_string(),
+            report: format!("{:?}", self),
+        };
+        let mut response = self.0.into_response();
+        response.extensions_mut().insert(report);
+        response
     }
 }
 
@@ -53,6 +81,118 @@ impl From<Error> for Box<dyn std::error::Error + Sync + Send> {
     }
 }
 
+/// Middleware that tags every request with a correlation
+/// ID and renders `Error`s for whoever asked
+pub async fn negotiate(
+    State(config): State<ErrorConfig>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let correlation_id = uuid::Uuid::new_v4().to_string();
+    let wants_json = prefers_json(request.headers());
+    let span = tracing::info_span!("request", %correlation_id);
+
+    let mut response = next.run(request).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&correlation_id) {
+        response.headers_mut().insert(CORRELATION_ID, value);
+    }
+
+    let Some(report) = response.extensions_mut().remove::<ErrorReport>() else {
+        return response;
+    };
+    let (mut parts, _) = response.into_parts();
+    tracing::error!(%correlation_id, status = %parts.status, "{}", report.report);
+
+    // Server errors can carry anything from SQL to panic
+    // messages, client errors are meant to be read
+    let detail = if config.production && parts.status.is_server_error() {
+        "Something went wrong on our end"
+    } else {
+        report.message.as_str()
+    };
+    let title = parts.status.canonical_reason().unwrap_or("Error");
+
+    let (content_type, body) = if wants_json {
+        let problem = serde_json::json!({
+            "type": "about:blank",
+            "title": title,
+            "status": parts.status.as_u16(),
+            "detail": detail,
+            "correlation_id": correlation_id,
+        });
+        ("application/problem+json", problem.to_string())
+    } else if config.production {
+        let page = maud::html! {
+            (maud::DOCTYPE)
+            html {
+                head { meta charset="utf8"; }
+                body {
+                    h1 { (parts.status.as_u16()) " " (title) }
+                    p { (detail) }
+                    p { "Correlation ID: " code { (correlation_id) } }
+                }
+            }
+        };
+        ("text/html; charset=utf-8", page.into_string())
+    } else {
+        let error = ansi_to_html::convert(&report.report).unwrap();
+        let page = format!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf8\"></head><body><pre><code>{}</code></pre><p>Correlation ID: <code>{}</code></p></body></html>",
+            error, correlation_id
+        );
+        ("text/html; charset=utf-8", page)
+    };
+
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Fallback for paths no route matches
+pub async fn not_found(uri: Uri) -> Error {
+    Error(
+        StatusCode::NOT_FOUND,
+        eyre!("There's nothing at {}", uri.path()),
+    )
+}
+
+/// Fallback for routes that don't take `method`
+pub async fn method_not_allowed(method: Method, uri: Uri) -> Error {
+    Error(
+        StatusCode::METHOD_NOT_ALLOWED,
+        eyre!("{} doesn't take {} requests", uri.path(), method),
+    )
+}
+
+/// Whether the `Accept` header ranks JSON over HTML.
+/// Clients that accept anything get JSON, browsers
+/// always ask for `text/html` explicitly.
+fn prefers_json(headers: &HeaderMap) -> bool {
+    let Some(accept) = headers.get(ACCEPT).and_then(|v| v.to_str().ok()) else {
+        return true;
+    };
+
+    let mut html = 0.0f32;
+    let mut json = 0.0f32;
+    for media_range in accept.split(',') {
+        let mut params = media_range.split(';');
+        let media_type = params.next().unwrap_or_default().trim();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match media_type {
+            "text/html" | "application/xhtml+xml" => html = html.max(quality),
+            "application/json" | "application/problem+json" => json = json.max(quality),
+            _ => {}
+        }
+    }
+
+    html == 0.0 || json > html
+}
+
 #[derive(Clone, Copy)]
 pub struct PanicHandler;
 
@@ -63,6 +203,7 @@ impl ResponseForPanic for PanicHandler {
         &mut self,
         err: Box<dyn std::any::Any + Send + 'static>,
     ) -> axum::http::Response<Self::ResponseBody> {
+        metrics::counter!(crate::telemetry::PANICS).increment(1);
         let error_string = if let Some(s) = err.downcast_ref::<String>() {
             tracing::error!("Service panicked: {}", s);
             s.as_str()
@@ -88,3 +229,113 @@ impl<T> WithStatusCode<T> for std::result::Result<T, color_eyre::eyre::Report> {
         self.map_err(|e| Error(code, e))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        http::{self, header::ALLOW},
+        middleware,
+        routing::post,
+    };
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::extract::{Json, Path, Query};
+
+    #[derive(Deserialize)]
+    struct Options {
+        loud: bool,
+    }
+
+    #[derive(Deserialize)]
+    struct Thing {
+        name: String,
+    }
+
+    async fn thing(
+        Path(id): Path<u32>,
+        Query(options): Query<Options>,
+        Json(thing): Json<Thing>,
+    ) -> String {
+        format!("{} {} {}", id, thing.name, options.loud)
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route("/things/{id}", post(thing))
+            .fallback(not_found)
+            .method_not_allowed_fallback(method_not_allowed)
+            .layer(middleware::from_fn_with_state(
+                ErrorConfig { production: false },
+                negotiate,
+            ))
+    }
+
+    /// Sends a JSON request, returning the
+    /// response and its problem details
+    async fn problem(method: &str, uri: &str, body: &str) -> (Response, serde_json::Value) {
+        let request = http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(ACCEPT, "application/json")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_owned()))
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/problem+json");
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["status"], parts.status.as_u16());
+        (Response::from_parts(parts, Body::empty()), problem)
+    }
+
+    #[tokio::test]
+    async fn ok() {
+        let request = http::Request::builder()
+            .method("POST")
+            .uri("/things/1?loud=true")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"name": "thing"}"#))
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn unknown_path() {
+        let (response, problem) = problem("GET", "/nothing", "").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(problem["detail"], "There's nothing at /nothing");
+    }
+
+    #[tokio::test]
+    async fn wrong_method() {
+        let (response, _) = problem("GET", "/things/1?loud=true", "").await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[ALLOW], "POST");
+    }
+
+    #[tokio::test]
+    async fn bad_path() {
+        let (response, _) = problem("POST", "/things/one?loud=true", "{}").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn bad_query() {
+        let (response, _) = problem("POST", "/things/1?loud=very", "{}").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn bad_json() {
+        let (response, _) = problem("POST", "/things/1?loud=true", "{").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let (response, _) = problem("POST", "/things/1?loud=true", "{}").await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
@@ -0,0 +1,46 @@
+//! Axum's extractors, rejecting with `error::Error` so bad
+//! requests are rendered by `negotiate` like any other error
+use axum::extract::{
+    FromRequest, FromRequestParts,
+    rejection::{FormRejection, JsonRejection, PathRejection, QueryRejection},
+    ws::rejection::WebSocketUpgradeRejection,
+};
+use color_eyre::eyre::eyre;
+
+use crate::error::Error;
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(Error))]
+pub struct Json<T>(pub T);
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Form), rejection(Error))]
+pub struct Form<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(Error))]
+pub struct Query<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(Error))]
+pub struct Path<T>(pub T);
+
+macro_rules! rejections {
+    ($($rejection:ty),*) => {
+        $(
+            impl From<$rejection> for Error {
+                fn from(rejection: $rejection) -> Self {
+                    Error(rejection.status(), eyre!(rejection.body_text()))
+                }
+            }
+        )*
+    };
+}
+
+rejections!(
+    FormRejection,
+    JsonRejection,
+    PathRejection,
+    QueryRejection,
+    WebSocketUpgradeRejection
+);
@@ -1,43 +1,183 @@
 use std::{
     net::SocketAddr,
-    time::{SystemTime, UNIX_EPOCH},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use axum::{
     Router,
     body::Body,
-    extract::{ConnectInfo, connect_info::Connected},
+    extract::{ConnectInfo, FromRef, connect_info::Connected},
     http::StatusCode,
+    middleware,
     routing::get,
     serve::IncomingStream,
 };
+use clap::Parser;
 use color_eyre::eyre::{self, Context};
+use supabase_auth::models::AuthClient;
 use tokio::{net::TcpListener, signal};
 use tower_http::catch_panic::CatchPanicLayer;
 use tracing::instrument;
 
 use crate::error::WithStatusCode;
 
+mod api;
+mod auth;
+mod dashboard;
 mod error;
+mod extract;
+mod rate_limit;
+mod rooms;
+mod store;
+mod telemetry;
 mod time_sync;
 
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    /// Don't send error reports, backtraces or span traces
+    /// in responses. Turn this on when the server is public
+    #[arg(long, env = "TINKER_TERMINAL_PRODUCTION")]
+    production: bool,
+    /// How many `/time_sync` requests one IP can make back to back
+    #[arg(
+        long,
+        env = "TINKER_TERMINAL_TIME_SYNC_BURST",
+        default_value_t = 16,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    time_sync_burst: u32,
+    /// How many `/time_sync` requests per second one IP gets back
+    #[arg(
+        long,
+        env = "TINKER_TERMINAL_TIME_SYNC_PER_SECOND",
+        default_value_t = 2.0,
+        value_parser = positive
+    )]
+    time_sync_per_second: f64,
+    /// Secret Supabase signs HS256 access tokens with
+    #[arg(long, env = "SUPABASE_JWT_SECRET", hide_env_values = true)]
+    jwt_secret: Option<String>,
+    /// Where Supabase publishes its public signing keys,
+    /// used when `--jwt-secret` isn't given
+    #[arg(long, env = "SUPABASE_JWKS_URL")]
+    jwks_url: Option<String>,
+    /// Supabase project the dashboard signs people in to
+    #[arg(long, env = "SUPABASE_URL")]
+    supabase_url: Option<String>,
+    /// The project's anon key, needed alongside `--supabase-url`
+    #[arg(long, env = "SUPABASE_API_KEY", hide_env_values = true)]
+    supabase_api_key: Option<String>,
+    /// Seconds between failing `/readyz` on shutdown and
+    /// draining connections, so load balancers notice first
+    #[arg(long, env = "TINKER_TERMINAL_SHUTDOWN_GRACE", default_value_t = 5)]
+    shutdown_grace: u64,
+    /// DuckDB file synced timesheets and room history are kept in
+    #[arg(
+        long,
+        env = "TINKER_TERMINAL_DATABASE",
+        default_value = "tinker-terminal.duckdb"
+    )]
+    database: PathBuf,
+}
+
+/// A rate that refills at all, since the limiter
+/// divides by it
+fn positive(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(n) if n.is_finite() && n > 0.0 => Ok(n),
+        Ok(_) => Err("must be more than 0".to_owned()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[derive(Clone, FromRef)]
+struct AppState {
+    auth: auth::Auth,
+    dashboard: dashboard::Dashboard,
+    rooms: rooms::Rooms,
+    store: store::Store,
+    telemetry: telemetry::Telemetry,
+}
+
+/// When the connection was accepted and who it came from
 #[derive(Clone, Copy, Debug)]
-pub struct TimestampConnectInfo(pub SystemTime);
+pub struct TimestampConnectInfo(pub SystemTime, pub SocketAddr);
 
 impl Connected<IncomingStream<'_, TcpListener>> for TimestampConnectInfo {
-    fn connect_info(_stream: IncomingStream<'_, TcpListener>) -> Self {
-        Self(SystemTime::now())
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        Self(SystemTime::now(), *stream.remote_addr())
     }
 }
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
+    let cli = Cli::parse();
+
     lockinspiel_common::install_init_boilerplate(None)?;
 
+    let login = match (cli.supabase_url, cli.supabase_api_key) {
+        (Some(url), Some(api_key)) => Some(Arc::new(AuthClient::new(
+            url,
+            api_key,
+            cli.jwt_secret.clone().unwrap_or_default(),
+        ))),
+        _ => None,
+    };
+    let auth = match (cli.jwt_secret, cli.jwks_url) {
+        (Some(secret), _) => auth::Auth::secret(&secret),
+        (None, Some(url)) => auth::Auth::jwks(url),
+        (None, None) => {
+            tracing::warn!(
+                "Neither SUPABASE_JWT_SECRET nor SUPABASE_JWKS_URL is set, so nobody can sign in"
+            );
+            auth::Auth::unconfigured()
+        }
+    };
+    let telemetry = telemetry::Telemetry::install()?;
+    let store = store::Store::open(&cli.database)?;
+    store.close_abandoned_rooms().await?;
+
     let app = Router::new()
         .route("/", get(index))
-        .route("/time_sync", get(time_handler))
-        .layer(CatchPanicLayer::custom(error::PanicHandler));
+        .route(
+            "/time_sync",
+            get(time_handler).layer(rate_limit::RateLimitLayer::new(
+                rate_limit::RateLimitConfig {
+                    burst: cli.time_sync_burst,
+                    per_second: cli.time_sync_per_second,
+                },
+            )),
+        )
+        .merge(rooms::router())
+        .merge(store::router())
+        .merge(api::router())
+        .merge(dashboard::router())
+        .merge(telemetry::router())
+        .fallback(error::not_found)
+        // After every route, it only covers the ones added so far
+        .method_not_allowed_fallback(error::method_not_allowed)
+        .layer(CatchPanicLayer::custom(error::PanicHandler))
+        .layer(middleware::from_fn(telemetry::track))
+        .layer(middleware::from_fn_with_state(
+            error::ErrorConfig {
+                production: cli.production,
+            },
+            error::negotiate,
+        ))
+        .with_state(AppState {
+            auth,
+            dashboard: dashboard::Dashboard {
+                login,
+                secure_cookies: cli.production,
+                time_zone: store::local_time_zone(),
+            },
+            rooms: rooms::Rooms::new(store.clone()),
+            store,
+            telemetry: telemetry.clone(),
+        });
 
     let listen_addr: SocketAddr = ([127, 0, 0, 1], 8080).into();
     let listener = TcpListener::bind(listen_addr)
@@ -47,7 +187,10 @@ async fn main() -> eyre::Result<()> {
         listener,
         app.into_make_service_with_connect_info::<TimestampConnectInfo>(),
     )
-    .with_graceful_shutdown(shutdown_signal())
+    .with_graceful_shutdown(shutdown_signal(
+        telemetry,
+        Duration::from_secs(cli.shutdown_grace),
+    ))
     .await
     .wrap_err("Failed to serve make service")
 }
@@ -57,6 +200,7 @@ async fn index() -> maud::Markup {
     maud::html! {
         h1 { "It's working" }
         p { "The server is up" }
+        p { a href="/dashboard" { "Dashboard" } }
     }
 }
 
@@ -72,7 +216,10 @@ async fn time_handler(
     )))
 }
 
-async fn shutdown_signal() {
+/// Resolves once it's time to drain: on Ctrl+C or SIGTERM,
+/// stop being ready, then wait out `grace` while traffic
+/// moves elsewhere
+async fn shutdown_signal(telemetry: telemetry::Telemetry, grace: Duration) {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -94,4 +241,8 @@ async fn shutdown_signal() {
         _ = ctrl_c => {},
         _ = terminate => {},
     }
+
+    telemetry.set_not_ready();
+    tracing::info!(?grace, "Shutting down, draining after the grace period");
+    tokio::time::sleep(grace).await;
 }
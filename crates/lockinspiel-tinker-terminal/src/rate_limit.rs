@@ -0,0 +1,173 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{HeaderValue, StatusCode, header::RETRY_AFTER},
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::eyre;
+use pin_project::pin_project;
+use tower::{Layer, Service};
+
+use crate::{TimestampConnectInfo, error, telemetry};
+
+/// Buckets that have been full this long belong
+/// to clients that went away
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimitConfig {
+    /// Requests a client can make back to back
+    pub burst: u32,
+    /// Requests per second a client gets back
+    pub per_second: f64,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+struct Buckets {
+    buckets: HashMap<IpAddr, Bucket>,
+    cleaned_at: Instant,
+}
+
+/// Per-IP token bucket. Requests over the limit get a
+/// 429 with a `Retry-After` header instead of reaching
+/// the service
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    config: RateLimitConfig,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimitLayer {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Arc::new(Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                cleaned_at: Instant::now(),
+            })),
+        }
+    }
+
+    /// Takes a token from `ip`'s bucket, or returns
+    /// how long until there will be one
+    fn acquire(&self, ip: IpAddr) -> Result<(), Duration> {
+        let burst = self.config.burst as f64;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if now.duration_since(buckets.cleaned_at) >= CLEANUP_INTERVAL {
+            let per_second = self.config.per_second;
+            buckets.buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.refilled_at).as_secs_f64() * per_second
+                    < burst
+            });
+            buckets.cleaned_at = now;
+        }
+
+        let bucket = buckets.buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            refilled_at: now,
+        });
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.refilled_at).as_secs_f64() * self.config.per_second)
+            .min(burst);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.config.per_second,
+            ))
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    layer: RateLimitLayer,
+}
+
+impl<S> Service<Request> for RateLimit<S>
+where
+    S: Service<Request, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = RateLimitFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let ip = request
+            .extensions()
+            .get::<ConnectInfo<TimestampConnectInfo>>()
+            .map(|info| info.0.1.ip());
+
+        if let Some(Err(wait)) = ip.map(|ip| self.layer.acquire(ip)) {
+            metrics::counter!(telemetry::RATE_LIMITED).increment(1);
+            // Round up so clients never come back too early
+            let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            let mut response = error::Error(
+                StatusCode::TOO_MANY_REQUESTS,
+                eyre!("Too many requests, try again in {} seconds", retry_after),
+            )
+            .into_response();
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+            return RateLimitFuture::Limited(Some(response));
+        }
+
+        RateLimitFuture::Inner(self.inner.call(request))
+    }
+}
+
+#[pin_project(project = RateLimitFutureProj)]
+pub enum RateLimitFuture<F> {
+    Limited(Option<Response>),
+    Inner(#[pin] F),
+}
+
+impl<F, E> Future for RateLimitFuture<F>
+where
+    F: Future<Output = Result<Response, E>>,
+{
+    type Output = Result<Response, E>;
+
+    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            RateLimitFutureProj::Limited(response) => Poll::Ready(Ok(response
+                .take()
+                .expect("RateLimitFuture polled after completion"))),
+            RateLimitFutureProj::Inner(future) => future.poll(cx),
+        }
+    }
+}
@@ -0,0 +1,497 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    Json, Router,
+    extract::{
+        FromRef, State, WebSocketUpgrade,
+        ws::{Message, WebSocket, rejection::WebSocketUpgradeRejection},
+    },
+    http::StatusCode,
+    response::Response,
+    routing::{get, post},
+};
+use color_eyre::eyre::eyre;
+use jiff::{SignedDuration, Timestamp};
+use lockinspiel_common::{
+    room::{CreateRoom, RoomAction, RoomEvent, RoomState},
+    timer::Timer,
+};
+use rand::Rng;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    auth::{Auth, AuthUser},
+    error::{self, WithStatusCode},
+    extract::{self, Path},
+    store::Store,
+    telemetry,
+};
+
+/// No 0/O or 1/I so codes can be read out loud
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LEN: usize = 6;
+
+/// Rooms nobody's in close after this long without
+/// anything happening, like ones created but never joined
+const ROOM_IDLE_TIMEOUT: SignedDuration = SignedDuration::from_mins(10);
+/// How often to look for idle rooms
+const ROOM_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Room {
+    state: RoomState,
+    events: broadcast::Sender<RoomEvent>,
+    owner: Uuid,
+    /// Tells this room apart from earlier
+    /// ones with the same code in the store
+    opened_at: Timestamp,
+    /// Open connections per signed in member
+    users: HashMap<Uuid, usize>,
+}
+
+/// Every room hosted by this server, keyed by code.
+/// What happens in them is recorded in the store
+#[derive(Clone)]
+pub struct Rooms {
+    rooms: Arc<Mutex<HashMap<String, Room>>>,
+    store: Store,
+}
+
+pub fn router<S>() -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+    Rooms: FromRef<S>,
+    Auth: FromRef<S>,
+{
+    Router::new()
+        .route("/rooms", post(create_room))
+        .route("/rooms/{code}", get(get_room))
+        .route("/rooms/{code}/events", get(room_events))
+        .route("/rooms/{code}/{action}", post(room_action))
+}
+
+fn room_code() -> String {
+    let mut rng = rand::rng();
+    (0..CODE_LEN)
+        .map(|_| CODE_ALPHABET[rng.random_range(0..CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+fn room_not_found(code: &str) -> error::Error {
+    error::Error(StatusCode::NOT_FOUND, eyre!("Room {} does not exist", code))
+}
+
+impl Rooms {
+    pub fn new(store: Store) -> Self {
+        let rooms = Self {
+            rooms: Arc::default(),
+            store,
+        };
+
+        let sweeper = rooms.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ROOM_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                sweeper.close_idle(Timestamp::now());
+            }
+        });
+
+        rooms
+    }
+
+    /// Closes rooms with nobody in them that have sat
+    /// idle past `ROOM_IDLE_TIMEOUT`. Leaving closes the
+    /// rest, but a room nobody joined never sees a leave
+    fn close_idle(&self, now: Timestamp) {
+        let mut rooms = self.rooms.lock().unwrap();
+        let open = rooms.len();
+        rooms.retain(|code, room| {
+            let idle = room.state.members == 0
+                && now.duration_since(room.state.updated_at) >= ROOM_IDLE_TIMEOUT;
+            if idle {
+                self.store.room_closed(code, room.opened_at);
+                tracing::info!(code, "Closed idle room");
+            }
+            !idle
+        });
+        if rooms.len() != open {
+            metrics::gauge!(telemetry::ACTIVE_ROOMS).set(rooms.len() as f64);
+        }
+    }
+
+    /// Adds a member to the room and tells everybody
+    /// about it. The returned receiver gets the
+    /// membership change as its first event
+    fn join(&self, code: &str, user: Uuid) -> Option<broadcast::Receiver<RoomEvent>> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let room = rooms.get_mut(code)?;
+        let events = room.events.subscribe();
+        room.state.members += 1;
+        *room.users.entry(user).or_default() += 1;
+        self.store
+            .room_event(room.opened_at, user, "join", &room.state);
+        let _ = room.events.send(RoomEvent {
+            action: None,
+            room: room.state.clone(),
+        });
+
+        Some(events)
+    }
+
+    /// Removes a member from the room, closing the
+    /// room once nobody is left in it
+    fn leave(&self, code: &str, user: Uuid) {
+        let mut rooms = self.rooms.lock().unwrap();
+        let Some(room) = rooms.get_mut(code) else {
+            return;
+        };
+        room.state.members = room.state.members.saturating_sub(1);
+        self.store
+            .room_event(room.opened_at, user, "leave", &room.state);
+        if let Some(connections) = room.users.get_mut(&user) {
+            *connections -= 1;
+            if *connections == 0 {
+                room.users.remove(&user);
+            }
+        }
+        if room.state.members == 0 {
+            self.store.room_closed(code, room.opened_at);
+            rooms.remove(code);
+            metrics::gauge!(telemetry::ACTIVE_ROOMS).set(rooms.len() as f64);
+            tracing::info!(code, "Closed empty room");
+        } else {
+            let _ = room.events.send(RoomEvent {
+                action: None,
+                room: room.state.clone(),
+            });
+        }
+    }
+
+    /// Rooms `user` created or is in right now
+    pub fn for_user(&self, user: Uuid) -> Vec<RoomState> {
+        let rooms = self.rooms.lock().unwrap();
+        let mut states: Vec<RoomState> = rooms
+            .values()
+            .filter(|room| room.owner == user || room.users.contains_key(&user))
+            .map(|room| room.state.clone())
+            .collect();
+        states.sort_by(|a, b| a.code.cmp(&b.code));
+        states
+    }
+}
+
+#[instrument(skip(rooms, user))]
+async fn create_room(
+    State(rooms): State<Rooms>,
+    user: AuthUser,
+    extract::Json(create): extract::Json<CreateRoom>,
+) -> error::Result<Json<RoomState>> {
+    if create.timers.is_empty() {
+        return Err(eyre!("A room needs at least one timer"))
+            .with_status_code(StatusCode::BAD_REQUEST);
+    }
+
+    let store = rooms.store.clone();
+    let mut rooms = rooms.rooms.lock().unwrap();
+    let code = loop {
+        let code = room_code();
+        if !rooms.contains_key(&code) {
+            break code;
+        }
+    };
+    let now = Timestamp::now();
+    let state = RoomState {
+        code: code.clone(),
+        split: create.split,
+        timer: Timer::new(create.timers),
+        members: 0,
+        updated_at: now,
+    };
+    store.room_opened(&state, user.id);
+    let (events, _) = broadcast::channel(16);
+    rooms.insert(
+        code.clone(),
+        Room {
+            state: state.clone(),
+            events,
+            owner: user.id,
+            opened_at: now,
+            users: HashMap::new(),
+        },
+    );
+    metrics::gauge!(telemetry::ACTIVE_ROOMS).set(rooms.len() as f64);
+    tracing::info!(code, user = %user.id, "Created room");
+
+    Ok(Json(state))
+}
+
+#[instrument(skip(rooms, _user))]
+async fn get_room(
+    State(rooms): State<Rooms>,
+    _user: AuthUser,
+    Path(code): Path<String>,
+) -> error::Result<Json<RoomState>> {
+    let code = code.to_uppercase();
+    let rooms = rooms.rooms.lock().unwrap();
+    let room = rooms.get(&code).ok_or_else(|| room_not_found(&code))?;
+
+    Ok(Json(room.state.clone()))
+}
+
+#[instrument(skip(rooms, user))]
+async fn room_action(
+    State(rooms): State<Rooms>,
+    user: AuthUser,
+    Path((code, action)): Path<(String, RoomAction)>,
+) -> error::Result<Json<RoomState>> {
+    let code = code.to_uppercase();
+    let store = rooms.store.clone();
+    let mut rooms = rooms.rooms.lock().unwrap();
+    let room = rooms.get_mut(&code).ok_or_else(|| room_not_found(&code))?;
+    // Knowing the code isn't enough, the owner or
+    // somebody in the room has to be the one asking
+    if room.owner != user.id && !room.users.contains_key(&user.id) {
+        return Err(eyre!("Join room {} to change its timer", code))
+            .with_status_code(StatusCode::FORBIDDEN);
+    }
+
+    // The server's clock is the one every member syncs to,
+    // so it decides when the timer starts and stops
+    let now = Timestamp::now();
+    let changed = match action {
+        RoomAction::Start => room.state.timer.start(now).is_some(),
+        RoomAction::Pause => room.state.timer.pause(now).is_some(),
+        RoomAction::Skip => {
+            room.state.timer.skip();
+            true
+        }
+    };
+
+    if changed {
+        tracing::info!(code, user = %user.id, ?action, "Room timer changed");
+        room.state.updated_at = now;
+        store.room_event(room.opened_at, user.id, action.as_str(), &room.state);
+        let _ = room.events.send(RoomEvent {
+            action: Some(action),
+            room: room.state.clone(),
+        });
+    }
+
+    Ok(Json(room.state.clone()))
+}
+
+#[instrument(skip(rooms, user, ws))]
+async fn room_events(
+    State(rooms): State<Rooms>,
+    user: AuthUser,
+    Path(code): Path<String>,
+    ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> error::Result<Response> {
+    let ws = ws?;
+    let code = code.to_uppercase();
+    if !rooms.rooms.lock().unwrap().contains_key(&code) {
+        return Err(room_not_found(&code));
+    }
+
+    tracing::info!(code, user = %user.id, "Joining room");
+    Ok(ws.on_upgrade(move |socket| member(rooms, code, user.id, socket)))
+}
+
+/// Forwards room events to a member until either
+/// side hangs up
+async fn member(rooms: Rooms, code: String, user: Uuid, mut socket: WebSocket) {
+    let Some(mut events) = rooms.join(&code, user) else {
+        return;
+    };
+    metrics::gauge!(telemetry::ACTIVE_WEBSOCKETS).increment(1);
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let json = match serde_json::to_string(&event) {
+                        Ok(json) => json,
+                        Err(e) => {
+                            tracing::error!(?e, "Failed to serialize room event");
+                            continue;
+                        }
+                    };
+                    if socket.send(Message::Text(json.into())).await.is_err() {
+                        break;
+                    }
+                }
+                // Every event carries the whole room, so
+                // the next one catches this member up
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    metrics::gauge!(telemetry::ACTIVE_WEBSOCKETS).decrement(1);
+    rooms.leave(&code, user);
+}
+
+#[cfg(test)]
+mod tests {
+    use lockinspiel_common::timer::{SplitTimer, TimerState};
+
+    use super::*;
+
+    /// A store in a fresh file, removed once dropped
+    struct TestStore {
+        path: std::path::PathBuf,
+        store: Store,
+    }
+
+    impl TestStore {
+        fn new() -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("tinker-terminal-rooms-{}.duckdb", Uuid::new_v4()));
+            let store = Store::open(&path).unwrap();
+            Self { path, store }
+        }
+    }
+
+    impl Drop for TestStore {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+            let _ = std::fs::remove_file(self.path.with_extension("duckdb.wal"));
+        }
+    }
+
+    fn user() -> AuthUser {
+        AuthUser {
+            id: Uuid::new_v4(),
+            email: None,
+        }
+    }
+
+    async fn create(rooms: &Rooms, owner: &AuthUser) -> RoomState {
+        let create = CreateRoom {
+            split: "Pomodoro".to_owned(),
+            timers: vec![SplitTimer {
+                name: "Work".to_owned(),
+                len: SignedDuration::from_mins(25),
+                work: true,
+            }],
+        };
+        let Ok(Json(state)) =
+            create_room(State(rooms.clone()), owner.clone(), extract::Json(create)).await
+        else {
+            panic!("Failed to create room");
+        };
+        state
+    }
+
+    async fn act(
+        rooms: &Rooms,
+        user: &AuthUser,
+        code: &str,
+        action: RoomAction,
+    ) -> error::Result<RoomState> {
+        room_action(
+            State(rooms.clone()),
+            user.clone(),
+            Path((code.to_owned(), action)),
+        )
+        .await
+        .map(|Json(state)| state)
+    }
+
+    #[tokio::test]
+    async fn create_and_join_by_code() {
+        let store = TestStore::new();
+        let rooms = Rooms::new(store.store.clone());
+        let owner = user();
+        let created = create(&rooms, &owner).await;
+        assert_eq!(created.code.len(), CODE_LEN);
+        assert_eq!(created.members, 0);
+
+        // Codes are read out loud, so case doesn't matter
+        let Ok(Json(found)) = get_room(
+            State(rooms.clone()),
+            user(),
+            Path(created.code.to_lowercase()),
+        )
+        .await
+        else {
+            panic!("Failed to find room");
+        };
+        assert_eq!(found, created);
+
+        let member = user();
+        let mut events = rooms.join(&created.code, member.id).unwrap();
+        let joined = events.recv().await.unwrap();
+        assert_eq!(joined.action, None);
+        assert_eq!(joined.room.members, 1);
+        assert_eq!(rooms.for_user(member.id), vec![joined.room]);
+
+        assert!(rooms.join("NOROOM", member.id).is_none());
+    }
+
+    #[tokio::test]
+    async fn action_reaches_members() {
+        let store = TestStore::new();
+        let rooms = Rooms::new(store.store.clone());
+        let created = create(&rooms, &user()).await;
+        let member = user();
+        let mut events = rooms.join(&created.code, member.id).unwrap();
+        events.recv().await.unwrap();
+
+        let state = act(&rooms, &member, &created.code, RoomAction::Start)
+            .await
+            .unwrap();
+        assert!(matches!(state.timer.state, TimerState::Going(_)));
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.action, Some(RoomAction::Start));
+        assert_eq!(event.room, state);
+    }
+
+    #[tokio::test]
+    async fn outsiders_cant_act() {
+        let store = TestStore::new();
+        let rooms = Rooms::new(store.store.clone());
+        let owner = user();
+        let created = create(&rooms, &owner).await;
+
+        let Err(error) = act(&rooms, &user(), &created.code, RoomAction::Start).await else {
+            panic!("Somebody outside the room started its timer");
+        };
+        assert_eq!(error.0, StatusCode::FORBIDDEN);
+        let room = rooms.rooms.lock().unwrap()[&created.code].state.clone();
+        assert_eq!(room, created);
+
+        // The owner doesn't have to be in it
+        act(&rooms, &owner, &created.code, RoomAction::Start)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn closes_idle_rooms() {
+        let store = TestStore::new();
+        let rooms = Rooms::new(store.store.clone());
+        let empty = create(&rooms, &user()).await;
+        let joined = create(&rooms, &user()).await;
+        let _events = rooms.join(&joined.code, Uuid::new_v4()).unwrap();
+
+        rooms.close_idle(empty.updated_at + SignedDuration::from_mins(1));
+        assert!(rooms.rooms.lock().unwrap().contains_key(&empty.code));
+
+        rooms.close_idle(empty.updated_at + ROOM_IDLE_TIMEOUT);
+        let open = rooms.rooms.lock().unwrap();
+        assert!(!open.contains_key(&empty.code));
+        assert!(open.contains_key(&joined.code));
+    }
+}
@@ -0,0 +1,591 @@
+use std::{collections::HashMap, path::Path};
+
+use axum::{
+    Router,
+    extract::{FromRef, State},
+    http::StatusCode,
+    routing::post,
+};
+use color_eyre::eyre::{self, Context};
+use duckdb::{Connection, params};
+use jiff::{SignedDuration, Timestamp, tz::TimeZone};
+use lockinspiel_common::{
+    db::{Database, JiffTimestamp, TimesheetRow},
+    history::{self, Entry, TagTotal},
+    idle,
+    room::RoomState,
+    sync::{SyncBatch, SyncedSplit},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    auth::{Auth, AuthUser},
+    error::{self, WithStatusCode},
+    extract::Json,
+};
+
+const MIGRATIONS: [&str; 4] = [
+    include_str!("../migrations/000-initial.sql"),
+    include_str!("../migrations/001-completed.sql"),
+    include_str!("../migrations/002-row-keys.sql"),
+    include_str!("../migrations/003-idle-gap.sql"),
+];
+
+/// Rows of user `$1` that started in `[$2, $3)`
+const IN_RANGE: &str = "t.user_id = $1
+    AND ($2::TIMESTAMP IS NULL OR t.start_time >= $2::TIMESTAMP)
+    AND ($3::TIMESTAMP IS NULL OR t.start_time < $3::TIMESTAMP)";
+
+/// Timesheets users have synced to this server and the
+/// history of the rooms it has hosted, kept in DuckDB
+#[derive(Clone)]
+pub struct Store {
+    db: Database,
+    writes: mpsc::UnboundedSender<Write>,
+}
+
+/// Bookkeeping for the writer to do, named for the logs
+struct Write {
+    what: &'static str,
+    f: Box<dyn FnOnce(&mut Connection) -> eyre::Result<()> + Send>,
+}
+
+/// A timesheet row along with its split and tags
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    #[serde(flatten)]
+    pub row: TimesheetRow,
+    pub split: Option<String>,
+    pub tags: Vec<String>,
+    /// Time away from the computer while it was going
+    pub idle: SignedDuration,
+}
+
+/// Rows that started in `[from, to)`. Either end
+/// can be left off
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct TimeRange {
+    pub from: Option<Timestamp>,
+    pub to: Option<Timestamp>,
+}
+
+impl TimeRange {
+    pub fn is_backwards(&self) -> bool {
+        matches!((self.from, self.to), (Some(from), Some(to)) if from > to)
+    }
+}
+
+/// One page of a list. `total` counts every
+/// item matching the filters, not just this page
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+/// What the dashboard shows up top
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub totals: history::Stats,
+    /// Work time per tag, most worked first
+    pub tags: Vec<TagTotal>,
+}
+
+pub fn router<S>() -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+    Store: FromRef<S>,
+    Auth: FromRef<S>,
+{
+    Router::new().route("/sync", post(sync))
+}
+
+/// Tags on every timesheet row of `user`, by when it started
+fn row_tags(conn: &Connection, user: &str) -> eyre::Result<HashMap<Timestamp, Vec<String>>> {
+    let mut stmt =
+        conn.prepare("SELECT start_time, tag FROM synced_tag WHERE user_id = ? ORDER BY tag")?;
+    let mut rows = stmt.query([user])?;
+
+    let mut tags: HashMap<Timestamp, Vec<String>> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let start_time: JiffTimestamp = row.get(0)?;
+        tags.entry(start_time.0).or_default().push(row.get(1)?);
+    }
+    Ok(tags)
+}
+
+/// Every time `user` was away from the computer, oldest first
+fn idle_gaps(conn: &Connection, user: &str) -> eyre::Result<Vec<(Timestamp, Timestamp)>> {
+    let mut stmt = conn.prepare(
+        "SELECT start_time, end_time FROM synced_idle_gap WHERE user_id = ? ORDER BY start_time",
+    )?;
+    let gaps = stmt
+        .query_map([user], |row| {
+            Ok((
+                row.get::<_, JiffTimestamp>(0)?.0,
+                row.get::<_, JiffTimestamp>(1)?.0,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(gaps)
+}
+
+/// `LIMIT` and `OFFSET` take a `BIGINT`
+fn sql_count(n: usize) -> i64 {
+    i64::try_from(n).unwrap_or(i64::MAX)
+}
+
+/// The user's rows that started in `range`, oldest first,
+/// for the totals `history` works out
+fn entries(conn: &Connection, user: &str, range: TimeRange) -> eyre::Result<Vec<Entry>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT t.start_time, t.end_time, t.work, t.completed, t.split
+        FROM synced_timesheet t
+        WHERE {}
+        ORDER BY t.start_time",
+        IN_RANGE
+    ))?;
+    let (from, to) = (range.from.map(JiffTimestamp), range.to.map(JiffTimestamp));
+    let rows = stmt
+        .query_map(params![user, from, to], |row| {
+            Ok((
+                row.get::<_, JiffTimestamp>(0)?.0,
+                row.get::<_, JiffTimestamp>(1)?.0,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let tags = row_tags(conn, user)?;
+    let gaps = idle_gaps(conn, user)?;
+    Ok(rows
+        .into_iter()
+        .map(|(start_time, end_time, work, completed, split)| Entry {
+            start_time,
+            end_time,
+            work,
+            completed,
+            split,
+            tags: tags.get(&start_time).cloned().unwrap_or_default(),
+            idle: idle::overlap(&gaps, start_time, end_time),
+        })
+        .collect())
+}
+
+impl Store {
+    pub fn open(path: &Path) -> eyre::Result<Self> {
+        let db = Database::with_migrations(path, &MIGRATIONS)
+            .wrap_err_with(|| format!("Failed to open database at {}", path.display()))?;
+        let (writes, queue) = mpsc::unbounded_channel();
+        tokio::spawn(write(db.clone(), queue));
+        Ok(Self { db, writes })
+    }
+
+    /// Runs `f` on a pooled connection, off the async runtime
+    async fn blocking<T, F>(&self, f: F) -> eyre::Result<T>
+    where
+        F: FnOnce(&mut Connection) -> eyre::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || f(db.get()?.connection()))
+            .await
+            .wrap_err("Database task panicked")?
+    }
+
+    /// Like `blocking`, but doesn't wait for `f`. For
+    /// bookkeeping whose failure only needs logging.
+    /// Runs after everything spawned before it, so a
+    /// room's events land after it opened
+    fn spawn<F>(&self, what: &'static str, f: F)
+    where
+        F: FnOnce(&mut Connection) -> eyre::Result<()> + Send + 'static,
+    {
+        let write = Write {
+            what,
+            f: Box::new(f),
+        };
+        if self.writes.send(write).is_err() {
+            tracing::error!(what, "Failed to write to database, the writer is gone");
+        }
+    }
+
+    /// Replaces everything the user synced from the batch's
+    /// `since` on, so rows deleted, moved, split or merged
+    /// on the device don't linger here. Groups only mean
+    /// something within one batch, since every device
+    /// numbers its own
+    pub async fn sync(&self, user: Uuid, batch: SyncBatch) -> eyre::Result<()> {
+        self.blocking(move |conn| {
+            let user = user.to_string();
+            let since = batch
+                .since
+                .or_else(|| batch.timesheet.iter().map(|row| row.start_time.0).min());
+            let splits: HashMap<i64, String> = batch
+                .groups
+                .into_iter()
+                .map(|group| (group.timesheet_group, group.split))
+                .collect();
+            let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+            for tag in batch.tags {
+                tags.entry(tag.timesheet_group).or_default().push(tag.tag);
+            }
+
+            let tx = conn.transaction()?;
+            if let Some(since) = since {
+                tx.execute(
+                    "DELETE FROM synced_tag WHERE user_id = ? AND start_time >= ?",
+                    params![user, JiffTimestamp(since)],
+                )?;
+                tx.execute(
+                    "DELETE FROM synced_timesheet WHERE user_id = ? AND start_time >= ?",
+                    params![user, JiffTimestamp(since)],
+                )?;
+                tx.execute(
+                    "DELETE FROM synced_idle_gap WHERE user_id = ? AND end_time > ?",
+                    params![user, JiffTimestamp(since)],
+                )?;
+            }
+            for gap in batch.idle_gaps {
+                tx.execute(
+                    "INSERT OR REPLACE INTO synced_idle_gap VALUES (?, ?, ?)",
+                    params![
+                        user,
+                        JiffTimestamp(gap.start_time),
+                        JiffTimestamp(gap.end_time)
+                    ],
+                )?;
+            }
+            for row in batch.timesheet {
+                tx.execute(
+                    "INSERT OR REPLACE INTO synced_timesheet
+                        (user_id, timesheet_group, start_time, end_time, work, completed, split)
+                    VALUES (?, ?, ?, ?, ?, ?, ?)",
+                    params![
+                        user,
+                        row.group,
+                        row.start_time,
+                        row.end_time,
+                        row.work,
+                        row.completed,
+                        splits.get(&row.group)
+                    ],
+                )?;
+                for tag in tags.get(&row.group).into_iter().flatten() {
+                    tx.execute(
+                        "INSERT OR IGNORE INTO synced_tag VALUES (?, ?, ?)",
+                        params![user, row.start_time, tag],
+                    )?;
+                }
+            }
+            for split in batch.splits {
+                tx.execute(
+                    "INSERT OR REPLACE INTO synced_split VALUES (?, ?, ?, ?)",
+                    params![
+                        user,
+                        split.name,
+                        split.description,
+                        serde_json::to_string(&split.timers)?
+                    ],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// The user's latest `limit` sessions, newest first
+    pub async fn history(&self, user: Uuid, limit: usize) -> eyre::Result<Vec<Session>> {
+        let page = self
+            .timesheets(user, TimeRange::default(), None, None, 0, limit)
+            .await?;
+        Ok(page.items)
+    }
+
+    /// Sessions that started in `range`, newest first. With
+    /// a `tag` or `split`, only the sessions that have it
+    pub async fn timesheets(
+        &self,
+        user: Uuid,
+        range: TimeRange,
+        tag: Option<String>,
+        split: Option<String>,
+        offset: usize,
+        limit: usize,
+    ) -> eyre::Result<Page<Session>> {
+        self.blocking(move |conn| {
+            let user = user.to_string();
+            let filtered = format!(
+                "FROM synced_timesheet t
+                WHERE {}
+                    AND ($4::VARCHAR IS NULL OR EXISTS (
+                        SELECT 1 FROM synced_tag s
+                        WHERE s.user_id = t.user_id
+                            AND s.start_time = t.start_time
+                            AND s.tag = $4
+                    ))
+                    AND ($5::VARCHAR IS NULL OR t.split = $5)",
+                IN_RANGE
+            );
+            let (from, to) = (range.from.map(JiffTimestamp), range.to.map(JiffTimestamp));
+
+            let total: i64 = conn
+                .prepare(&format!("SELECT count(*) {}", filtered))?
+                .query_row(params![user, from, to, tag, split], |row| row.get(0))?;
+
+            let mut stmt = conn.prepare(&format!(
+                "SELECT t.timesheet_group, t.start_time, t.end_time, t.work, t.completed, t.split
+                {}
+                ORDER BY t.start_time DESC
+                LIMIT $6 OFFSET $7",
+                filtered
+            ))?;
+            let rows = stmt
+                .query_map(
+                    params![
+                        user,
+                        from,
+                        to,
+                        tag,
+                        split,
+                        sql_count(limit),
+                        sql_count(offset)
+                    ],
+                    |row| {
+                        Ok((
+                            TimesheetRow::try_from(row)?,
+                            row.get::<_, Option<String>>(5)?,
+                        ))
+                    },
+                )?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let tags = row_tags(conn, &user)?;
+            let gaps = idle_gaps(conn, &user)?;
+            let sessions = rows
+                .into_iter()
+                .map(|(row, split)| Session {
+                    tags: tags.get(&row.start_time.0).cloned().unwrap_or_default(),
+                    idle: idle::overlap(&gaps, row.start_time.0, row.end_time.0),
+                    row,
+                    split,
+                })
+                .collect();
+
+            Ok(Page {
+                items: sessions,
+                total: total as usize,
+                offset,
+                limit,
+            })
+        })
+        .await
+    }
+
+    /// The user's splits, by name
+    pub async fn splits(
+        &self,
+        user: Uuid,
+        offset: usize,
+        limit: usize,
+    ) -> eyre::Result<Page<SyncedSplit>> {
+        self.blocking(move |conn| {
+            let user = user.to_string();
+            let total: i64 = conn.query_row(
+                "SELECT count(*) FROM synced_split WHERE user_id = ?",
+                [&user],
+                |row| row.get(0),
+            )?;
+
+            let mut stmt = conn.prepare(
+                "SELECT name, description, timers FROM synced_split
+                WHERE user_id = ?
+                ORDER BY name
+                LIMIT ? OFFSET ?",
+            )?;
+            let rows = stmt
+                .query_map(params![user, sql_count(limit), sql_count(offset)], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            let items = rows
+                .into_iter()
+                .map(|(name, description, timers)| {
+                    Ok(SyncedSplit {
+                        name,
+                        description,
+                        timers: serde_json::from_str(&timers)
+                            .wrap_err("Stored split timers are malformed")?,
+                    })
+                })
+                .collect::<eyre::Result<Vec<_>>>()?;
+
+            Ok(Page {
+                items,
+                total: total as usize,
+                offset,
+                limit,
+            })
+        })
+        .await
+    }
+
+    /// Work time per tag on sessions that started in `range`
+    pub async fn tags(
+        &self,
+        user: Uuid,
+        range: TimeRange,
+        now: Timestamp,
+        offset: usize,
+        limit: usize,
+    ) -> eyre::Result<Page<TagTotal>> {
+        self.blocking(move |conn| {
+            let entries = entries(conn, &user.to_string(), range)?;
+            let totals = history::tag_totals(Vec::new(), &entries, now);
+            Ok(Page {
+                total: totals.len(),
+                items: totals.into_iter().skip(offset).take(limit).collect(),
+                offset,
+                limit,
+            })
+        })
+        .await
+    }
+
+    /// Totals of work time, with days split up in `tz`
+    pub async fn stats(&self, user: Uuid, now: Timestamp, tz: TimeZone) -> eyre::Result<Stats> {
+        self.blocking(move |conn| {
+            let entries = entries(conn, &user.to_string(), TimeRange::default())?;
+            Ok(Stats {
+                totals: history::stats(&entries, now, &tz)?,
+                tags: history::tag_totals(Vec::new(), &entries, now),
+            })
+        })
+        .await
+    }
+
+    pub fn room_opened(&self, room: &RoomState, owner: Uuid) {
+        let (code, opened_at, split) = (room.code.clone(), room.updated_at, room.split.clone());
+        self.spawn("room_opened", move |conn| {
+            conn.execute(
+                "INSERT INTO room (code, opened_at, owner, split) VALUES (?, ?, ?, ?)",
+                params![code, JiffTimestamp(opened_at), owner.to_string(), split],
+            )?;
+            Ok(())
+        });
+    }
+
+    /// Records something that happened in the room that
+    /// opened at `opened_at`, along with the state after it
+    pub fn room_event(
+        &self,
+        opened_at: Timestamp,
+        user: Uuid,
+        event: &'static str,
+        room: &RoomState,
+    ) {
+        let code = room.code.clone();
+        let at = Timestamp::now();
+        let state = serde_json::to_string(room);
+        self.spawn("room_event", move |conn| {
+            let state = state?;
+            conn.execute(
+                "INSERT INTO room_event VALUES (?, ?, ?, ?, ?, ?)",
+                params![
+                    code,
+                    JiffTimestamp(opened_at),
+                    JiffTimestamp(at),
+                    user.to_string(),
+                    event,
+                    state
+                ],
+            )?;
+            Ok(())
+        });
+    }
+
+    pub fn room_closed(&self, code: &str, opened_at: Timestamp) {
+        let code = code.to_owned();
+        let closed_at = Timestamp::now();
+        self.spawn("room_closed", move |conn| {
+            conn.execute(
+                "UPDATE room SET closed_at = ? WHERE code = ? AND opened_at = ?",
+                params![JiffTimestamp(closed_at), code, JiffTimestamp(opened_at)],
+            )?;
+            Ok(())
+        });
+    }
+
+    /// Closes rooms a previous run left open. Their
+    /// members' connections died with it
+    pub async fn close_abandoned_rooms(&self) -> eyre::Result<()> {
+        let closed_at = Timestamp::now();
+        let closed = self
+            .blocking(move |conn| {
+                Ok(conn.execute(
+                    "UPDATE room SET closed_at = ? WHERE closed_at IS NULL",
+                    [JiffTimestamp(closed_at)],
+                )?)
+            })
+            .await?;
+        if closed > 0 {
+            tracing::info!(closed, "Closed rooms left open by the last run");
+        }
+        Ok(())
+    }
+}
+
+/// Does the writes `Store::spawn` queues up one at a
+/// time, until every store is dropped
+async fn write(db: Database, mut queue: mpsc::UnboundedReceiver<Write>) {
+    while let Some(Write { what, f }) = queue.recv().await {
+        let db = db.clone();
+        let result = tokio::task::spawn_blocking(move || f(db.get()?.connection()))
+            .await
+            .wrap_err("Database task panicked")
+            .and_then(|result| result);
+        if let Err(e) = result {
+            tracing::error!(?e, what, "Failed to write to database");
+        }
+    }
+}
+
+#[instrument(skip(store, user, batch))]
+async fn sync(
+    State(store): State<Store>,
+    user: AuthUser,
+    Json(batch): Json<SyncBatch>,
+) -> error::Result<StatusCode> {
+    tracing::info!(
+        user = %user.id,
+        rows = batch.timesheet.len(),
+        tags = batch.tags.len(),
+        "Syncing timesheet"
+    );
+    store
+        .sync(user.id, batch)
+        .await
+        .with_status_code(StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The zone days are counted in for stats. The
+/// tinker terminal serves one building, so that's
+/// wherever the server is
+pub fn local_time_zone() -> TimeZone {
+    TimeZone::try_system()
+        .wrap_err("Failed to get system time zone")
+        .unwrap_or_else(|e| {
+            tracing::warn!(?e, "Counting days in UTC");
+            TimeZone::UTC
+        })
+}
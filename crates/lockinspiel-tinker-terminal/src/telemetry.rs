@@ -0,0 +1,125 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Router,
+    extract::{FromRef, MatchedPath, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use color_eyre::eyre::{self, Context};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use tracing::instrument;
+
+pub const REQUESTS: &str = "http_requests_total";
+pub const REQUEST_DURATION: &str = "http_request_duration_seconds";
+pub const PANICS: &str = "http_panics_total";
+pub const RATE_LIMITED: &str = "http_rate_limited_total";
+pub const ACTIVE_WEBSOCKETS: &str = "websockets_active";
+pub const ACTIVE_ROOMS: &str = "rooms_active";
+
+const DURATION_BUCKETS: [f64; 11] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// Handle to the Prometheus recorder and whether
+/// the server should still be sent traffic
+#[derive(Clone)]
+pub struct Telemetry {
+    prometheus: PrometheusHandle,
+    ready: Arc<AtomicBool>,
+}
+
+impl Telemetry {
+    /// Installs the global metrics recorder. Call this
+    /// once, from inside the runtime.
+    pub fn install() -> eyre::Result<Self> {
+        let prometheus = PrometheusBuilder::new()
+            .set_buckets_for_metric(
+                Matcher::Full(REQUEST_DURATION.to_owned()),
+                &DURATION_BUCKETS,
+            )?
+            .install_recorder()
+            .wrap_err("Failed to install Prometheus recorder")?;
+
+        let upkeep = prometheus.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(5));
+            loop {
+                interval.tick().await;
+                upkeep.run_upkeep();
+            }
+        });
+
+        Ok(Self {
+            prometheus,
+            ready: Arc::new(AtomicBool::new(true)),
+        })
+    }
+
+    /// Makes `/readyz` fail, so orchestrators stop
+    /// routing to a server that is shutting down
+    pub fn set_not_ready(&self) {
+        self.ready.store(false, Ordering::Relaxed);
+    }
+}
+
+pub fn router<S>() -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+    Telemetry: FromRef<S>,
+{
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(metrics_handler))
+}
+
+/// Middleware that counts and times requests per route
+pub async fn track(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    // Label by route rather than path, so `/rooms/{code}`
+    // doesn't make a new series for every room
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".to_owned());
+    let method = request.method().to_string();
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    metrics::counter!(REQUESTS, "method" => method.clone(), "route" => route.clone(), "status" => status)
+        .increment(1);
+    metrics::histogram!(REQUEST_DURATION, "method" => method, "route" => route)
+        .record(start.elapsed().as_secs_f64());
+
+    response
+}
+
+#[instrument]
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+#[instrument(skip(telemetry))]
+async fn readyz(State(telemetry): State<Telemetry>) -> Response {
+    if telemetry.ready.load(Ordering::Relaxed) {
+        "ready".into_response()
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "shutting down").into_response()
+    }
+}
+
+#[instrument(skip(telemetry))]
+async fn metrics_handler(State(telemetry): State<Telemetry>) -> String {
+    telemetry.prometheus.render()
+}
@@ -0,0 +1,219 @@
+use jiff::{SignedDuration, Timestamp};
+use lockinspiel_common::{
+    daemon::{DaemonClient, DaemonError, DaemonState},
+    history::Entry,
+    sync::SyncedSplit,
+    timer::TimerState,
+};
+use ratatui::{
+    crossterm::event::{KeyCode, KeyEvent, KeyModifiers},
+    widgets::ListState,
+};
+use tokio::runtime::Runtime;
+
+/// How far back the history pane goes
+const HISTORY: SignedDuration = SignedDuration::from_hours(7 * 24);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pane {
+    Splits,
+    Tags,
+    History,
+}
+
+pub struct App {
+    pub state: DaemonState,
+    /// Every split in the database
+    pub splits: Vec<SyncedSplit>,
+    /// Every tag in the database
+    pub all_tags: Vec<String>,
+    /// Newest first
+    pub history: Vec<Entry>,
+    pub focus: Pane,
+    pub split_list: ListState,
+    pub tag_list: ListState,
+    pub history_list: ListState,
+    /// A tag being typed in
+    pub new_tag: Option<String>,
+    /// Last error, shown until the next key press
+    pub message: Option<String>,
+    pub quit: bool,
+    daemon: DaemonClient,
+    runtime: Runtime,
+}
+
+impl App {
+    pub fn new(runtime: Runtime, mut daemon: DaemonClient) -> Result<Self, DaemonError> {
+        let splits = runtime.block_on(daemon.splits())?;
+        let state = runtime.block_on(daemon.status())?;
+
+        let mut app = Self {
+            state,
+            splits,
+            all_tags: Vec::new(),
+            history: Vec::new(),
+            focus: Pane::Splits,
+            split_list: ListState::default(),
+            tag_list: ListState::default(),
+            history_list: ListState::default(),
+            new_tag: None,
+            message: None,
+            quit: false,
+            daemon,
+            runtime,
+        };
+        app.refresh(Timestamp::now())?;
+        app.tag_list.select_first();
+        Ok(app)
+    }
+
+    /// Picks up changes made by the daemon or
+    /// other frontends since the last tick
+    pub fn tick(&mut self, now: Timestamp) {
+        let status = self.runtime.block_on(self.daemon.status());
+        if let Some(state) = self.report(status) {
+            self.set_state(state, now);
+        }
+    }
+
+    pub fn on_key(&mut self, key: KeyEvent, now: Timestamp) {
+        self.message = None;
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+
+        if let Some(new_tag) = &mut self.new_tag {
+            match key.code {
+                KeyCode::Char(c) => new_tag.push(c),
+                KeyCode::Backspace => {
+                    new_tag.pop();
+                }
+                KeyCode::Esc => self.new_tag = None,
+                KeyCode::Enter => {
+                    let tag = self.new_tag.take().unwrap_or_default();
+                    let tag = tag.trim();
+                    if !tag.is_empty() {
+                        self.toggle_tag(tag, now);
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char(' ') => {
+                let result = match self.state.timer.state {
+                    TimerState::Going(_) => self.runtime.block_on(self.daemon.pause()),
+                    TimerState::Paused(_) => self.runtime.block_on(self.daemon.start(None)),
+                };
+                self.update(result, now);
+            }
+            // Same as the app, skipping only works while paused
+            KeyCode::Char('n') | KeyCode::Right => {
+                if let TimerState::Paused(_) = self.state.timer.state {
+                    let result = self.runtime.block_on(self.daemon.skip());
+                    self.update(result, now);
+                }
+            }
+            KeyCode::Char(c @ '1'..='9') => {
+                let index = c as usize - '1' as usize;
+                if let Some(split) = self.splits.get(index).cloned() {
+                    self.select_split(split, now);
+                }
+            }
+            KeyCode::Char('t') => self.new_tag = Some(String::new()),
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Pane::Splits => Pane::Tags,
+                    Pane::Tags => Pane::History,
+                    Pane::History => Pane::Splits,
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.focused_list().select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.focused_list().select_next(),
+            KeyCode::Enter => match self.focus {
+                Pane::Splits => {
+                    let selected = self.split_list.selected();
+                    if let Some(split) = selected.and_then(|i| self.splits.get(i)).cloned() {
+                        self.select_split(split, now);
+                    }
+                }
+                Pane::Tags => {
+                    let selected = self.tag_list.selected();
+                    if let Some(tag) = selected.and_then(|i| self.all_tags.get(i)).cloned() {
+                        self.toggle_tag(&tag, now);
+                    }
+                }
+                Pane::History => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn focused_list(&mut self) -> &mut ListState {
+        match self.focus {
+            Pane::Splits => &mut self.split_list,
+            Pane::Tags => &mut self.tag_list,
+            Pane::History => &mut self.history_list,
+        }
+    }
+
+    fn select_split(&mut self, split: SyncedSplit, now: Timestamp) {
+        let result = self.runtime.block_on(self.daemon.select_split(split.name));
+        self.update(result, now);
+    }
+
+    fn toggle_tag(&mut self, tag: &str, now: Timestamp) {
+        let result = self
+            .runtime
+            .block_on(self.daemon.toggle_tag(tag.to_owned()));
+        self.update(result, now);
+    }
+
+    fn update(&mut self, result: Result<DaemonState, DaemonError>, now: Timestamp) {
+        if let Some(state) = self.report(result) {
+            self.set_state(state, now);
+        }
+    }
+
+    /// The history and tags only change along with
+    /// the state, so they're reloaded when it does
+    fn set_state(&mut self, state: DaemonState, now: Timestamp) {
+        if state == self.state {
+            return;
+        }
+        self.state = state;
+        let refreshed = self.refresh(now);
+        self.report(refreshed);
+    }
+
+    /// Reloads everything kept by the daemon
+    fn refresh(&mut self, now: Timestamp) -> Result<(), DaemonError> {
+        self.all_tags = self.runtime.block_on(self.daemon.tags())?;
+        let since = now.checked_sub(HISTORY).unwrap_or(Timestamp::UNIX_EPOCH);
+        self.history = self.runtime.block_on(self.daemon.history(since))?;
+        self.history.reverse();
+        self.split_list.select(
+            self.splits
+                .iter()
+                .position(|split| split.name == self.state.split),
+        );
+        Ok(())
+    }
+
+    /// Shows an error in the status line instead
+    /// of taking the whole terminal down
+    fn report<T>(&mut self, result: Result<T, DaemonError>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                tracing::error!(?e, "Call to daemon failed");
+                self.message = Some(e.to_string());
+                None
+            }
+        }
+    }
+}
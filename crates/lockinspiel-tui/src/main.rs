@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use clap::Parser;
+use color_eyre::eyre::{self, Context};
+use jiff::Timestamp;
+use lockinspiel_common::daemon::DaemonClient;
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+
+mod app;
+mod ui;
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+#[command(propagate_version = true)]
+struct Cli {}
+
+fn main() -> eyre::Result<()> {
+    Cli::parse();
+
+    // No tracing subscriber here, logging to
+    // the terminal would draw over the UI
+    color_eyre::install()?;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let daemon = runtime
+        .block_on(DaemonClient::connect_or_spawn())
+        .wrap_err("Failed to connect to lockinspield")?;
+    let mut app = app::App::new(runtime, daemon)?;
+
+    let mut terminal = ratatui::try_init().wrap_err("Failed to set up the terminal")?;
+    let result = run(&mut terminal, &mut app);
+    ratatui::restore();
+    result
+}
+
+fn run(terminal: &mut ratatui::DefaultTerminal, app: &mut app::App) -> eyre::Result<()> {
+    while !app.quit {
+        let now = Timestamp::now();
+        app.tick(now);
+        terminal.draw(|frame| ui::draw(frame, app, now))?;
+
+        // Redraw on the next second so the countdown
+        // ticks, or sooner if a key comes in
+        let wait = 1000 - now.subsec_millisecond().clamp(0, 999) as u64;
+        if event::poll(Duration::from_millis(wait))? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    app.on_key(key, Timestamp::now());
+                }
+            }
+        }
+    }
+    Ok(())
+}
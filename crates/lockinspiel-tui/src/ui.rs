@@ -0,0 +1,214 @@
+use jiff::{SignedDuration, Timestamp, tz::TimeZone};
+use lockinspiel_common::timer::TimerState;
+use ratatui::{
+    Frame,
+    layout::{Alignment, Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Gauge, List, ListItem, Paragraph},
+};
+
+use crate::app::{App, Pane};
+
+/// 3x5 digits for the countdown, so it's
+/// readable from across the room over SSH too
+const DIGITS: [[&str; 5]; 11] = [
+    ["███", "█ █", "█ █", "█ █", "███"],
+    ["  █", "  █", "  █", "  █", "  █"],
+    ["███", "  █", "███", "█  ", "███"],
+    ["███", "  █", "███", "  █", "███"],
+    ["█ █", "█ █", "███", "  █", "  █"],
+    ["███", "█  ", "███", "  █", "███"],
+    ["███", "█  ", "███", "█ █", "███"],
+    ["███", "  █", "  █", "  █", "  █"],
+    ["███", "█ █", "███", "█ █", "███"],
+    ["███", "█ █", "███", "  █", "███"],
+    // The colon
+    [" ", "█", " ", "█", " "],
+];
+
+pub fn draw(frame: &mut Frame<'_>, app: &mut App, now: Timestamp) {
+    let [timer_area, lists_area, history_area, status_area] = Layout::vertical([
+        Constraint::Length(9),
+        Constraint::Min(6),
+        Constraint::Percentage(35),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [splits_area, tags_area] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .areas(lists_area);
+
+    draw_timer(frame, app, now, timer_area);
+    draw_splits(frame, app, splits_area);
+    draw_tags(frame, app, tags_area);
+    draw_history(frame, app, now, history_area);
+    draw_status(frame, app, status_area);
+}
+
+fn draw_timer(frame: &mut Frame<'_>, app: &App, now: Timestamp, area: Rect) {
+    let timer = &app.state.timer;
+    let current = timer.current();
+    let remaining = timer.remaining(now);
+    let going = matches!(timer.state, TimerState::Going(_));
+    let color = if current.work {
+        Color::Red
+    } else {
+        Color::Green
+    };
+
+    let block = Block::bordered()
+        .title(format!(" {} ", app.state.split))
+        .title_bottom(
+            Line::from(format!(
+                " {} of {} ",
+                timer.timer_on + 1,
+                timer.timers.len()
+            ))
+            .right_aligned(),
+        );
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let [name_area, digits_area, gauge_area] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(5),
+        Constraint::Length(1),
+    ])
+    .areas(inner);
+
+    let name = Line::from(vec![
+        Span::styled(current.name.as_str(), Style::new().fg(color).bold()),
+        Span::raw(if going { "" } else { " (paused)" }),
+    ]);
+    frame.render_widget(Paragraph::new(name).alignment(Alignment::Center), name_area);
+
+    frame.render_widget(
+        Paragraph::new(big_digits(remaining))
+            .style(Style::new().fg(color))
+            .alignment(Alignment::Center),
+        digits_area,
+    );
+
+    let total = current.len.as_secs_f64();
+    let ratio = if total > 0.0 {
+        (1.0 - remaining.as_secs_f64() / total).clamp(0.0, 1.0)
+    } else {
+        1.0
+    };
+    frame.render_widget(
+        Gauge::default()
+            .gauge_style(Style::new().fg(color))
+            .label("")
+            .ratio(ratio),
+        gauge_area,
+    );
+}
+
+fn draw_splits(frame: &mut Frame<'_>, app: &mut App, area: Rect) {
+    let items = app.splits.iter().enumerate().map(|(i, split)| {
+        let mut item = ListItem::new(if i < 9 {
+            format!("{} {}", i + 1, split.name)
+        } else {
+            format!("  {}", split.name)
+        });
+        if split.name == app.state.split {
+            item = item.bold();
+        }
+        item
+    });
+    let list = List::new(items)
+        .block(pane_block("Splits", app.focus == Pane::Splits))
+        .highlight_style(Modifier::REVERSED);
+    frame.render_stateful_widget(list, area, &mut app.split_list);
+}
+
+fn draw_tags(frame: &mut Frame<'_>, app: &mut App, area: Rect) {
+    let items = app.all_tags.iter().map(|tag| {
+        let on = app.state.tags.contains(tag);
+        ListItem::new(format!("[{}] {}", if on { "x" } else { " " }, tag))
+    });
+    let title = match &app.new_tag {
+        Some(new_tag) => format!("New tag: {}_", new_tag),
+        None => "Tags".to_owned(),
+    };
+    let list = List::new(items)
+        .block(pane_block(&title, app.focus == Pane::Tags))
+        .highlight_style(Modifier::REVERSED);
+    frame.render_stateful_widget(list, area, &mut app.tag_list);
+}
+
+fn draw_history(frame: &mut Frame<'_>, app: &mut App, now: Timestamp, area: Rect) {
+    let tz = TimeZone::system();
+    let items = app.history.iter().map(|entry| {
+        let length = entry.length(now).as_secs();
+        let mut line = Line::from(vec![
+            Span::raw(format!(
+                "{} - {} ",
+                entry
+                    .start_time
+                    .to_zoned(tz.clone())
+                    .strftime("%a %b %-d %H:%M"),
+                entry.end_time.to_zoned(tz.clone()).strftime("%H:%M"),
+            )),
+            Span::raw(format!("{:>3}:{:02} ", length / 60, length % 60)),
+            if entry.work {
+                Span::styled("Work ", Style::new().fg(Color::Red))
+            } else {
+                Span::styled("Break", Style::new().fg(Color::Green))
+            },
+            Span::raw(format!(
+                "  {}  {}",
+                entry.split.as_deref().unwrap_or("-"),
+                entry.tags.join(", ")
+            )),
+        ]);
+        if !entry.completed && entry.end_time <= now {
+            line = line.dim();
+        }
+        ListItem::new(line)
+    });
+    let list = List::new(items)
+        .block(pane_block("History", app.focus == Pane::History))
+        .highlight_style(Modifier::REVERSED);
+    frame.render_stateful_widget(list, area, &mut app.history_list);
+}
+
+fn draw_status(frame: &mut Frame<'_>, app: &App, area: Rect) {
+    let line = match &app.message {
+        Some(message) => Line::from(message.as_str()).fg(Color::Red),
+        None if app.new_tag.is_some() => Line::from("enter add tag  esc cancel").dim(),
+        None => Line::from(
+            "space start/pause  n skip  1-9 split  tab switch pane  enter pick  t new tag  q quit",
+        )
+        .dim(),
+    };
+    frame.render_widget(Paragraph::new(line), area);
+}
+
+fn pane_block(title: &str, focused: bool) -> Block<'static> {
+    let block = Block::bordered().title(format!(" {} ", title));
+    if focused {
+        block.border_style(Style::new().fg(Color::Yellow))
+    } else {
+        block
+    }
+}
+
+/// Renders m:ss in block digits, five lines tall
+fn big_digits(remaining: SignedDuration) -> Vec<Line<'static>> {
+    let secs = remaining.as_secs().max(0);
+    let text = format!("{}:{:02}", secs / 60, secs % 60);
+    (0..5)
+        .map(|row| {
+            let glyphs: Vec<_> = text
+                .chars()
+                .map(|c| match c.to_digit(10) {
+                    Some(digit) => DIGITS[digit as usize][row],
+                    None => DIGITS[10][row],
+                })
+                .collect();
+            Line::from(glyphs.join(" "))
+        })
+        .collect()
+}